@@ -0,0 +1,116 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::util::prettybytes;
+
+/// Origin of a block of data, as identified by [`Disktest::analyze`](crate::Disktest::analyze).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisktestBlockOrigin {
+    /// The block only contains 0x00 bytes.
+    Zero,
+    /// The block only contains 0xFF bytes.
+    Ones,
+    /// The block has been found in the random data stream.
+    Stream {
+        /// The absolute byte offset the block has been written to.
+        offset: u64,
+        /// The round the block has been written in.
+        round_id: u64,
+        /// The block has been written with inverted pattern.
+        inverted: bool,
+    },
+    /// The block has not been found in the random data stream.
+    Unrelated,
+}
+
+impl std::fmt::Display for DisktestBlockOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisktestBlockOrigin::Zero => write!(f, "all bytes are 0x00"),
+            DisktestBlockOrigin::Ones => write!(f, "all bytes are 0xFF"),
+            DisktestBlockOrigin::Stream {
+                offset,
+                round_id,
+                inverted,
+            } => write!(
+                f,
+                "data written for offset {}, round {}{}",
+                prettybytes(*offset, true, true, true),
+                round_id,
+                if *inverted {
+                    ", with inverted pattern"
+                } else {
+                    ""
+                }
+            ),
+            DisktestBlockOrigin::Unrelated => write!(f, "unrelated data"),
+        }
+    }
+}
+
+/// Classify blocks that only contain 0x00 or 0xFF bytes.
+pub fn classify_fill(block: &[u8]) -> Option<DisktestBlockOrigin> {
+    if block.iter().all(|x| *x == 0x00) {
+        Some(DisktestBlockOrigin::Zero)
+    } else if block.iter().all(|x| *x == 0xFF) {
+        Some(DisktestBlockOrigin::Ones)
+    } else {
+        None
+    }
+}
+
+/// Search `needle` at all `step` aligned positions of `haystack`.
+/// Returns the position of the first match.
+pub fn find_aligned(haystack: &[u8], needle: &[u8], step: usize) -> Option<usize> {
+    debug_assert!(step > 0);
+    (0..haystack.len())
+        .step_by(step)
+        .find(|&pos| haystack[pos..].starts_with(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_fill() {
+        assert_eq!(classify_fill(&[0; 512]), Some(DisktestBlockOrigin::Zero));
+        assert_eq!(classify_fill(&[0xFF; 512]), Some(DisktestBlockOrigin::Ones));
+        assert_eq!(classify_fill(&[0, 0xFF]), None);
+    }
+
+    #[test]
+    fn test_find_aligned() {
+        let haystack: Vec<u8> = (0..64).collect();
+        assert_eq!(find_aligned(&haystack, &[16, 17, 18], 8), Some(16));
+        assert_eq!(find_aligned(&haystack, &[17, 18], 8), None);
+        assert_eq!(find_aligned(&haystack, &[17, 18], 1), Some(17));
+        assert_eq!(find_aligned(&haystack, &[56, 57, 58, 59], 8), Some(56));
+        // The needle must not exceed the haystack.
+        assert_eq!(find_aligned(&haystack, &[63, 64], 1), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let origin = DisktestBlockOrigin::Stream {
+            offset: 1024,
+            round_id: 2,
+            inverted: true,
+        };
+        assert_eq!(
+            origin.to_string(),
+            "data written for offset 1.0 kiB (1.0 kB, 1024 bytes), round 2, with inverted pattern"
+        );
+        assert_eq!(DisktestBlockOrigin::Zero.to_string(), "all bytes are 0x00");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
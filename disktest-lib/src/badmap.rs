@@ -0,0 +1,291 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::generator::DisktestSectorTag;
+
+/// Bit level analysis of mismatching data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DisktestBitErrors {
+    /// Number of bits that have been written as 0, but read as 1.
+    pub flipped_0to1: u64,
+    /// Number of bits that have been written as 1, but read as 0.
+    pub flipped_1to0: u64,
+    /// Mask of the bit positions (within a byte) that read as 0 in every
+    /// byte of a sector and that caused at least one mismatch in that sector.
+    pub stuck_at_0: u8,
+    /// Mask of the bit positions (within a byte) that read as 1 in every
+    /// byte of a sector and that caused at least one mismatch in that sector.
+    pub stuck_at_1: u8,
+}
+
+impl DisktestBitErrors {
+    /// Analyze one sector of read data against the expected data.
+    pub fn from_sector(read: &[u8], expected: &[u8]) -> DisktestBitErrors {
+        debug_assert_eq!(read.len(), expected.len());
+        let mut flipped_0to1 = 0;
+        let mut flipped_1to0 = 0;
+        let mut and_all = 0xFF_u8;
+        let mut or_all = 0x00_u8;
+        let mut diff_all = 0x00_u8;
+        for (r, e) in read.iter().zip(expected) {
+            flipped_0to1 += u64::from((r & !e).count_ones());
+            flipped_1to0 += u64::from((!r & e).count_ones());
+            and_all &= r;
+            or_all |= r;
+            diff_all |= r ^ e;
+        }
+        DisktestBitErrors {
+            flipped_0to1,
+            flipped_1to0,
+            stuck_at_0: !or_all & diff_all,
+            stuck_at_1: and_all & diff_all,
+        }
+    }
+
+    /// Get the total number of flipped bits.
+    pub fn flipped(&self) -> u64 {
+        self.flipped_0to1 + self.flipped_1to0
+    }
+
+    /// Accumulate another analysis result into this one.
+    pub fn merge(&mut self, other: &DisktestBitErrors) {
+        self.flipped_0to1 += other.flipped_0to1;
+        self.flipped_1to0 += other.flipped_1to0;
+        self.stuck_at_0 |= other.stuck_at_0;
+        self.stuck_at_1 |= other.stuck_at_1;
+    }
+}
+
+impl std::fmt::Display for DisktestBitErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bits flipped (0->1: {}, 1->0: {})",
+            self.flipped(),
+            self.flipped_0to1,
+            self.flipped_1to0
+        )?;
+        if self.stuck_at_0 != 0 {
+            write!(f, ", stuck at 0: bit mask 0x{:02X}", self.stuck_at_0)?;
+        }
+        if self.stuck_at_1 != 0 {
+            write!(f, ", stuck at 1: bit mask 0x{:02X}", self.stuck_at_1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Contiguous range of bad sectors found during verification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisktestBadRange {
+    /// Absolute byte offset of the first bad sector.
+    pub offset: u64,
+    /// Length of the range, in bytes.
+    pub length: u64,
+    /// Number of bytes within the range that did not match the expected data.
+    pub bytes_affected: u64,
+    /// Bit level analysis of all sectors in this range.
+    /// The stuck bit masks are the union over all sectors.
+    pub bit_errors: DisktestBitErrors,
+    /// The sector header found in the first sector of this range.
+    /// This is only available, if the self-describing
+    /// [`DtStreamType::Tagged`](crate::DtStreamType::Tagged) stream is used.
+    pub tag: Option<DisktestSectorTag>,
+}
+
+/// Map of bad ranges.
+/// Adjacent bad sectors are coalesced into one range.
+#[derive(Default)]
+pub struct BadMap {
+    ranges: Vec<DisktestBadRange>,
+    nr_sectors: u64,
+}
+
+impl BadMap {
+    pub fn new() -> BadMap {
+        Default::default()
+    }
+
+    /// Remove all ranges.
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.nr_sectors = 0;
+    }
+
+    /// Add a bad sector.
+    /// Sectors must be added in ascending order.
+    pub fn add(
+        &mut self,
+        offset: u64,
+        length: u64,
+        bytes_affected: u64,
+        bit_errors: &DisktestBitErrors,
+        tag: Option<DisktestSectorTag>,
+    ) {
+        self.nr_sectors += 1;
+        if let Some(last) = self.ranges.last_mut() {
+            debug_assert!(last.offset + last.length <= offset);
+            if last.offset + last.length == offset {
+                last.length += length;
+                last.bytes_affected += bytes_affected;
+                last.bit_errors.merge(bit_errors);
+                return;
+            }
+        }
+        self.ranges.push(DisktestBadRange {
+            offset,
+            length,
+            bytes_affected,
+            bit_errors: *bit_errors,
+            tag,
+        });
+    }
+
+    /// Get all bad ranges.
+    pub fn ranges(&self) -> &[DisktestBadRange] {
+        &self.ranges
+    }
+
+    /// Get the number of bad sectors that have been added.
+    pub fn nr_sectors(&self) -> u64 {
+        self.nr_sectors
+    }
+
+    /// Get the total number of bytes that did not match.
+    pub fn bytes_affected(&self) -> u64 {
+        self.ranges.iter().map(|r| r.bytes_affected).sum()
+    }
+
+    /// Get the accumulated bit level analysis of all ranges.
+    pub fn bit_errors(&self) -> DisktestBitErrors {
+        let mut bit_errors = DisktestBitErrors::default();
+        for range in &self.ranges {
+            bit_errors.merge(&range.bit_errors);
+        }
+        bit_errors
+    }
+
+    /// Returns true, if no bad sector has been added.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_badmap() {
+        let mut map = BadMap::new();
+        assert!(map.is_empty());
+
+        let be = |flips| DisktestBitErrors {
+            flipped_0to1: flips,
+            flipped_1to0: 1,
+            stuck_at_0: 0,
+            stuck_at_1: 0,
+        };
+        map.add(512, 512, 3, &be(3), None);
+        map.add(1024, 512, 1, &be(1), None);
+        map.add(4096, 512, 512, &be(10), None);
+        map.add(4608, 512, 2, &be(2), None);
+        map.add(5120, 512, 1, &be(1), None);
+        map.add(8192, 512, 7, &be(7), None);
+
+        assert!(!map.is_empty());
+        assert_eq!(map.nr_sectors(), 6);
+        assert_eq!(map.bytes_affected(), 3 + 1 + 512 + 2 + 1 + 7);
+        assert_eq!(map.bit_errors().flipped(), 24 + 6);
+        assert_eq!(
+            map.ranges(),
+            &[
+                DisktestBadRange {
+                    offset: 512,
+                    length: 1024,
+                    bytes_affected: 4,
+                    bit_errors: DisktestBitErrors {
+                        flipped_0to1: 4,
+                        flipped_1to0: 2,
+                        stuck_at_0: 0,
+                        stuck_at_1: 0,
+                    },
+                    tag: None,
+                },
+                DisktestBadRange {
+                    offset: 4096,
+                    length: 1536,
+                    bytes_affected: 515,
+                    bit_errors: DisktestBitErrors {
+                        flipped_0to1: 13,
+                        flipped_1to0: 3,
+                        stuck_at_0: 0,
+                        stuck_at_1: 0,
+                    },
+                    tag: None,
+                },
+                DisktestBadRange {
+                    offset: 8192,
+                    length: 512,
+                    bytes_affected: 7,
+                    bit_errors: DisktestBitErrors {
+                        flipped_0to1: 7,
+                        flipped_1to0: 1,
+                        stuck_at_0: 0,
+                        stuck_at_1: 0,
+                    },
+                    tag: None,
+                },
+            ]
+        );
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.nr_sectors(), 0);
+    }
+
+    #[test]
+    fn test_bit_errors() {
+        let be = DisktestBitErrors::from_sector(&[0x00, 0xFF, 0x5A], &[0x00, 0xFF, 0x5A]);
+        assert_eq!(be, DisktestBitErrors::default());
+        assert_eq!(be.flipped(), 0);
+
+        let be = DisktestBitErrors::from_sector(&[0x01, 0xFE, 0x5B], &[0x00, 0xFF, 0x5A]);
+        assert_eq!(be.flipped_0to1, 2);
+        assert_eq!(be.flipped_1to0, 1);
+        assert_eq!(be.flipped(), 3);
+        assert_eq!(be.stuck_at_0, 0);
+        assert_eq!(be.stuck_at_1, 0);
+
+        // Bit 7 is stuck at 1 and bit 0 is stuck at 0.
+        let be = DisktestBitErrors::from_sector(&[0x80, 0xFE, 0xDA], &[0x01, 0x7F, 0x5A]);
+        assert_eq!(be.flipped_0to1, 3);
+        assert_eq!(be.flipped_1to0, 2);
+        assert_eq!(be.stuck_at_0, 0x01);
+        assert_eq!(be.stuck_at_1, 0x80);
+
+        assert_eq!(
+            be.to_string(),
+            "5 bits flipped (0->1: 3, 1->0: 2), \
+             stuck at 0: bit mask 0x01, stuck at 1: bit mask 0x80"
+        );
+
+        let mut acc = DisktestBitErrors::default();
+        acc.merge(&be);
+        acc.merge(&DisktestBitErrors::from_sector(&[0x00], &[0x40]));
+        assert_eq!(acc.flipped_0to1, 3);
+        assert_eq!(acc.flipped_1to0, 3);
+        assert_eq!(acc.stuck_at_0, 0x41);
+        assert_eq!(acc.stuck_at_1, 0x80);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,209 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::disktest::DisktestQuiet;
+use crate::stream_aggregator::{DtStreamAgg, DtStreamType};
+use anyhow as ah;
+use std::num::NonZero;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::available_parallelism;
+use std::time::{Duration, Instant};
+
+/// Seed of the benchmark streams. The generator speed does not depend on it.
+const BENCH_SEED: &[u8] = b"disktest benchmark";
+
+/// Result of one generator benchmark run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisktestBench {
+    /// The benchmarked algorithm.
+    pub algorithm: DtStreamType,
+    /// The number of generator threads.
+    pub threads: usize,
+    /// The number of bytes generated.
+    pub bytes: u64,
+    /// The time it took to generate the bytes.
+    pub elapsed: Duration,
+}
+
+impl DisktestBench {
+    /// The random generator algorithms that are benchmarked by default,
+    /// from the most to the least cryptographically secure one.
+    pub const ALGORITHMS: [DtStreamType; 5] = [
+        DtStreamType::ChaCha20,
+        DtStreamType::ChaCha12,
+        DtStreamType::ChaCha8,
+        DtStreamType::Crc,
+        DtStreamType::Xoshiro,
+    ];
+
+    /// Get the default thread counts to benchmark:
+    /// The powers of two below the number of online CPUs and the number of CPUs itself.
+    pub fn default_threads() -> Vec<usize> {
+        let cpus = available_parallelism().map_or(1, NonZero::get);
+        let mut threads: Vec<usize> = (0..usize::BITS)
+            .map(|shift| 1 << shift)
+            .take_while(|&threads| threads < cpus)
+            .collect();
+        threads.push(cpus);
+        threads
+    }
+
+    /// Generate the stream of `algorithm` with `threads` threads for `duration`
+    /// and measure the data rate.
+    /// Nothing is written to or read from any device.
+    /// The special value 0 for `threads` selects the number of online CPUs in the system.
+    pub fn run(
+        algorithm: DtStreamType,
+        threads: usize,
+        duration: Duration,
+        abort: Option<&AtomicBool>,
+    ) -> ah::Result<DisktestBench> {
+        let threads = if threads == 0 {
+            available_parallelism().map_or(1, NonZero::get)
+        } else {
+            threads
+        };
+
+        // The stream threads generate ahead, as soon as they are activated.
+        // Start measuring right before the activation,
+        // so that no data is generated before the measurement starts.
+        let mut agg = DtStreamAgg::new(
+            algorithm,
+            BENCH_SEED,
+            0,
+            false,
+            threads,
+            DisktestQuiet::NoWarn,
+        );
+        let begin = Instant::now();
+        agg.activate(0, 512)?;
+
+        let mut bytes = 0;
+        loop {
+            let elapsed = begin.elapsed();
+            if elapsed >= duration {
+                break Ok(DisktestBench {
+                    algorithm,
+                    threads,
+                    bytes,
+                    elapsed,
+                });
+            }
+            if abort.is_some_and(|abort| abort.load(Ordering::Relaxed)) {
+                break Err(ah::format_err!("Aborted by signal!"));
+            }
+            let chunk = agg.wait_chunk()?;
+            bytes += u64::try_from(chunk.get_data().len())?;
+        }
+    }
+
+    /// Get the generated data rate, in bytes per second.
+    pub fn rate(&self) -> u64 {
+        let elapsed_us = self.elapsed.as_micros();
+        (u128::from(self.bytes) * 1_000_000)
+            .checked_div(elapsed_us)
+            .map_or(u64::MAX, |rate| u64::try_from(rate).unwrap_or(u64::MAX))
+    }
+
+    /// Find the cheapest of the `results` that generates at least `target_rate` bytes per second.
+    /// The cheapest result is the one with the fewest threads.
+    /// Of those, the algorithm that comes first in [`DisktestBench::ALGORITHMS`] is preferred,
+    /// because it is the most secure one.
+    pub fn recommend(results: &[DisktestBench], target_rate: u64) -> Option<&DisktestBench> {
+        let security = |algorithm: DtStreamType| {
+            DisktestBench::ALGORITHMS
+                .iter()
+                .position(|a| *a == algorithm)
+                .unwrap_or(DisktestBench::ALGORITHMS.len())
+        };
+        results
+            .iter()
+            .filter(|result| result.rate() >= target_rate)
+            .min_by_key(|result| (result.threads, security(result.algorithm)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(algorithm: DtStreamType, threads: usize, rate: u64) -> DisktestBench {
+        DisktestBench {
+            algorithm,
+            threads,
+            bytes: rate * 2,
+            elapsed: Duration::from_secs(2),
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let a = DisktestBench::run(DtStreamType::Crc, 2, Duration::from_millis(100), None).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::Crc);
+        assert_eq!(a.threads, 2);
+        assert!(a.bytes > 0);
+        assert!(a.elapsed >= Duration::from_millis(100));
+        assert!(a.rate() > 0);
+
+        let abort = AtomicBool::new(true);
+        assert!(
+            DisktestBench::run(DtStreamType::Crc, 1, Duration::from_secs(10), Some(&abort))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_default_threads() {
+        let threads = DisktestBench::default_threads();
+        assert_eq!(threads[0], 1);
+        assert!(threads.is_sorted());
+        assert_eq!(
+            *threads.last().unwrap(),
+            available_parallelism().unwrap().get()
+        );
+    }
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(result(DtStreamType::Crc, 1, 1000).rate(), 1000);
+        let a = DisktestBench {
+            algorithm: DtStreamType::Crc,
+            threads: 1,
+            bytes: 1000,
+            elapsed: Duration::ZERO,
+        };
+        assert_eq!(a.rate(), u64::MAX);
+    }
+
+    #[test]
+    fn test_recommend() {
+        let results = [
+            result(DtStreamType::ChaCha20, 1, 100),
+            result(DtStreamType::Crc, 1, 400),
+            result(DtStreamType::Xoshiro, 1, 450),
+            result(DtStreamType::ChaCha20, 2, 200),
+            result(DtStreamType::ChaCha8, 2, 500),
+            result(DtStreamType::Crc, 2, 800),
+            result(DtStreamType::ChaCha20, 4, 400),
+        ];
+        let rec =
+            |target| DisktestBench::recommend(&results, target).map(|r| (r.algorithm, r.threads));
+        assert_eq!(rec(0), Some((DtStreamType::ChaCha20, 1)));
+        assert_eq!(rec(100), Some((DtStreamType::ChaCha20, 1)));
+        assert_eq!(rec(101), Some((DtStreamType::Crc, 1)));
+        assert_eq!(rec(420), Some((DtStreamType::Xoshiro, 1)));
+        assert_eq!(rec(460), Some((DtStreamType::ChaCha8, 2)));
+        assert_eq!(rec(600), Some((DtStreamType::Crc, 2)));
+        assert_eq!(rec(801), None);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
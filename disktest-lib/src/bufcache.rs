@@ -10,11 +10,69 @@
 //
 
 use crate::disktest::DisktestQuiet;
+use disktest_rawio::DIRECT_IO_ALIGN;
 use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender, channel};
 
+/// Zero initialized buffer with a start address that is aligned for direct I/O.
+pub struct AlignedBuf {
+    buf: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl AlignedBuf {
+    pub fn new(len: usize) -> AlignedBuf {
+        // Allocate enough space to move the start to the next aligned address.
+        // The Vec is never resized, so the address does not change.
+        let buf = vec![0; len + DIRECT_IO_ALIGN - 1];
+        let offset = buf.as_ptr().addr().wrapping_neg() % DIRECT_IO_ALIGN;
+        AlignedBuf { buf, offset, len }
+    }
+
+    /// Change the length of the buffer.
+    /// The contents are not preserved, if the buffer grows beyond its capacity.
+    pub fn resize(&mut self, len: usize) {
+        if self.offset + len <= self.buf.len() {
+            self.len = len;
+        } else {
+            *self = AlignedBuf::new(len);
+        }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.offset..self.offset + self.len]
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.offset..self.offset + self.len]
+    }
+}
+
+impl AsRef<[u8]> for AlignedBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for AlignedBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
 pub struct BufCache {
-    snd: HashMap<u32, Sender<Vec<u8>>>,
+    snd: HashMap<u32, (Sender<AlignedBuf>, Arc<AtomicUsize>)>,
+    max_bufs: usize,
     quiet_level: DisktestQuiet,
 }
 
@@ -22,42 +80,61 @@ impl BufCache {
     pub fn new(quiet_level: DisktestQuiet) -> BufCache {
         BufCache {
             snd: HashMap::new(),
+            max_bufs: usize::MAX,
             quiet_level,
         }
     }
 
+    /// Set the maximum number of buffers that are cached per consumer.
+    /// Buffers beyond that are freed instead of cached.
+    pub fn set_max_bufs(&mut self, max_bufs: usize) {
+        self.max_bufs = max_bufs;
+    }
+
     pub fn new_consumer(&mut self, cons_id: u32) -> BufCacheCons {
         let (snd, rcv) = channel();
-        self.snd.insert(cons_id, snd);
-        BufCacheCons { rcv }
+        let count = Arc::new(AtomicUsize::new(0));
+        self.snd.insert(cons_id, (snd, Arc::clone(&count)));
+        BufCacheCons { rcv, count }
     }
 
-    pub fn push(&mut self, cons_id: u32, buf: Vec<u8>) {
-        let Some(snd) = self.snd.get(&cons_id) else {
+    pub fn push(&mut self, cons_id: u32, buf: AlignedBuf) {
+        let Some((snd, count)) = self.snd.get(&cons_id) else {
             panic!("BufCache: Consumer {cons_id} does not exist.");
         };
-        if let Err(e) = snd.send(buf) {
-            if self.quiet_level < DisktestQuiet::NoWarn {
-                eprintln!("BufCache: Failed to send: {e}");
+        if count.load(Ordering::Relaxed) >= self.max_bufs {
+            return; // Cache is full. Free the buffer.
+        }
+        match snd.send(buf) {
+            Ok(()) => {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    eprintln!("BufCache: Failed to send: {e}");
+                }
             }
         }
     }
 }
 
 pub struct BufCacheCons {
-    rcv: Receiver<Vec<u8>>,
+    rcv: Receiver<AlignedBuf>,
+    count: Arc<AtomicUsize>,
 }
 
 impl BufCacheCons {
-    pub fn pull(&mut self, buf_len: usize) -> Vec<u8> {
-        let mut buf = match self.rcv.try_recv() {
-            Ok(buf) => buf,
-            Err(_) => Vec::with_capacity(buf_len),
-        };
-        if buf.len() != buf_len {
-            buf.resize(buf_len, 0);
+    pub fn pull(&mut self, buf_len: usize) -> AlignedBuf {
+        match self.rcv.try_recv() {
+            Ok(mut buf) => {
+                self.count.fetch_sub(1, Ordering::Relaxed);
+                if buf.len() != buf_len {
+                    buf.resize(buf_len);
+                }
+                buf
+            }
+            Err(_) => AlignedBuf::new(buf_len),
         }
-        buf
     }
 }
 
@@ -65,6 +142,29 @@ impl BufCacheCons {
 mod tests {
     use super::*;
 
+    fn aligned(data: &[u8]) -> AlignedBuf {
+        let mut buf = AlignedBuf::new(data.len());
+        buf.copy_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn test_aligned_buf() {
+        for len in [0, 1, 4096, 10000] {
+            let mut buf = AlignedBuf::new(len);
+            assert_eq!(buf.len(), len);
+            assert_eq!(buf.as_ptr().addr() % DIRECT_IO_ALIGN, 0);
+            assert!(buf.iter().all(|x| *x == 0));
+            buf.fill(0x5A);
+            buf.resize(len / 2);
+            assert_eq!(buf.len(), len / 2);
+            assert!(buf.iter().all(|x| *x == 0x5A));
+            buf.resize(len * 2 + 1);
+            assert_eq!(buf.len(), len * 2 + 1);
+            assert_eq!(buf.as_ptr().addr() % DIRECT_IO_ALIGN, 0);
+        }
+    }
+
     #[test]
     fn test_bufcache() {
         let mut cache = BufCache::new(DisktestQuiet::Normal);
@@ -73,31 +173,47 @@ mod tests {
 
         let buf = cons0.pull(4);
         assert_eq!(buf.len(), 4);
-        assert_eq!(buf, vec![0, 0, 0, 0]);
+        assert_eq!(*buf, [0, 0, 0, 0]);
 
-        cache.push(42, vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        cache.push(42, aligned(&[0xDE, 0xAD, 0xBE, 0xEF]));
         let buf = cons0.pull(4);
         assert_eq!(buf.len(), 4);
-        assert_eq!(buf, vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(*buf, [0xDE, 0xAD, 0xBE, 0xEF]);
 
         let buf = cons0.pull(4);
         assert_eq!(buf.len(), 4);
-        assert_eq!(buf, vec![0, 0, 0, 0]);
+        assert_eq!(*buf, [0, 0, 0, 0]);
 
-        cache.push(43, vec![0xCA, 0xFE, 0xAF, 0xFE]);
+        cache.push(43, aligned(&[0xCA, 0xFE, 0xAF, 0xFE]));
         let buf = cons0.pull(4);
         assert_eq!(buf.len(), 4);
-        assert_eq!(buf, vec![0, 0, 0, 0]);
+        assert_eq!(*buf, [0, 0, 0, 0]);
         let buf = cons1.pull(4);
         assert_eq!(buf.len(), 4);
-        assert_eq!(buf, vec![0xCA, 0xFE, 0xAF, 0xFE]);
+        assert_eq!(*buf, [0xCA, 0xFE, 0xAF, 0xFE]);
+    }
+
+    #[test]
+    fn test_bufcache_max_bufs() {
+        let mut cache = BufCache::new(DisktestQuiet::Normal);
+        cache.set_max_bufs(2);
+        let mut cons = cache.new_consumer(42);
+
+        cache.push(42, aligned(&[1]));
+        cache.push(42, aligned(&[2]));
+        cache.push(42, aligned(&[3])); // Freed.
+        assert_eq!(*cons.pull(1), [1]);
+        cache.push(42, aligned(&[4]));
+        assert_eq!(*cons.pull(1), [2]);
+        assert_eq!(*cons.pull(1), [4]);
+        assert_eq!(*cons.pull(1), [0]);
     }
 
     #[test]
     #[should_panic(expected = "Consumer 42 does not exist")]
     fn test_bufcache_cons_invalid() {
         let mut cache = BufCache::new(DisktestQuiet::Normal);
-        cache.push(42, vec![]);
+        cache.push(42, AlignedBuf::new(0));
     }
 }
 
@@ -9,23 +9,50 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 //
 
+use crate::analyze::{DisktestBlockOrigin, classify_fill, find_aligned};
+use crate::badmap::{BadMap, DisktestBadRange, DisktestBitErrors};
+use crate::bufcache::AlignedBuf;
+use crate::erase::DisktestDeviceIdentity;
+use crate::export::{merge_ranges, write_badblocks, write_dmsetup_table};
+use crate::generator::{DisktestSectorTag, parse_sector_tag};
+use crate::hexdump::{DisktestHexdump, hexdump_diff, hexdump_regions};
+use crate::kdf::seed_fingerprint;
+use crate::probe::{
+    DisktestProbeResult, PROBE_BLOCK_SIZE, PROBE_NR_RANDOM, ProbeStatus, probe_block,
+    probe_block_parse, probe_evaluate, probe_offsets,
+};
+use crate::sample::{DisktestCoverage, DisktestSampling};
+use crate::state::{DisktestPhase, DisktestState};
 use crate::stream_aggregator::{DtStreamAgg, DtStreamAggChunk};
 use crate::util::{Hhmmss as _, prettybytes};
 use anyhow::{self as ah, Context as _};
 use chrono::prelude::*;
-use disktest_rawio::{DEFAULT_SECTOR_SIZE, RawIo, RawIoOsIntf as _, RawIoResult};
+use disktest_rawio::{
+    DEFAULT_SECTOR_SIZE, RawIo, RawIoGeometry, RawIoOsIntf as _, RawIoQueue, RawIoQueueIntf as _,
+    RawIoResult,
+};
 use movavg::MovAvg;
+use rand::RngExt as _;
 use std::cmp::min;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::available_parallelism;
 use std::time::Instant;
 
-pub use crate::stream_aggregator::DtStreamType;
+pub use crate::stream_aggregator::{DtPattern, DtStreamLayout, DtStreamType};
 
 const LOG_BYTE_THRES: u64 = 1024 * 1024;
 const LOG_SEC_THRES: u64 = 10;
+const PROBE_MAX_ALIAS_LINES: usize = 16;
+const CHECKPOINT_SEC_THRES: u64 = 60;
+/// Number of buffers queued for the I/O thread.
+const IO_THREAD_DEPTH: usize = 3;
+/// Number of chunks that are compared by the generator threads,
+/// while the next chunk is being read.
+const VERIFY_COMPARE_DEPTH: usize = 2;
 
 /// Disktest console print verbosity.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -40,15 +67,41 @@ pub enum DisktestQuiet {
     NoWarn = 3,
 }
 
+/// Read errors injected by the tests.
+#[cfg(test)]
+#[derive(Default)]
+struct InjectedReadErrors {
+    /// Byte ranges that fail to read.
+    ranges: Vec<std::ops::Range<u64>>,
+    /// Current file position of the synchronous reads.
+    pos: u64,
+    /// Byte ranges of the pending queued reads.
+    queued: std::collections::VecDeque<std::ops::Range<u64>>,
+}
+
+#[cfg(test)]
+impl InjectedReadErrors {
+    /// Check whether the read of `len` bytes at `offset` fails.
+    fn hit(&self, offset: u64, len: u64) -> bool {
+        self.ranges
+            .iter()
+            .any(|r| r.start < offset + len && offset < r.end)
+    }
+}
+
 /// Handle for a device or file to write to and/or read from.
 pub struct DisktestFile {
     path: PathBuf,
     read: bool,
     write: bool,
     io: Option<RawIo>,
+    direct: bool,
+    queue_depth: usize,
     drop_offset: u64,
     drop_count: u64,
     quiet_level: DisktestQuiet,
+    #[cfg(test)]
+    read_errors: InjectedReadErrors,
 }
 
 impl DisktestFile {
@@ -59,17 +112,58 @@ impl DisktestFile {
             read,
             write,
             io: None,
+            direct: false,
+            queue_depth: 1,
             drop_offset: 0,
             drop_count: 0,
             quiet_level: DisktestQuiet::Normal,
+            #[cfg(test)]
+            read_errors: InjectedReadErrors::default(),
         })
     }
 
+    /// Enable direct I/O (`O_DIRECT`), which bypasses the operating system caches.
+    /// If the file system or operating system does not support direct I/O,
+    /// then a warning is printed and cached I/O is used instead.
+    pub fn set_direct_io(&mut self, direct: bool) {
+        self.direct = direct;
+    }
+
+    /// Set the number of transfers to keep in flight.
+    /// A queue depth above 1 uses queued I/O (`io_uring`).
+    /// If queued I/O is not available, then a warning is printed
+    /// and the I/O thread is used instead.
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.queue_depth = depth.max(1);
+    }
+
+    /// Get the maximum number of buffers that are held by the I/O queue or thread.
+    fn io_depth(&self) -> usize {
+        if self.queue_depth > 1 {
+            self.queue_depth
+        } else {
+            IO_THREAD_DEPTH
+        }
+    }
+
     fn do_open(&mut self) -> ah::Result<()> {
         if self.io.is_none() {
-            self.io = Some(RawIo::new(&self.path, self.write, self.read, self.write)?);
+            let mut io = RawIo::new(&self.path, self.write, self.read, self.write)?;
+            if self.direct {
+                if let Err(e) = io.set_direct(true) {
+                    if self.quiet_level < DisktestQuiet::NoWarn {
+                        eprintln!("WARNING: {e:#}. Falling back to cached I/O.");
+                    }
+                    self.direct = false;
+                }
+            }
+            self.io = Some(io);
             self.drop_offset = 0;
             self.drop_count = 0;
+            #[cfg(test)]
+            {
+                self.read_errors.pos = 0;
+            }
         }
         Ok(())
     }
@@ -103,6 +197,36 @@ impl DisktestFile {
         Ok(io.get_sector_size())
     }
 
+    /// Get the device's sector and I/O sizes.
+    fn get_geometry(&mut self) -> ah::Result<Option<RawIoGeometry>> {
+        self.do_open()?;
+        let io = self.io.as_ref().expect("get_geometry: No file.");
+        Ok(io.get_geometry())
+    }
+
+    /// Get the identity of the file or device.
+    pub fn identity(&mut self) -> ah::Result<DisktestDeviceIdentity> {
+        let size = self.get_size()?;
+        let sector_size = self.get_sector_size()?;
+        self.close()?;
+        Ok(DisktestDeviceIdentity::new(&self.path, size, sector_size))
+    }
+
+    /// Get the size of the file or device, in bytes.
+    fn get_size(&mut self) -> ah::Result<u64> {
+        self.do_open()?;
+        let io = self.io.as_mut().expect("get_size: No file.");
+        io.get_size()
+    }
+
+    /// Close the file and try to drop the caches of the given range.
+    /// This also covers data that has been written after `seek_noflush`.
+    fn close_drop_range(&mut self, offset: u64, size: u64) -> ah::Result<()> {
+        self.drop_offset = offset;
+        self.drop_count = size;
+        self.close()
+    }
+
     /// Flush written data and seek to a position in the file.
     fn seek(&mut self, offset: u64) -> ah::Result<u64> {
         if self.drop_count > 0 {
@@ -123,7 +247,12 @@ impl DisktestFile {
     fn seek_noflush(&mut self, offset: u64) -> ah::Result<u64> {
         self.do_open()?;
         let io = self.io.as_mut().expect("seek: No file.");
-        io.seek(offset)
+        let res = io.seek(offset);
+        #[cfg(test)]
+        {
+            self.read_errors.pos = offset;
+        }
+        res
     }
 
     /// Sync all written data to disk.
@@ -138,8 +267,20 @@ impl DisktestFile {
     /// Read data from the file.
     fn read(&mut self, buffer: &mut [u8]) -> ah::Result<RawIoResult> {
         self.do_open()?;
+        #[cfg(test)]
+        if self
+            .read_errors
+            .hit(self.read_errors.pos, buffer.len() as u64)
+        {
+            return Err(ah::format_err!("Injected read error"));
+        }
         let io = self.io.as_mut().expect("read: No file.");
-        io.read(buffer)
+        let res = io.read(buffer);
+        #[cfg(test)]
+        if let Ok(RawIoResult::Ok(n)) = res {
+            self.read_errors.pos += n as u64;
+        }
+        res
     }
 
     /// Write data to the file.
@@ -155,6 +296,78 @@ impl DisktestFile {
         }
     }
 
+    /// Create a queue for the transfers.
+    /// With a queue depth above 1 several transfers are in flight.
+    /// Otherwise the transfers are done by a dedicated I/O thread,
+    /// while the calling thread prepares the next buffers.
+    /// Returns None, if synchronous I/O shall be used.
+    fn queue<B: Send + 'static>(&mut self) -> ah::Result<Option<RawIoQueue<B>>> {
+        self.do_open()?;
+        let io = self.io.as_ref().expect("queue: No file.");
+        #[cfg(test)]
+        self.read_errors.queued.clear();
+        if self.queue_depth > 1 {
+            match RawIoQueue::new(io, self.queue_depth) {
+                Ok(queue) => return Ok(Some(queue)),
+                Err(e) => {
+                    if self.quiet_level < DisktestQuiet::NoWarn {
+                        eprintln!("WARNING: {e:#}. Falling back to the I/O thread.");
+                    }
+                    self.queue_depth = 1;
+                }
+            }
+        }
+        // The I/O thread is not available on all operating systems.
+        Ok(RawIoQueue::new_thread(io, IO_THREAD_DEPTH).ok())
+    }
+
+    /// Wait for the oldest queued write to complete.
+    fn complete_write<B: Send + 'static>(
+        &mut self,
+        queue: &mut RawIoQueue<B>,
+    ) -> Option<(B, ah::Result<RawIoResult>)> {
+        let (buffer, res) = queue.complete()?;
+        if let Ok(RawIoResult::Ok(count)) = res {
+            self.drop_count += count as u64;
+        }
+        Some((buffer, res))
+    }
+
+    /// Submit a read of `len` bytes at `offset` to the queue.
+    /// The tests inject read errors here.
+    #[cfg_attr(not(test), allow(clippy::unused_self))]
+    fn submit_read<B: Send + 'static + AsMut<[u8]>>(
+        &mut self,
+        queue: &mut RawIoQueue<B>,
+        offset: u64,
+        buffer: B,
+        len: usize,
+    ) -> ah::Result<()> {
+        queue.submit_read(offset, buffer, len)?;
+        #[cfg(test)]
+        self.read_errors
+            .queued
+            .push_back(offset..offset + len as u64);
+        Ok(())
+    }
+
+    /// Wait for the oldest queued read to complete.
+    /// The tests inject read errors here.
+    #[cfg_attr(not(test), allow(clippy::unused_self))]
+    fn complete_read<B: Send + 'static>(
+        &mut self,
+        queue: &mut RawIoQueue<B>,
+    ) -> Option<(B, ah::Result<RawIoResult>)> {
+        let (buffer, res) = queue.complete()?;
+        #[cfg(test)]
+        if let Some(r) = self.read_errors.queued.pop_front() {
+            if self.read_errors.hit(r.start, r.end - r.start) {
+                return Some((buffer, Err(ah::format_err!("Injected read error"))));
+            }
+        }
+        Some((buffer, res))
+    }
+
     /// Get a reference to the `PathBuf` in use.
     fn get_path(&self) -> &PathBuf {
         &self.path
@@ -174,10 +387,51 @@ impl Drop for DisktestFile {
     }
 }
 
+/// Result of the Disktest initialization.
+struct DisktestInit {
+    /// The absolute byte offset to start at.
+    byte_offset: u64,
+    /// The size of one generator chunk, in bytes.
+    chunk_size: u64,
+    /// The sector size, in bytes.
+    sector_size: u32,
+}
+
+/// The expected sector header contents of the self-describing stream.
+struct DisktestTagInfo {
+    seed_fingerprint: u64,
+    invert_pattern: bool,
+}
+
+/// The device that has been verified by the last verification run.
+struct DisktestVerifiedDevice {
+    path: PathBuf,
+    size: Option<u64>,
+    sector_size: u32,
+}
+
 /// Disktest core.
 pub struct Disktest {
     stream_agg: DtStreamAgg,
+    algorithm: DtStreamType,
+    layout: DtStreamLayout,
+    nr_threads: usize,
+    chunk_size: Option<u64>,
+    active_chunk_size: u64,
+    max_memory: Option<u64>,
+    seed_fingerprint: u64,
+    round_id: u64,
     abort: Option<Arc<AtomicBool>>,
+    max_errors: Option<u64>,
+    bad_map: BadMap,
+    unreadable_map: BadMap,
+    verified_bytes: u64,
+    verified_device: Option<DisktestVerifiedDevice>,
+    hexdump: Option<DisktestHexdump>,
+    hexdump_context: u32,
+    tag_info: Option<DisktestTagInfo>,
+    state_file: Option<PathBuf>,
+    checkpoint_time: Instant,
     log_count: u64,
     log_time: Instant,
     rate_count: u64,
@@ -229,6 +483,12 @@ impl Disktest {
             nr_threads
         };
 
+        let seed_fingerprint = seed_fingerprint(seed);
+        let tag_info = (algorithm == DtStreamType::Tagged).then_some(DisktestTagInfo {
+            seed_fingerprint,
+            invert_pattern,
+        });
+
         let now = Instant::now();
         Disktest {
             stream_agg: DtStreamAgg::new(
@@ -239,7 +499,25 @@ impl Disktest {
                 nr_threads,
                 quiet_level,
             ),
+            algorithm,
+            layout: DtStreamLayout::default(),
+            nr_threads,
+            chunk_size: None,
+            active_chunk_size: 0,
+            max_memory: None,
+            seed_fingerprint,
+            round_id,
             abort,
+            max_errors: None,
+            bad_map: BadMap::new(),
+            unreadable_map: BadMap::new(),
+            verified_bytes: 0,
+            verified_device: None,
+            hexdump: None,
+            hexdump_context: 0,
+            tag_info,
+            state_file: None,
+            checkpoint_time: now,
             log_count: 0,
             log_time: now,
             rate_count: 0,
@@ -250,6 +528,238 @@ impl Disktest {
         }
     }
 
+    /// Set the maximum number of bad sectors to collect during verification.
+    ///
+    /// If this is `None` (the default), then verification stops at the first
+    /// mismatch or read error.
+    /// Otherwise verification continues after mismatches and read errors and stops after
+    /// `max_errors` bad or unreadable sectors have been found.
+    /// [`Disktest::UNLIMITED`] never stops early.
+    /// On a read error the affected chunk is read again sector by sector
+    /// to find the exact unreadable sectors.
+    /// The collected ranges can be retrieved with [`Disktest::bad_ranges`]
+    /// and [`Disktest::unreadable_ranges`].
+    pub fn set_max_errors(&mut self, max_errors: Option<u64>) {
+        self.max_errors = max_errors;
+    }
+
+    /// Enable a side by side hex dump of the expected and the read data
+    /// of every mismatching sector during verification.
+    ///
+    /// - hexdump: The destination of the hex dump or `None` to disable it (default).
+    ///   The hex dumps of all verifications are appended to a file.
+    /// - context: The number of additional sectors to dump before and after
+    ///   each mismatching sector. The context is limited to the current chunk.
+    pub fn set_hexdump(&mut self, hexdump: Option<DisktestHexdump>, context: u32) {
+        self.hexdump = hexdump;
+        self.hexdump_context = context;
+    }
+
+    /// Select the layout of the random data stream on the device.
+    ///
+    /// The default is [`DtStreamLayout::Thread`] for compatibility with devices
+    /// that have been written by older versions.
+    /// With [`DtStreamLayout::Chunk`] the data does not depend on the number of threads.
+    pub fn set_stream_layout(&mut self, layout: DtStreamLayout) {
+        self.layout = layout;
+        self.stream_agg.set_layout(layout);
+    }
+
+    /// Set the chunk size, which is the size of the write and read transfers.
+    ///
+    /// The size is rounded to a multiple of the generator base size and the sector size.
+    /// The data depends on the chunk size.
+    /// Therefore, the same chunk size must be used for verification.
+    /// It is recorded in the state file.
+    /// `None` (the default) selects the default chunk size of the generator.
+    pub fn set_chunk_size(&mut self, chunk_size: Option<u64>) {
+        self.chunk_size = chunk_size;
+        self.stream_agg.set_chunk_size(chunk_size);
+    }
+
+    /// Limit the memory used by the buffers of the random data stream generator threads,
+    /// of the I/O queue and of the comparisons.
+    ///
+    /// The number of chunks that each thread computes in advance is reduced.
+    /// The chunk size is never changed, because the data depends on it.
+    /// If the limit is too small for the chunk size, then the run fails.
+    /// A smaller chunk size can be set with [`Disktest::set_chunk_size`].
+    /// `None` (the default) means no limit.
+    pub fn set_max_memory(&mut self, max_memory: Option<u64>) {
+        self.max_memory = max_memory;
+        self.stream_agg.set_max_memory(max_memory);
+    }
+
+    /// Distribute the memory limit for the next activation.
+    /// `extra_chunks` chunk sized buffers are held outside of the generator threads.
+    /// `shares` stream aggregators get an equal share of the limit each.
+    fn set_memory_budget(&mut self, extra_chunks: usize, shares: u64) {
+        self.stream_agg
+            .set_max_memory(self.max_memory.map(|max_memory| max_memory / shares));
+        self.stream_agg.set_extra_chunks(extra_chunks);
+    }
+
+    /// Enable checkpointing of write and verify runs to a state file.
+    ///
+    /// The state file is written at the start of every write and verify run,
+    /// periodically while running and when the run is aborted.
+    /// Before a write checkpoint is taken, the written data is synced to the device.
+    /// A run can be resumed from the state file with [`Disktest::check_state`]
+    /// and [`DisktestState::offset`] as the seek offset.
+    pub fn set_state_file(&mut self, path: Option<PathBuf>) {
+        self.state_file = path;
+    }
+
+    /// Check whether a run can be resumed from the given state with this instance.
+    /// The seed, algorithm, stream layout and chunk size must match.
+    /// The state records the rounded chunk size that has actually been used.
+    /// It must be set with [`Disktest::set_chunk_size`] before resuming.
+    /// With [`DtStreamLayout::Thread`] the number of threads must match, too.
+    pub fn check_state(&self, state: &DisktestState) -> ah::Result<()> {
+        if state.seed_fingerprint != self.seed_fingerprint {
+            return Err(ah::format_err!(
+                "Cannot resume: The state file has been written with a different seed."
+            ));
+        }
+        if state.algorithm != self.algorithm {
+            return Err(ah::format_err!(
+                "Cannot resume: The state file has been written with algorithm {:?}.",
+                state.algorithm
+            ));
+        }
+        if state.layout != self.layout {
+            return Err(ah::format_err!(
+                "Cannot resume: The state file has been written with stream layout {:?}.",
+                state.layout
+            ));
+        }
+        if state.layout == DtStreamLayout::Thread && state.threads != self.nr_threads {
+            return Err(ah::format_err!(
+                "Cannot resume: The state file has been written with {} threads.",
+                state.threads
+            ));
+        }
+        if self.chunk_size != Some(state.chunk_size) {
+            return Err(ah::format_err!(
+                "Cannot resume: The state file has been written with a chunk size of {}.",
+                prettybytes(state.chunk_size, true, true, true)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check whether the next periodic checkpoint is due.
+    fn checkpoint_due(&self) -> bool {
+        self.state_file.is_some()
+            && self.checkpoint_time.elapsed().as_secs() >= CHECKPOINT_SEC_THRES
+    }
+
+    /// Write a checkpoint to the state file, if enabled.
+    fn checkpoint(
+        &mut self,
+        phase: DisktestPhase,
+        offset: u64,
+        seek: u64,
+        max_bytes: u64,
+    ) -> ah::Result<()> {
+        if let Some(path) = self.state_file.as_ref() {
+            let state = DisktestState {
+                phase,
+                round_id: self.round_id,
+                offset,
+                seek,
+                max_bytes,
+                seed_fingerprint: self.seed_fingerprint,
+                algorithm: self.algorithm,
+                layout: self.layout,
+                threads: self.nr_threads,
+                chunk_size: self.active_chunk_size,
+            };
+            state.save(path)?;
+            self.checkpoint_time = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Get the bad ranges that have been found by the last verification run.
+    pub fn bad_ranges(&self) -> &[DisktestBadRange] {
+        self.bad_map.ranges()
+    }
+
+    /// Get the unreadable ranges that have been found by the last verification run.
+    pub fn unreadable_ranges(&self) -> &[DisktestBadRange] {
+        self.unreadable_map.ranges()
+    }
+
+    /// Write the bad and unreadable ranges of the last verification run
+    /// as a list of bad block numbers to the file at `path`.
+    ///
+    /// The list has the format that `mke2fs -l` and `e2fsck -l` accept.
+    /// - `block_size`: The block size of the file system, in bytes.
+    ///   The bad ranges are rounded out to this block size.
+    pub fn export_badblocks(&self, path: &Path, block_size: u64) -> ah::Result<()> {
+        if block_size == 0 {
+            return Err(ah::format_err!(
+                "The badblocks block size must not be zero."
+            ));
+        }
+        let ranges = merge_ranges(self.bad_ranges().iter().chain(self.unreadable_ranges()));
+        Self::export_to_file(path, "badblocks list", |out| {
+            write_badblocks(out, &ranges, block_size)
+        })
+    }
+
+    /// Write a `dmsetup` table for the device of the last verification run
+    /// to the file at `path`.
+    ///
+    /// The table maps the good areas of the device back to back
+    /// and leaves out the bad and unreadable ranges.
+    pub fn export_dmsetup_table(&self, path: &Path) -> ah::Result<()> {
+        let Some(device) = self.verified_device.as_ref() else {
+            return Err(ah::format_err!("No device has been verified."));
+        };
+        let Some(size) = device.size else {
+            return Err(ah::format_err!(
+                "The size of {} is unknown.",
+                device.path.display()
+            ));
+        };
+        let ranges = merge_ranges(self.bad_ranges().iter().chain(self.unreadable_ranges()));
+        Self::export_to_file(path, "dmsetup table", |out| {
+            write_dmsetup_table(out, &ranges, &device.path, size, device.sector_size)
+        })
+    }
+
+    /// Create the file at `path` and write an export to it.
+    fn export_to_file(
+        path: &Path,
+        what: &str,
+        f: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+    ) -> ah::Result<()> {
+        let file = File::create(path)
+            .map_err(|e| ah::format_err!("Failed to create {what} {}: {e}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        f(&mut out)
+            .and_then(|()| out.flush())
+            .map_err(|e| ah::format_err!("Failed to write {what} {}: {e}", path.display()))
+    }
+
+    /// Get the accumulated bit level analysis of all mismatches
+    /// that have been found by the last verification run.
+    pub fn bit_errors(&self) -> DisktestBitErrors {
+        self.bad_map.bit_errors()
+    }
+
+    /// Get the bit error rate of the last verification run.
+    /// That is the number of flipped bits divided by the number of verified bits.
+    pub fn bit_error_rate(&self) -> f64 {
+        if self.verified_bytes == 0 {
+            0.0
+        } else {
+            self.bit_errors().flipped() as f64 / (self.verified_bytes as f64 * 8.0)
+        }
+    }
+
     /// Abort was requested by user?
     fn abort_requested(&self) -> bool {
         if let Some(abort) = &self.abort {
@@ -336,18 +846,34 @@ impl Disktest {
         prefix: &str,
         seek: u64,
         max_bytes: u64,
-    ) -> ah::Result<u64> {
+    ) -> ah::Result<DisktestInit> {
         file.quiet_level = self.quiet_level;
         self.log_reset();
 
-        let sector_size = file.get_sector_size().unwrap_or(None);
+        let geometry = file.get_geometry().unwrap_or(None);
+        let sector_size = geometry.map(|g| g.physical_sector_size);
 
         if self.quiet_level < DisktestQuiet::NoInfo {
-            let sector_str = if let Some(sector_size) = sector_size.as_ref() {
-                format!(
-                    " ({} sectors)",
-                    prettybytes((*sector_size).into(), true, false, false),
-                )
+            let sector_str = if let Some(geometry) = geometry.as_ref() {
+                let size = |s: u32| prettybytes(s.into(), true, false, false);
+                let mut sizes = vec![if geometry.logical_sector_size
+                    == geometry.physical_sector_size
+                {
+                    format!("{} sectors", size(geometry.physical_sector_size))
+                } else {
+                    format!(
+                        "{} logical, {} physical sectors",
+                        size(geometry.logical_sector_size),
+                        size(geometry.physical_sector_size),
+                    )
+                }];
+                if let Some(min_io_size) = geometry.min_io_size {
+                    sizes.push(format!("minimum I/O {}", size(min_io_size)));
+                }
+                if let Some(opt_io_size) = geometry.opt_io_size {
+                    sizes.push(format!("optimal I/O {}", size(opt_io_size)));
+                }
+                format!(" ({})", sizes.join(", "))
             } else {
                 "".to_string()
             };
@@ -360,30 +886,55 @@ impl Disktest {
             );
         }
 
-        let res = self
-            .stream_agg
-            .activate(seek, sector_size.unwrap_or(DEFAULT_SECTOR_SIZE))?;
-
-        if let Err(e) = file.seek(res.byte_offset) {
-            return Err(ah::format_err!("File seek to {seek} failed: {e}"));
-        }
-
-        if let Some(sector_size) = sector_size.as_ref() {
+        if let Some(sector_size) = sector_size.map(u64::from) {
+            if seek % sector_size != 0 && self.quiet_level < DisktestQuiet::NoWarn {
+                eprintln!(
+                    "WARNING: The seek offset {} is not a multiple of the physical sector size {}. \
+                    Unaligned accesses cause read-modify-write cycles on the device.",
+                    prettybytes(seek, true, true, true),
+                    prettybytes(sector_size, true, true, true)
+                );
+            }
             if max_bytes < u64::MAX
-                && max_bytes % u64::from(*sector_size) != 0
+                && max_bytes % sector_size != 0
                 && self.quiet_level < DisktestQuiet::NoWarn
             {
-                #[cfg(target_os = "windows")]
                 eprintln!(
-                    "WARNING: The desired byte count of {} is not a multiple of the sector size {}. \
-                    This might result in a write or read error at the very end.",
+                    "WARNING: The byte count {} is not a multiple of the physical sector size {}. \
+                    Unaligned accesses cause read-modify-write cycles on the device.",
                     prettybytes(max_bytes, true, true, true),
-                    prettybytes(u64::from(*sector_size), true, true, true)
+                    prettybytes(sector_size, true, true, true)
                 );
+                #[cfg(target_os = "windows")]
+                eprintln!("This might result in a write or read error at the very end.");
             }
         }
 
-        Ok(res.chunk_size)
+        let res = self
+            .stream_agg
+            .activate(seek, sector_size.unwrap_or(DEFAULT_SECTOR_SIZE))?;
+        self.active_chunk_size = res.chunk_size;
+
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!(
+                "Generator memory: {} ({} threads with {} chunks and {} I/O buffers of {} each).",
+                prettybytes(res.memory, true, false, false),
+                self.nr_threads,
+                res.queue_depth + 1,
+                res.extra_chunks,
+                prettybytes(res.chunk_size, true, false, false),
+            );
+        }
+
+        if let Err(e) = file.seek(res.byte_offset) {
+            return Err(ah::format_err!("File seek to {seek} failed: {e}"));
+        }
+
+        Ok(DisktestInit {
+            byte_offset: res.byte_offset,
+            chunk_size: res.chunk_size,
+            sector_size: sector_size.unwrap_or(DEFAULT_SECTOR_SIZE),
+        })
     }
 
     /// Finalize and flush writing.
@@ -425,17 +976,51 @@ impl Disktest {
         let mut bytes_left = max_bytes;
         let mut bytes_written = 0_u64;
 
-        let write_chunk_size = self.init(&mut file, "Writing", seek, max_bytes)?;
+        // The chunks in the I/O queue.
+        self.set_memory_budget(file.io_depth(), 1);
+        let init = self.init(&mut file, "Writing", seek, max_bytes)?;
+        let write_chunk_size = init.chunk_size;
+        self.checkpoint(
+            DisktestPhase::Write,
+            init.byte_offset,
+            init.byte_offset,
+            max_bytes,
+        )?;
+        let mut queue = file.queue::<DtStreamAggChunk>()?;
+        let mut bytes_queued = 0_u64;
         loop {
-            // Get the next data chunk.
-            let chunk = self.stream_agg.wait_chunk()?;
-            let this_chunk = min(write_chunk_size, bytes_left);
-            let write_len = usize::try_from(this_chunk).unwrap_or(usize::MAX);
+            let res = if let Some(queue) = queue.as_mut() {
+                if !queue.is_full() && bytes_queued < bytes_left {
+                    // Queue the next data chunk for writing.
+                    let chunk = self.stream_agg.wait_chunk()?;
+                    let this_chunk = min(write_chunk_size, bytes_left - bytes_queued);
+                    let write_len = usize::try_from(this_chunk).unwrap_or(usize::MAX);
+                    let offset = init.byte_offset + bytes_written + bytes_queued;
+                    match queue.submit_write(offset, chunk, write_len) {
+                        Ok(()) => {
+                            bytes_queued += write_len as u64;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    // Wait for the oldest chunk to be written.
+                    let (_, res) = file.complete_write(queue).expect("No queued write");
+                    res
+                }
+            } else {
+                // Get the next data chunk.
+                let chunk = self.stream_agg.wait_chunk()?;
+                let this_chunk = min(write_chunk_size, bytes_left);
+                let write_len = usize::try_from(this_chunk).unwrap_or(usize::MAX);
 
-            // Write the chunk to disk.
-            match file.write(&chunk.get_data()[0..write_len]) {
-                Ok(RawIoResult::Ok(_)) => (),
+                // Write the chunk to disk.
+                file.write(&chunk.get_data()[0..write_len])
+            };
+            let write_len = match res {
+                Ok(RawIoResult::Ok(n)) => n,
                 Ok(RawIoResult::Enospc) => {
+                    drop(queue.take()); // Wait for the pending writes.
                     if max_bytes == Disktest::UNLIMITED {
                         self.write_finalize(&mut file, true, bytes_written)?;
                         break; // End of device. -> Success.
@@ -444,9 +1029,13 @@ impl Disktest {
                     return Err(ah::format_err!("Write error: Out of disk space."));
                 }
                 Err(e) => {
+                    drop(queue.take()); // Wait for the pending writes.
                     let _ = self.write_finalize(&mut file, false, bytes_written);
                     return Err(e);
                 }
+            };
+            if queue.is_some() {
+                bytes_queued -= write_len as u64;
             }
 
             // Account for the written bytes.
@@ -458,8 +1047,29 @@ impl Disktest {
             }
             self.log("Wrote ", write_len, bytes_written, false);
 
+            if self.checkpoint_due() {
+                if let Err(e) = file.sync() {
+                    let _ = self.write_finalize(&mut file, false, bytes_written);
+                    return Err(ah::format_err!("Sync failed: {e}"));
+                }
+                self.checkpoint(
+                    DisktestPhase::Write,
+                    init.byte_offset + bytes_written,
+                    init.byte_offset,
+                    max_bytes,
+                )?;
+            }
+
             if self.abort_requested() {
-                let _ = self.write_finalize(&mut file, false, bytes_written);
+                drop(queue.take()); // Wait for the pending writes.
+                // Only record the progress, if the written data has been synced.
+                self.write_finalize(&mut file, false, bytes_written)?;
+                self.checkpoint(
+                    DisktestPhase::Write,
+                    init.byte_offset + bytes_written,
+                    init.byte_offset,
+                    max_bytes,
+                )?;
                 return Err(ah::format_err!("Aborted by signal!"));
             }
         }
@@ -494,6 +1104,7 @@ impl Disktest {
     fn verify_failed(
         &mut self,
         file: &mut DisktestFile,
+        init: &DisktestInit,
         read_count: usize,
         bytes_read: u64,
         buffer: &[u8],
@@ -506,6 +1117,32 @@ impl Disktest {
         }
         for (i, buffer_byte) in buffer.iter().enumerate().take(read_count) {
             if *buffer_byte != chunk.get_data()[i] {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    let sector_size = init.sector_size as usize;
+                    let begin = i - (i % sector_size);
+                    let end = min(begin + sector_size, read_count);
+                    let bit_errors = DisktestBitErrors::from_sector(
+                        &buffer[begin..end],
+                        &chunk.get_data()[begin..end],
+                    );
+                    let desc = if self.tag_info.is_some() {
+                        format!(
+                            "; {}",
+                            self.describe_sector(parse_sector_tag(&buffer[begin..end]).as_ref())
+                        )
+                    } else {
+                        String::new()
+                    };
+                    eprintln!(
+                        "Bad sector at {}: {bit_errors}{desc}.",
+                        prettybytes(
+                            init.byte_offset + bytes_read + begin as u64,
+                            true,
+                            true,
+                            true
+                        ),
+                    );
+                }
                 let pos = bytes_read + i as u64;
                 if pos >= 1024 {
                     return ah::format_err!(
@@ -519,85 +1156,1522 @@ impl Disktest {
         panic!("Internal error: verify_failed() no mismatch.");
     }
 
-    /// Run disktest in verify mode.
-    pub fn verify(&mut self, file: DisktestFile, seek: u64, max_bytes: u64) -> ah::Result<u64> {
-        let mut file = file;
-        let mut bytes_left = max_bytes;
-        let mut bytes_read = 0_u64;
+    /// Compare the read buffer to the expected data sector by sector
+    /// and add the mismatching sectors to the bad map.
+    /// Stops, if the maximum number of errors has been reached.
+    fn verify_collect_mismatches(
+        &mut self,
+        offset: u64,
+        sector_size: u32,
+        buffer: &[u8],
+        expected: &[u8],
+    ) {
+        debug_assert_eq!(buffer.len(), expected.len());
+        let sector_size = sector_size as usize;
+        let mut sector_offset = offset;
+        for (read, exp) in buffer.chunks(sector_size).zip(expected.chunks(sector_size)) {
+            let len = read.len() as u64;
+            if read != exp {
+                let bytes_affected = read.iter().zip(exp).filter(|(a, b)| a != b).count();
+                let bit_errors = DisktestBitErrors::from_sector(read, exp);
+                let tag = self.tag_info.as_ref().and_then(|_| parse_sector_tag(read));
+                self.bad_map
+                    .add(sector_offset, len, bytes_affected as u64, &bit_errors, tag);
+                if self.max_errors_reached() {
+                    break;
+                }
+            }
+            sector_offset += len;
+        }
+    }
 
-        let readbuf_len = self.init(&mut file, "Verifying", seek, max_bytes)?;
-        let readbuf_len =
-            usize::try_from(readbuf_len).context("Number of bytes overflows usize")?;
-        let mut buffer = vec![0; readbuf_len];
-        let mut read_count = 0;
-        let read_len = min(readbuf_len as u64, bytes_left);
-        let mut read_len = usize::try_from(read_len).context("Number of bytes overflows usize")?;
+    /// Describe what a bad sector contains, based on its sector header.
+    /// This is only meaningful, if the self-describing stream is used.
+    fn describe_sector(&self, tag: Option<&DisktestSectorTag>) -> String {
+        let Some(tag) = tag else {
+            return "contains no sector header".to_string();
+        };
+        let mut desc = vec![format!(
+            "contains data written for offset {}",
+            prettybytes(tag.offset, true, true, true)
+        )];
+        if tag.round_id == self.round_id {
+            desc.push(format!("round {}", tag.round_id));
+        } else {
+            desc.push(format!(
+                "round {} (expected round {})",
+                tag.round_id, self.round_id
+            ));
+        }
+        if let Some(info) = self.tag_info.as_ref() {
+            if tag.seed_fingerprint != info.seed_fingerprint {
+                desc.push("with a different seed".to_string());
+            }
+            if tag.inverted != info.invert_pattern {
+                desc.push(if tag.inverted {
+                    "with inverted pattern".to_string()
+                } else {
+                    "with non-inverted pattern".to_string()
+                });
+            }
+        }
+        if !tag.checksum_ok {
+            desc.push("checksum mismatch".to_string());
+        }
+        desc.join(", ")
+    }
 
-        loop {
-            // Read the next chunk from disk.
-            match file.read(&mut buffer[read_count..read_count + (read_len - read_count)]) {
-                Ok(RawIoResult::Ok(n)) => {
-                    read_count += n;
-
-                    // Check if the read buffer is full, or if we are the the end of the disk.
-                    assert!(read_count <= read_len);
-                    if read_count == read_len || (read_count > 0 && n == 0) {
-                        // Calculate and compare the read buffer to the pseudo random sequence.
-                        let chunk = self.stream_agg.wait_chunk()?;
-                        if buffer[..read_count] != chunk.get_data()[..read_count] {
-                            return Err(self.verify_failed(
-                                &mut file, read_count, bytes_read, &buffer, &chunk,
-                            ));
-                        }
+    /// Get the number of bad and unreadable sectors found so far.
+    fn nr_errors(&self) -> u64 {
+        self.bad_map.nr_sectors() + self.unreadable_map.nr_sectors()
+    }
 
-                        // Account for the read bytes.
-                        bytes_read += u64::try_from(read_count).context("u64 overflow")?;
-                        bytes_left -= u64::try_from(read_count).context("u64 overflow")?;
-                        if bytes_left == 0 {
-                            self.verify_finalize(&mut file, true, bytes_read)?;
-                            break;
-                        }
-                        self.log("Verified ", read_count, bytes_read, false);
-                        read_count = 0;
-                        read_len = usize::try_from(min(readbuf_len as u64, bytes_left))
-                            .context("Number of bytes overflows usize")?;
-                    }
+    /// Check if the configured maximum number of errors has been reached.
+    fn max_errors_reached(&self) -> bool {
+        self.max_errors
+            .is_some_and(|max_errors| self.nr_errors() >= max_errors)
+    }
 
-                    // End of the disk?
-                    if n == 0 {
-                        self.verify_finalize(&mut file, true, bytes_read)?;
-                        break;
-                    }
+    /// Check if no bad and no unreadable sectors have been found.
+    fn verify_good(&self) -> bool {
+        self.bad_map.is_empty() && self.unreadable_map.is_empty()
+    }
+
+    /// Print all collected bad and unreadable ranges and construct the verification error.
+    fn verify_bad_ranges_error(&self, limit_reached: bool) -> ah::Error {
+        if self.quiet_level < DisktestQuiet::NoWarn {
+            if !self.unreadable_map.is_empty() {
+                eprintln!("Unreadable ranges:");
+                for (i, range) in self.unreadable_map.ranges().iter().enumerate() {
+                    eprintln!(
+                        "  {:>4}: offset {}, length {}",
+                        i,
+                        prettybytes(range.offset, true, true, true),
+                        prettybytes(range.length, true, true, true),
+                    );
                 }
-                Ok(_) => unreachable!(),
-                Err(e) => {
-                    let _ = self.verify_finalize(&mut file, false, bytes_read);
-                    return Err(ah::format_err!(
-                        "Read error at {}: {}",
-                        prettybytes(bytes_read, true, true, true),
-                        e
-                    ));
+            }
+            if !self.bad_map.is_empty() {
+                eprintln!("Bad ranges:");
+                for (i, range) in self.bad_map.ranges().iter().enumerate() {
+                    eprintln!(
+                        "  {:>4}: offset {}, length {}, {} bytes affected, {}",
+                        i,
+                        prettybytes(range.offset, true, true, true),
+                        prettybytes(range.length, true, true, true),
+                        range.bytes_affected,
+                        range.bit_errors,
+                    );
+                    if self.tag_info.is_some() {
+                        eprintln!("        {}", self.describe_sector(range.tag.as_ref()));
+                    }
                 }
+                eprintln!(
+                    "Bit errors: {}, bit error rate: {:.3e}",
+                    self.bit_errors(),
+                    self.bit_error_rate()
+                );
             }
-
-            if self.abort_requested() {
-                let _ = self.verify_finalize(&mut file, false, bytes_read);
-                return Err(ah::format_err!("Aborted by signal!"));
+            if limit_reached {
+                eprintln!("Maximum number of errors reached. Verification stopped.");
             }
         }
-
-        Ok(bytes_read)
+        let mut msg = vec![];
+        if !self.bad_map.is_empty() {
+            msg.push(format!(
+                "Data MISMATCH in {} bad sectors ({} bad ranges, {} bytes affected)",
+                self.bad_map.nr_sectors(),
+                self.bad_map.ranges().len(),
+                self.bad_map.bytes_affected()
+            ));
+        }
+        if !self.unreadable_map.is_empty() {
+            msg.push(format!(
+                "Read error in {} unreadable sectors ({} unreadable ranges)",
+                self.unreadable_map.nr_sectors(),
+                self.unreadable_map.ranges().len()
+            ));
+        }
+        ah::format_err!("{}!", msg.join(", "))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::generator::{GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCrc};
-    use std::fs::OpenOptions;
-    use std::io::{Seek as _, SeekFrom, Write as _};
-    use std::path::PathBuf;
-    use tempfile::tempdir;
+    /// Open the hex dump destination.
+    /// The hex dump is appended to the file, so that the dumps of all runs are kept.
+    fn hexdump_open(&self) -> ah::Result<Option<Box<dyn Write>>> {
+        Ok(match &self.hexdump {
+            None => None,
+            Some(DisktestHexdump::Console) => Some(Box::new(std::io::stdout())),
+            Some(DisktestHexdump::File(path)) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| {
+                        ah::format_err!("Failed to open hex dump file {}: {e}", path.display())
+                    })?;
+                Some(Box::new(BufWriter::new(file)))
+            }
+        })
+    }
+
+    /// Write a hex dump of up to `max_sectors` mismatching sectors
+    /// and their context sectors.
+    fn hexdump_mismatches(
+        &self,
+        out: &mut dyn Write,
+        offset: u64,
+        sector_size: u32,
+        buffer: &[u8],
+        expected: &[u8],
+        max_sectors: u64,
+    ) {
+        let regions = hexdump_regions(
+            sector_size as usize,
+            self.hexdump_context as usize,
+            buffer,
+            expected,
+            max_sectors,
+        );
+        for (begin, end) in regions {
+            let region_offset = offset + begin as u64;
+            let res = writeln!(
+                out,
+                "\nData MISMATCH in {} at offset {}:",
+                prettybytes((end - begin) as u64, true, false, false),
+                prettybytes(region_offset, true, true, true),
+            )
+            .and_then(|()| {
+                hexdump_diff(
+                    out,
+                    region_offset,
+                    &expected[begin..end],
+                    &buffer[begin..end],
+                )
+            })
+            .and_then(|()| out.flush());
+            if let Err(e) = res {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    eprintln!("WARNING: Failed to write hex dump: {e}");
+                }
+                break;
+            }
+        }
+    }
+
+    /// Re-read the remaining part of the current chunk sector by sector after a read error.
+    /// Unreadable sectors are recorded in `unreadable` as buffer ranges.
+    /// Returns the number of bytes that have been processed,
+    /// which is less than the remaining chunk length only at the end of the disk.
+    fn verify_recover_read(
+        &mut self,
+        file: &mut DisktestFile,
+        chunk_offset: u64,
+        sector_size: u32,
+        buffer: &mut [u8],
+        read_count: usize,
+        unreadable: &mut Vec<(usize, usize)>,
+    ) -> ah::Result<usize> {
+        let sector_size = sector_size as usize;
+        let begin = read_count - (read_count % sector_size);
+        let mut pos = begin;
+
+        'sectors: while pos < buffer.len() {
+            let end = min(pos + sector_size, buffer.len());
+            let sector_offset = chunk_offset + pos as u64;
+
+            file.seek(sector_offset)?;
+            let mut count = 0;
+            while pos + count < end {
+                match file.read(&mut buffer[pos + count..end]) {
+                    Ok(RawIoResult::Ok(0)) => {
+                        // End of the disk.
+                        pos += count;
+                        break 'sectors;
+                    }
+                    Ok(RawIoResult::Ok(n)) => count += n,
+                    Ok(_) => unreachable!(),
+                    Err(e) => {
+                        if self.quiet_level < DisktestQuiet::NoWarn {
+                            eprintln!(
+                                "Unreadable sector at {}: {}",
+                                prettybytes(sector_offset, true, true, true),
+                                e
+                            );
+                        }
+                        unreadable.push((pos, end));
+                        break;
+                    }
+                }
+            }
+            pos = end;
+
+            if self.abort_requested() {
+                break;
+            }
+        }
+
+        // Continue reading after the recovered area.
+        file.seek(chunk_offset + pos as u64)?;
+
+        Ok(pos.max(read_count) - read_count)
+    }
+
+    /// Handle a failed chunk read.
+    /// Without an error limit this fails the verification.
+    /// Otherwise the rest of the chunk after `read_count` is re-read sector by sector.
+    /// Returns the number of bytes that have been processed after `read_count`.
+    #[allow(clippy::too_many_arguments)]
+    fn verify_read_error(
+        &mut self,
+        file: &mut DisktestFile,
+        e: &ah::Error,
+        init: &DisktestInit,
+        bytes_read: u64,
+        buffer: &mut [u8],
+        read_count: usize,
+        unreadable: &mut Vec<(usize, usize)>,
+    ) -> ah::Result<usize> {
+        if self.max_errors.is_none() {
+            return Err(ah::format_err!(
+                "Read error at {}: {}",
+                prettybytes(bytes_read, true, true, true),
+                e
+            ));
+        }
+        if self.quiet_level < DisktestQuiet::NoWarn {
+            eprintln!(
+                "Read error at {}: {}\nRetrying sector by sector...",
+                prettybytes(init.byte_offset + bytes_read, true, true, true),
+                e
+            );
+        }
+        self.verify_recover_read(
+            file,
+            init.byte_offset + bytes_read,
+            init.sector_size,
+            buffer,
+            read_count,
+            unreadable,
+        )
+    }
+
+    /// Read the next chunk from the current file position.
+    /// Returns the number of bytes read,
+    /// which is less than the buffer length only at the end of the disk or on abort.
+    fn verify_read_chunk(
+        &mut self,
+        file: &mut DisktestFile,
+        init: &DisktestInit,
+        bytes_read: u64,
+        buffer: &mut [u8],
+        unreadable: &mut Vec<(usize, usize)>,
+    ) -> ah::Result<usize> {
+        let mut read_count = 0;
+        while read_count < buffer.len() {
+            let n = match file.read(&mut buffer[read_count..]) {
+                Ok(RawIoResult::Ok(n)) => n,
+                Ok(_) => unreachable!(),
+                Err(e) => self.verify_read_error(
+                    file, &e, init, bytes_read, buffer, read_count, unreadable,
+                )?,
+            };
+            if n == 0 {
+                break; // End of the disk.
+            }
+            read_count += n;
+            if self.abort_requested() {
+                break;
+            }
+        }
+        Ok(read_count)
+    }
+
+    /// Evaluate the comparisons of the read chunks to the generated data,
+    /// until no more than `max_pending` comparisons are pending.
+    /// Mismatches fail the verification or are added to the bad map.
+    #[allow(clippy::too_many_arguments)]
+    fn verify_compare_results(
+        &mut self,
+        file: &mut DisktestFile,
+        init: &DisktestInit,
+        max_bytes: u64,
+        mut hexdump_out: Option<&mut Box<dyn Write>>,
+        bytes_compared: &mut u64,
+        spare_buffers: &mut Vec<AlignedBuf>,
+        max_pending: usize,
+    ) -> ah::Result<()> {
+        while self.stream_agg.compares_pending() > max_pending {
+            let cmp = match self.stream_agg.wait_compare() {
+                Ok(cmp) => cmp.expect("No pending comparison"),
+                Err(e) => {
+                    let _ = self.verify_finalize(file, false, *bytes_compared);
+                    return Err(e);
+                }
+            };
+            let read_count = cmp.len;
+            let buffer = &cmp.buffer[..read_count];
+            let expected = &cmp.chunk.get_data()[..read_count];
+
+            if !cmp.equal {
+                let nr_errors = self.nr_errors();
+                if self.max_errors.is_none() {
+                    if let Some(out) = hexdump_out.as_mut() {
+                        self.hexdump_mismatches(
+                            out.as_mut(),
+                            init.byte_offset + *bytes_compared,
+                            init.sector_size,
+                            buffer,
+                            expected,
+                            1,
+                        );
+                    }
+                    return Err(self.verify_failed(
+                        file,
+                        init,
+                        read_count,
+                        *bytes_compared,
+                        &cmp.buffer,
+                        &cmp.chunk,
+                    ));
+                }
+                self.verify_collect_mismatches(
+                    init.byte_offset + *bytes_compared,
+                    init.sector_size,
+                    buffer,
+                    expected,
+                );
+                if let Some(out) = hexdump_out.as_mut() {
+                    self.hexdump_mismatches(
+                        out.as_mut(),
+                        init.byte_offset + *bytes_compared,
+                        init.sector_size,
+                        buffer,
+                        expected,
+                        self.nr_errors() - nr_errors,
+                    );
+                }
+            }
+            self.verified_bytes += u64::try_from(read_count).context("u64 overflow")?;
+            if self.max_errors_reached() {
+                let _ = self.verify_finalize(file, false, *bytes_compared);
+                return Err(self.verify_bad_ranges_error(true));
+            }
+
+            // Account for the verified bytes.
+            *bytes_compared += u64::try_from(read_count).context("u64 overflow")?;
+            self.log("Verified ", read_count, *bytes_compared, false);
+            if self.checkpoint_due() {
+                self.checkpoint(
+                    DisktestPhase::Verify,
+                    init.byte_offset + *bytes_compared,
+                    init.byte_offset,
+                    max_bytes,
+                )?;
+            }
+            spare_buffers.push(cmp.buffer);
+        }
+        Ok(())
+    }
+
+    /// Run disktest in verify mode.
+    pub fn verify(&mut self, file: DisktestFile, seek: u64, max_bytes: u64) -> ah::Result<u64> {
+        let mut file = file;
+        let mut bytes_left = max_bytes;
+        let mut bytes_read = 0_u64;
+
+        self.bad_map.clear();
+        self.unreadable_map.clear();
+        self.verified_bytes = 0;
+
+        let mut hexdump_out = self.hexdump_open()?;
+
+        // The read buffers in the I/O queue, the current read buffer
+        // and the read buffers and chunks of the pending comparisons.
+        self.set_memory_budget(file.io_depth() + 1 + 2 * (VERIFY_COMPARE_DEPTH + 1), 1);
+        let init = self.init(&mut file, "Verifying", seek, max_bytes)?;
+        self.verified_device = Some(DisktestVerifiedDevice {
+            path: file.get_path().clone(),
+            size: file.get_size().ok(),
+            sector_size: init.sector_size,
+        });
+        self.checkpoint(
+            DisktestPhase::Verify,
+            init.byte_offset,
+            init.byte_offset,
+            max_bytes,
+        )?;
+        let readbuf_len =
+            usize::try_from(init.chunk_size).context("Number of bytes overflows usize")?;
+        let mut buffer = AlignedBuf::new(readbuf_len);
+        let mut unreadable = vec![];
+        let mut bytes_compared = 0_u64;
+
+        // The reads of the next chunks are in flight in the I/O thread or queue,
+        // while the generator threads compare the previous chunks.
+        let mut queue = file.queue::<AlignedBuf>()?;
+        let mut bytes_queued = 0_u64;
+        let mut spare_buffers = vec![];
+
+        loop {
+            // Read the next chunk from disk.
+            let read_len = usize::try_from(min(readbuf_len as u64, bytes_left))
+                .context("Number of bytes overflows usize")?;
+            let res = if let Some(q) = queue.as_mut() {
+                let mut res = Ok(());
+                while res.is_ok() && !q.is_full() && bytes_queued < bytes_left {
+                    let len = usize::try_from(bytes_left - bytes_queued).unwrap_or(usize::MAX);
+                    let len = min(readbuf_len, len);
+                    let offset = init.byte_offset + bytes_read + bytes_queued;
+                    let buf = spare_buffers
+                        .pop()
+                        .unwrap_or_else(|| AlignedBuf::new(readbuf_len));
+                    res = file.submit_read(q, offset, buf, len);
+                    bytes_queued += len as u64;
+                }
+                match res {
+                    Ok(()) => {
+                        let (buf, res) = file.complete_read(q).expect("No queued read");
+                        spare_buffers.push(std::mem::replace(&mut buffer, buf));
+                        bytes_queued -= read_len as u64;
+                        match res {
+                            Ok(RawIoResult::Ok(n)) => Ok(n),
+                            Ok(_) => unreachable!(),
+                            Err(e) => {
+                                // Wait for the pending reads,
+                                // before the file is accessed directly.
+                                drop(queue.take());
+                                bytes_queued = 0;
+                                let res = self.verify_read_error(
+                                    &mut file,
+                                    &e,
+                                    &init,
+                                    bytes_read,
+                                    &mut buffer[..read_len],
+                                    0,
+                                    &mut unreadable,
+                                );
+                                queue = file.queue()?;
+                                res
+                            }
+                        }
+                    }
+                    Err(e) => Err(e),
+                }
+            } else {
+                self.verify_read_chunk(
+                    &mut file,
+                    &init,
+                    bytes_read,
+                    &mut buffer[..read_len],
+                    &mut unreadable,
+                )
+            };
+            let read_count = match res {
+                Ok(read_count) => read_count,
+                Err(e) => {
+                    // Report the mismatches in front of the read error first.
+                    drop(queue.take());
+                    self.verify_compare_results(
+                        &mut file,
+                        &init,
+                        max_bytes,
+                        hexdump_out.as_mut(),
+                        &mut bytes_compared,
+                        &mut spare_buffers,
+                        0,
+                    )?;
+                    let _ = self.verify_finalize(&mut file, false, bytes_compared);
+                    return Err(e);
+                }
+            };
+            // An abort interrupts the reading of the chunk.
+            let interrupted = read_count < read_len && self.abort_requested();
+
+            if read_count > 0 && !interrupted {
+                // Calculate the pseudo random sequence
+                // and let the generator threads compare the read buffer to it.
+                let chunk = self.stream_agg.wait_chunk()?;
+
+                // Record the unreadable sectors and exclude them from the comparison.
+                for (begin, end) in unreadable.drain(..) {
+                    let end = min(end, read_count);
+                    if begin >= end {
+                        continue;
+                    }
+                    self.unreadable_map.add(
+                        init.byte_offset + bytes_read + begin as u64,
+                        (end - begin) as u64,
+                        (end - begin) as u64,
+                        &DisktestBitErrors::default(),
+                        None,
+                    );
+                    buffer[begin..end].copy_from_slice(&chunk.get_data()[begin..end]);
+                }
+
+                let next_buffer = spare_buffers
+                    .pop()
+                    .unwrap_or_else(|| AlignedBuf::new(readbuf_len));
+                self.stream_agg.submit_compare(
+                    chunk,
+                    std::mem::replace(&mut buffer, next_buffer),
+                    read_count,
+                );
+
+                // Account for the read bytes.
+                bytes_read += u64::try_from(read_count).context("u64 overflow")?;
+                bytes_left -= u64::try_from(read_count).context("u64 overflow")?;
+            }
+
+            // End of the disk?
+            let finished = bytes_left == 0 || (read_count < read_len && !interrupted);
+            let aborted = !finished && self.abort_requested();
+
+            // Evaluate the comparisons that are done.
+            // All of them, if the reading has stopped.
+            let max_pending = if finished || aborted {
+                0
+            } else {
+                VERIFY_COMPARE_DEPTH
+            };
+            self.verify_compare_results(
+                &mut file,
+                &init,
+                max_bytes,
+                hexdump_out.as_mut(),
+                &mut bytes_compared,
+                &mut spare_buffers,
+                max_pending,
+            )?;
+
+            if finished {
+                drop(queue.take());
+                self.verify_finalize(&mut file, self.verify_good(), bytes_compared)?;
+                break;
+            }
+
+            if aborted {
+                drop(queue.take());
+                let _ = self.verify_finalize(&mut file, false, bytes_compared);
+                self.checkpoint(
+                    DisktestPhase::Verify,
+                    init.byte_offset + bytes_compared,
+                    init.byte_offset,
+                    max_bytes,
+                )?;
+                return Err(ah::format_err!("Aborted by signal!"));
+            }
+        }
+
+        if !self.verify_good() {
+            return Err(self.verify_bad_ranges_error(false));
+        }
+        Ok(bytes_read)
+    }
+
+    /// Read one complete block at the given offset.
+    fn read_block_at(file: &mut DisktestFile, offset: u64, buffer: &mut [u8]) -> ah::Result<()> {
+        file.seek_noflush(offset)?;
+        let mut count = 0;
+        while count < buffer.len() {
+            match file.read(&mut buffer[count..])? {
+                RawIoResult::Ok(0) | RawIoResult::Enospc => {
+                    return Err(ah::format_err!("Unexpected end of file."));
+                }
+                RawIoResult::Ok(n) => count += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Initialize a sampled run and get the absolute end of the sampled range.
+    fn sampled_init(
+        &mut self,
+        file: &mut DisktestFile,
+        prefix: &str,
+        seek: u64,
+        max_bytes: u64,
+        sampling: DisktestSampling,
+    ) -> ah::Result<(DisktestInit, u64)> {
+        sampling.check()?;
+        // The current chunk and read buffer.
+        self.set_memory_budget(2, 1);
+        let init = self.init(file, prefix, seek, max_bytes)?;
+        let end = if max_bytes == Disktest::UNLIMITED {
+            file.get_size().context("Failed to get the device size")?
+        } else {
+            init.byte_offset.saturating_add(max_bytes)
+        };
+        if end <= init.byte_offset {
+            return Err(ah::format_err!(
+                "Nothing to sample. \
+                 The size of the device is unknown or smaller than the seek offset. \
+                 Please specify the number of bytes."
+            ));
+        }
+        Ok((init, end))
+    }
+
+    /// Print the coverage of a sampled run.
+    fn sampled_print_coverage(&self, prefix: &str, coverage: &DisktestCoverage) {
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!("{prefix} {coverage}.");
+        }
+    }
+
+    /// Get the data of the chunk at `chunk_index` from the stream.
+    /// The stream is only re-activated, if the chunk does not directly
+    /// follow the previously fetched chunk.
+    fn sampled_chunk(
+        &mut self,
+        chunk_index: u64,
+        next_index: &mut u64,
+        init: &DisktestInit,
+    ) -> ah::Result<DtStreamAggChunk> {
+        if chunk_index != *next_index {
+            self.stream_agg
+                .activate(chunk_index * init.chunk_size, init.sector_size)?;
+        }
+        *next_index = chunk_index + 1;
+        self.stream_agg.wait_chunk()
+    }
+
+    /// Run disktest in sampled write mode.
+    ///
+    /// Only the chunks selected by `sampling` are written.
+    /// The selected chunks are spread over the whole range from `seek` to `seek + max_bytes`.
+    /// If `max_bytes` is `UNLIMITED`, then the range ends at the end of the device.
+    /// The written data is identical to the data a full [`Disktest::write`]
+    /// writes to the selected chunks.
+    pub fn write_sampled(
+        &mut self,
+        file: DisktestFile,
+        seek: u64,
+        max_bytes: u64,
+        sampling: DisktestSampling,
+    ) -> ah::Result<DisktestCoverage> {
+        let mut file = file;
+        let (init, end) =
+            self.sampled_init(&mut file, "Sample writing", seek, max_bytes, sampling)?;
+        let mut coverage = DisktestCoverage::new(init.byte_offset, end, init.chunk_size);
+
+        let mut result = Ok(());
+        let first_index = init.byte_offset / init.chunk_size;
+        let mut next_index = first_index;
+        for chunk_index in first_index..end.div_ceil(init.chunk_size) {
+            if !sampling.is_selected(chunk_index, self.seed_fingerprint, self.round_id) {
+                continue;
+            }
+            let offset = chunk_index * init.chunk_size;
+            let chunk = self.sampled_chunk(chunk_index, &mut next_index, &init)?;
+            let write_len = min(init.chunk_size, end - offset);
+            let write_len =
+                usize::try_from(write_len).context("Number of bytes overflows usize")?;
+
+            // Write the chunk to disk.
+            if let Err(e) = file.seek_noflush(offset) {
+                result = Err(e);
+                break;
+            }
+            match file.write(&chunk.get_data()[..write_len]) {
+                Ok(RawIoResult::Ok(_)) => (),
+                Ok(RawIoResult::Enospc) => {
+                    result = Err(ah::format_err!(
+                        "Write error at {}: Out of disk space.",
+                        prettybytes(offset, true, true, true)
+                    ));
+                    break;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            coverage.add(offset, write_len as u64);
+            self.log("Wrote ", write_len, coverage.bytes_sampled, false);
+
+            if self.abort_requested() {
+                result = Err(ah::format_err!("Aborted by signal!"));
+                break;
+            }
+        }
+        coverage.finish();
+
+        // Sync and drop the caches of the whole sampled range.
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!("Writing stopped. Syncing...");
+        }
+        let sync_result = file.sync().context("Sync failed");
+        self.log(
+            if result.is_ok() {
+                "Done. Wrote "
+            } else {
+                "Wrote "
+            },
+            0,
+            coverage.bytes_sampled,
+            true,
+        );
+        let close_result = file
+            .close_drop_range(init.byte_offset, end - init.byte_offset)
+            .context("Failed to drop operating system caches");
+        self.sampled_print_coverage("Wrote", &coverage);
+        result.and(sync_result).and(close_result)?;
+
+        Ok(coverage)
+    }
+
+    /// Read one complete chunk at the given offset for verification.
+    /// With `max_errors`, read errors are recovered sector by sector
+    /// and the unreadable sectors are recorded in `unreadable` as buffer ranges.
+    fn verify_read_at(
+        &mut self,
+        file: &mut DisktestFile,
+        offset: u64,
+        sector_size: u32,
+        buffer: &mut [u8],
+        unreadable: &mut Vec<(usize, usize)>,
+    ) -> ah::Result<()> {
+        file.seek_noflush(offset)?;
+        let mut count = 0;
+        while count < buffer.len() {
+            match file.read(&mut buffer[count..]) {
+                Ok(RawIoResult::Ok(0) | RawIoResult::Enospc) => break,
+                Ok(RawIoResult::Ok(n)) => count += n,
+                Err(e) => {
+                    if self.max_errors.is_none() {
+                        return Err(ah::format_err!(
+                            "Read error at {}: {}",
+                            prettybytes(offset + count as u64, true, true, true),
+                            e
+                        ));
+                    }
+                    if self.quiet_level < DisktestQuiet::NoWarn {
+                        eprintln!(
+                            "Read error at {}: {}\nRetrying sector by sector...",
+                            prettybytes(offset + count as u64, true, true, true),
+                            e
+                        );
+                    }
+                    count += self.verify_recover_read(
+                        file,
+                        offset,
+                        sector_size,
+                        buffer,
+                        count,
+                        unreadable,
+                    )?;
+                    break;
+                }
+            }
+        }
+        if count < buffer.len() {
+            return Err(ah::format_err!(
+                "Unexpected end of file at {}.",
+                prettybytes(offset + count as u64, true, true, true)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Compare a chunk that has been read at `offset` to the expected data.
+    /// The `unreadable` buffer ranges are recorded and excluded from the comparison.
+    /// The mismatching sectors are added to the bad map.
+    fn verify_compare_at(
+        &mut self,
+        offset: u64,
+        sector_size: u32,
+        buffer: &mut [u8],
+        expected: &[u8],
+        unreadable: &mut Vec<(usize, usize)>,
+        hexdump_out: &mut Option<Box<dyn Write>>,
+    ) {
+        for (begin, end) in unreadable.drain(..) {
+            self.unreadable_map.add(
+                offset + begin as u64,
+                (end - begin) as u64,
+                (end - begin) as u64,
+                &DisktestBitErrors::default(),
+                None,
+            );
+            buffer[begin..end].copy_from_slice(&expected[begin..end]);
+        }
+        if buffer != expected {
+            let nr_errors = self.nr_errors();
+            self.verify_collect_mismatches(offset, sector_size, buffer, expected);
+            if let Some(out) = hexdump_out.as_mut() {
+                let count = if self.max_errors.is_none() {
+                    1
+                } else {
+                    self.nr_errors() - nr_errors
+                };
+                self.hexdump_mismatches(out.as_mut(), offset, sector_size, buffer, expected, count);
+            }
+        }
+        self.verified_bytes += buffer.len() as u64;
+    }
+
+    /// Check if verification has to stop because of the errors found so far.
+    fn verify_must_stop(&self) -> bool {
+        (self.max_errors.is_none() && !self.verify_good()) || self.max_errors_reached()
+    }
+
+    /// Run disktest in sampled verify mode.
+    ///
+    /// Only the chunks selected by `sampling` are verified.
+    /// The device must have been written with the same seed, round and layout,
+    /// either fully with [`Disktest::write`] or with [`Disktest::write_sampled`]
+    /// and the same `sampling`.
+    /// See [`Disktest::write_sampled`] for the range.
+    pub fn verify_sampled(
+        &mut self,
+        file: DisktestFile,
+        seek: u64,
+        max_bytes: u64,
+        sampling: DisktestSampling,
+    ) -> ah::Result<DisktestCoverage> {
+        let mut file = file;
+
+        self.bad_map.clear();
+        self.unreadable_map.clear();
+        self.verified_bytes = 0;
+
+        let mut hexdump_out = self.hexdump_open()?;
+
+        let (init, end) =
+            self.sampled_init(&mut file, "Sample verifying", seek, max_bytes, sampling)?;
+        self.verified_device = Some(DisktestVerifiedDevice {
+            path: file.get_path().clone(),
+            size: file.get_size().ok(),
+            sector_size: init.sector_size,
+        });
+        let mut coverage = DisktestCoverage::new(init.byte_offset, end, init.chunk_size);
+
+        let readbuf_len =
+            usize::try_from(init.chunk_size).context("Number of bytes overflows usize")?;
+        let mut buffer = AlignedBuf::new(readbuf_len);
+        let mut unreadable = vec![];
+
+        let mut result = Ok(());
+        let first_index = init.byte_offset / init.chunk_size;
+        let mut next_index = first_index;
+        for chunk_index in first_index..end.div_ceil(init.chunk_size) {
+            if !sampling.is_selected(chunk_index, self.seed_fingerprint, self.round_id) {
+                continue;
+            }
+            let offset = chunk_index * init.chunk_size;
+            let read_len = min(init.chunk_size, end - offset);
+            let read_len = usize::try_from(read_len).context("Number of bytes overflows usize")?;
+            let buffer = &mut buffer[..read_len];
+
+            // Read the chunk from disk.
+            if let Err(e) =
+                self.verify_read_at(&mut file, offset, init.sector_size, buffer, &mut unreadable)
+            {
+                result = Err(e);
+                break;
+            }
+
+            // Compare the chunk to the pseudo random sequence.
+            let chunk = self.sampled_chunk(chunk_index, &mut next_index, &init)?;
+            self.verify_compare_at(
+                offset,
+                init.sector_size,
+                buffer,
+                &chunk.get_data()[..read_len],
+                &mut unreadable,
+                &mut hexdump_out,
+            );
+            drop(chunk);
+
+            coverage.add(offset, read_len as u64);
+            if self.verify_must_stop() {
+                break;
+            }
+            self.log("Verified ", read_len, coverage.bytes_sampled, false);
+
+            if self.abort_requested() {
+                result = Err(ah::format_err!("Aborted by signal!"));
+                break;
+            }
+        }
+        coverage.finish();
+
+        let success = result.is_ok() && self.verify_good();
+        self.log(
+            if success {
+                "Done. Verified "
+            } else {
+                "Verified "
+            },
+            0,
+            coverage.bytes_sampled,
+            true,
+        );
+        let close_result = file.close().context("Failed to close device");
+        self.sampled_print_coverage("Verified", &coverage);
+        result.and(close_result)?;
+
+        if !self.verify_good() {
+            return Err(self.verify_bad_ranges_error(self.max_errors_reached()));
+        }
+        Ok(coverage)
+    }
+
+    /// Sync the written window from `begin` to `end`, drop its caches and verify it.
+    /// Returns the number of verified bytes.
+    fn write_verify_window(
+        &mut self,
+        file: &mut DisktestFile,
+        verify_agg: &mut DtStreamAgg,
+        init: &DisktestInit,
+        begin: u64,
+        end: u64,
+        hexdump_out: &mut Option<Box<dyn Write>>,
+    ) -> ah::Result<u64> {
+        file.sync().context("Sync failed")?;
+        file.close_drop_range(begin, end - begin)
+            .context("Failed to drop operating system caches")?;
+
+        let readbuf_len =
+            usize::try_from(init.chunk_size).context("Number of bytes overflows usize")?;
+        let mut buffer = AlignedBuf::new(readbuf_len);
+        let mut unreadable = vec![];
+        let mut offset = begin;
+        while offset < end {
+            let read_len = min(init.chunk_size, end - offset);
+            let read_len = usize::try_from(read_len).context("Number of bytes overflows usize")?;
+            let buffer = &mut buffer[..read_len];
+
+            self.verify_read_at(file, offset, init.sector_size, buffer, &mut unreadable)?;
+            let chunk = verify_agg.wait_chunk()?;
+            self.verify_compare_at(
+                offset,
+                init.sector_size,
+                buffer,
+                &chunk.get_data()[..read_len],
+                &mut unreadable,
+                hexdump_out,
+            );
+            offset += read_len as u64;
+            if self.verify_must_stop() {
+                break;
+            }
+        }
+
+        // Continue writing after the window.
+        file.seek_noflush(end)?;
+        Ok(offset - begin)
+    }
+
+    /// Write and verify loop of [`Disktest::write_verify`].
+    /// Returns the number of written and verified bytes.
+    fn write_verify_loop(
+        &mut self,
+        file: &mut DisktestFile,
+        init: &DisktestInit,
+        max_bytes: u64,
+        lag: u64,
+        hexdump_out: &mut Option<Box<dyn Write>>,
+    ) -> ah::Result<u64> {
+        let mut verify_agg = self.stream_agg.fork();
+        verify_agg.activate(init.byte_offset, init.sector_size)?;
+
+        let mut bytes_left = max_bytes;
+        let mut bytes_written = 0_u64;
+        let mut bytes_verified = 0_u64;
+        loop {
+            // Get the next data chunk and write it to disk.
+            let chunk = self.stream_agg.wait_chunk()?;
+            let this_chunk = min(init.chunk_size, bytes_left);
+            let write_len = usize::try_from(this_chunk).unwrap_or(usize::MAX);
+            let mut done = match file.write(&chunk.get_data()[0..write_len])? {
+                RawIoResult::Ok(_) => {
+                    bytes_written += write_len as u64;
+                    bytes_left -= write_len as u64;
+                    self.log("Wrote ", write_len, bytes_written, false);
+                    bytes_left == 0
+                }
+                RawIoResult::Enospc => {
+                    if max_bytes != Disktest::UNLIMITED {
+                        return Err(ah::format_err!("Write error: Out of disk space."));
+                    }
+                    true // End of device.
+                }
+            };
+            drop(chunk);
+
+            // Verify the window that has been written since the last verification.
+            if done || bytes_written - bytes_verified >= lag {
+                bytes_verified += self.write_verify_window(
+                    file,
+                    &mut verify_agg,
+                    init,
+                    init.byte_offset + bytes_verified,
+                    init.byte_offset + bytes_written,
+                    hexdump_out,
+                )?;
+                done |= self.verify_must_stop();
+            }
+            if done {
+                break;
+            }
+
+            if self.abort_requested() {
+                return Err(ah::format_err!("Aborted by signal!"));
+            }
+        }
+        Ok(bytes_verified)
+    }
+
+    /// Run disktest in interleaved write and verify mode.
+    ///
+    /// The data is written as with [`Disktest::write`].
+    /// Each time `lag` bytes have been written, the written window is synced,
+    /// its caches are dropped and it is read back and verified.
+    /// Then writing continues.
+    /// That way errors are found shortly after writing
+    /// and not only after a full write pass.
+    /// `lag` is rounded up to a multiple of the generator chunk size.
+    ///
+    /// The file must be opened for reading and writing.
+    pub fn write_verify(
+        &mut self,
+        file: DisktestFile,
+        seek: u64,
+        max_bytes: u64,
+        lag: u64,
+    ) -> ah::Result<u64> {
+        let mut file = file;
+
+        self.bad_map.clear();
+        self.unreadable_map.clear();
+        self.verified_bytes = 0;
+
+        let mut hexdump_out = self.hexdump_open()?;
+
+        // The writer and the verifier have their own generator threads.
+        // They hold the written chunk and the read buffer and chunk of the verification.
+        self.set_memory_budget(3, 2);
+        let init = self.init(&mut file, "Writing and verifying", seek, max_bytes)?;
+        self.verified_device = Some(DisktestVerifiedDevice {
+            path: file.get_path().clone(),
+            size: file.get_size().ok(),
+            sector_size: init.sector_size,
+        });
+        let lag = lag
+            .max(1)
+            .checked_next_multiple_of(init.chunk_size)
+            .unwrap_or(u64::MAX);
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!(
+                "Verifying after every {} written.",
+                prettybytes(lag, true, false, false)
+            );
+        }
+
+        let result = self.write_verify_loop(&mut file, &init, max_bytes, lag, &mut hexdump_out);
+        let sync_result = file.sync().context("Sync failed");
+        let close_result = file
+            .close()
+            .context("Failed to drop operating system caches");
+        let success = result.is_ok() && self.verify_good();
+        self.log(
+            if success {
+                "Done. Wrote and verified "
+            } else {
+                "Wrote and verified "
+            },
+            0,
+            self.verified_bytes,
+            true,
+        );
+        let bytes = result?;
+        sync_result?;
+        close_result?;
+
+        if !self.verify_good() {
+            return Err(self.verify_bad_ranges_error(self.max_errors_reached()));
+        }
+        Ok(bytes)
+    }
+
+    /// Print the result of a probe run.
+    fn probe_print_result(&self, res: &DisktestProbeResult) {
+        if self.quiet_level >= DisktestQuiet::NoInfo {
+            return;
+        }
+        let pretty = |n| prettybytes(n, true, true, true);
+        println!("Probe result:");
+        println!(
+            "    Advertised size:           {}",
+            pretty(res.advertised_size)
+        );
+        if let Some(highest_good) = res.highest_good {
+            println!("    Highest known-good offset: {}", pretty(highest_good));
+        } else {
+            println!("    Highest known-good offset: none");
+        }
+        println!(
+            "    Known-good capacity:       {}",
+            pretty(res.usable_size())
+        );
+        if let Some(lowest_bad) = res.lowest_bad {
+            println!("    Lowest bad offset:         {}", pretty(lowest_bad));
+        }
+        if let Some(wrap_size) = res.wrap_size {
+            println!(
+                "    Addresses wrap around every {} ({} aliased blocks):",
+                pretty(wrap_size),
+                res.aliases.len()
+            );
+            for (offset, other) in res.aliases.iter().take(PROBE_MAX_ALIAS_LINES) {
+                println!(
+                    "        Offset {} reads the data of offset {}",
+                    pretty(*offset),
+                    pretty(*other)
+                );
+            }
+            if res.aliases.len() > PROBE_MAX_ALIAS_LINES {
+                println!("        ...");
+            }
+        }
+    }
+
+    /// Run disktest in fake capacity probe mode.
+    ///
+    /// Small uniquely tagged blocks are written to logarithmically and randomly
+    /// distributed offsets of the device. After dropping the caches the blocks
+    /// are read back and checked for missing data and address aliasing.
+    ///
+    /// This overwrites the probed blocks on the device.
+    ///
+    /// `max_bytes` is the advertised size of the device to probe.
+    /// If it is `UNLIMITED`, then the size is taken from the device.
+    pub fn probe(&mut self, file: DisktestFile, max_bytes: u64) -> ah::Result<DisktestProbeResult> {
+        let mut file = file;
+        file.quiet_level = self.quiet_level;
+        self.log_reset();
+
+        let sector_size = file
+            .get_sector_size()
+            .unwrap_or(None)
+            .unwrap_or(DEFAULT_SECTOR_SIZE);
+        let block_size = PROBE_BLOCK_SIZE.next_multiple_of(sector_size.into());
+        let size = if max_bytes == Disktest::UNLIMITED {
+            file.get_size().context("Failed to get the device size")?
+        } else {
+            max_bytes
+        };
+
+        let mut rng = rand::rng();
+        let nonce: u64 = rng.random();
+        let offsets = probe_offsets(size, block_size, PROBE_NR_RANDOM, &mut rng);
+        if offsets.is_empty() {
+            let _ = file.close();
+            return Err(ah::format_err!(
+                "The device size of {} is too small for probing.",
+                prettybytes(size, true, true, true)
+            ));
+        }
+
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!(
+                "Probing {} ({} advertised) with {} blocks of {}...",
+                file.get_path().display(),
+                prettybytes(size, true, true, false),
+                offsets.len(),
+                prettybytes(block_size, true, false, false),
+            );
+        }
+
+        let block_len = usize::try_from(block_size).context("Block size overflows usize")?;
+        let mut buffer = AlignedBuf::new(block_len);
+        let mut probes: Vec<(u64, ProbeStatus)> =
+            offsets.iter().map(|&o| (o, ProbeStatus::Good)).collect();
+
+        // Write all probe blocks in ascending order.
+        for (offset, status) in &mut probes {
+            if self.abort_requested() {
+                let _ = file.close();
+                return Err(ah::format_err!("Aborted by signal!"));
+            }
+            probe_block(nonce, *offset, &mut buffer);
+            let res = file.seek_noflush(*offset).and_then(|_| file.write(&buffer));
+            if !matches!(res, Ok(RawIoResult::Ok(_))) {
+                *status = ProbeStatus::WriteError;
+            }
+        }
+        if let Err(e) = file.sync() {
+            let _ = file.close();
+            return Err(ah::format_err!("Sync failed: {e}"));
+        }
+        if let Err(e) = file.close_drop_range(0, size) {
+            return Err(ah::format_err!(
+                "Failed to drop operating system caches: {e}"
+            ));
+        }
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!("Probe blocks written. Reading them back...");
+        }
+
+        // Read all probe blocks back.
+        for (offset, status) in &mut probes {
+            if *status != ProbeStatus::Good {
+                continue;
+            }
+            if self.abort_requested() {
+                let _ = file.close();
+                return Err(ah::format_err!("Aborted by signal!"));
+            }
+            *status = match Self::read_block_at(&mut file, *offset, &mut buffer) {
+                Ok(()) => match probe_block_parse(nonce, &buffer) {
+                    Some(tag) if tag == *offset => ProbeStatus::Good,
+                    Some(tag) => ProbeStatus::Alias(tag),
+                    None => ProbeStatus::Corrupt,
+                },
+                Err(_) => ProbeStatus::ReadError,
+            };
+        }
+        file.close()?;
+
+        let res = probe_evaluate(size, block_size, &probes);
+        self.log("Done. Probed ", 0, probes.len() as u64 * block_size, true);
+        self.probe_print_result(&res);
+        Ok(res)
+    }
+
+    /// Check whether the stream contains `block` or its bitwise inversion
+    /// at the absolute byte offset `offset`.
+    /// Returns `Some(inverted)` on a match.
+    fn analyze_compare_at(
+        &mut self,
+        offset: u64,
+        sector_size: u32,
+        chunk_size: u64,
+        block: &[u8],
+        inverted_block: &[u8],
+    ) -> ah::Result<Option<bool>> {
+        let chunk_offset = offset - (offset % chunk_size);
+        self.stream_agg.activate(chunk_offset, sector_size)?;
+
+        let skip = usize::try_from(offset - chunk_offset).context("Offset overflows usize")?;
+        let mut data = Vec::with_capacity(skip + block.len());
+        while data.len() < skip + block.len() {
+            data.extend_from_slice(self.stream_agg.wait_chunk()?.get_data());
+        }
+        let data = &data[skip..skip + block.len()];
+
+        Ok(if data == block {
+            Some(false)
+        } else if data == inverted_block {
+            Some(true)
+        } else {
+            None
+        })
+    }
+
+    /// Search the stream from the start up to `max_bytes` for `block`
+    /// or its bitwise inversion at every 512 byte aligned position.
+    /// Returns `Some((offset, inverted))` on a match.
+    fn analyze_search(
+        &mut self,
+        sector_size: u32,
+        chunk_size: u64,
+        block: &[u8],
+        inverted_block: &[u8],
+        max_bytes: u64,
+    ) -> ah::Result<Option<(u64, bool)>> {
+        // All sector sizes are multiples of the default sector size.
+        let step = DEFAULT_SECTOR_SIZE as usize;
+        let needle_len = min(block.len(), step);
+
+        self.stream_agg.activate(0, sector_size)?;
+        let mut chunk_offset = 0;
+        while chunk_offset < max_bytes {
+            if self.abort_requested() {
+                return Err(ah::format_err!("Aborted by signal!"));
+            }
+
+            // Find the candidate positions in this chunk by comparing the first sector.
+            let mut candidates = vec![];
+            let chunk = self.stream_agg.wait_chunk()?;
+            let data = chunk.get_data();
+            let len = usize::try_from(min(data.len() as u64, max_bytes - chunk_offset))
+                .context("Chunk length overflows usize")?;
+            for needle in [&block[..needle_len], &inverted_block[..needle_len]] {
+                let mut pos = 0;
+                while let Some(found) = find_aligned(&data[pos..len], needle, step) {
+                    candidates.push(chunk_offset + (pos + found) as u64);
+                    pos += found + step;
+                }
+            }
+            drop(chunk);
+
+            // Compare the whole block at the candidate positions.
+            if !candidates.is_empty() {
+                candidates.sort_unstable();
+                for candidate in candidates {
+                    if let Some(inverted) = self.analyze_compare_at(
+                        candidate,
+                        sector_size,
+                        chunk_size,
+                        block,
+                        inverted_block,
+                    )? {
+                        return Ok(Some((candidate, inverted)));
+                    }
+                }
+                self.stream_agg
+                    .activate(chunk_offset + chunk_size, sector_size)?;
+            }
+
+            chunk_offset += chunk_size;
+            self.log("Searched ", len, min(chunk_offset, max_bytes), false);
+        }
+        self.log("Done. Searched ", 0, max_bytes, true);
+        Ok(None)
+    }
+
+    /// Run disktest in analyze mode to identify the origin of a block of data.
+    ///
+    /// The block of `length` bytes at `offset` is read from the file.
+    /// Blocks that only contain 0x00 or 0xFF bytes are classified as such.
+    /// Otherwise the block is searched for in the random data stream of this
+    /// instance's round, both in normal and in inverted pattern.
+    /// The expected position `offset` and, for the tagged stream, the offset from
+    /// the sector header are checked first.
+    /// Then the stream is searched from the start up to `max_bytes`.
+    ///
+    /// - length: The block length, in bytes. The special value `0` selects one sector.
+    /// - `max_bytes`: The search range, in bytes.
+    ///   If it is `UNLIMITED`, then the size of the file is used.
+    pub fn analyze(
+        &mut self,
+        file: DisktestFile,
+        offset: u64,
+        length: u64,
+        max_bytes: u64,
+    ) -> ah::Result<DisktestBlockOrigin> {
+        let mut file = file;
+        file.quiet_level = self.quiet_level;
+        self.log_reset();
+
+        let sector_size = file
+            .get_sector_size()
+            .unwrap_or(None)
+            .unwrap_or(DEFAULT_SECTOR_SIZE);
+        let length = if length == 0 {
+            sector_size.into()
+        } else {
+            length
+        };
+        let max_bytes = if max_bytes == Disktest::UNLIMITED {
+            file.get_size().context("Failed to get the device size")?
+        } else {
+            max_bytes
+        };
+
+        if self.quiet_level < DisktestQuiet::NoInfo {
+            println!(
+                "Analyzing {} of {} at offset {} (round {})...",
+                prettybytes(length, true, false, false),
+                file.get_path().display(),
+                prettybytes(offset, true, true, false),
+                self.round_id,
+            );
+        }
+
+        let mut block = vec![0; usize::try_from(length).context("Length overflows usize")?];
+        let res = Self::read_block_at(&mut file, offset, &mut block);
+        file.close()?;
+        if let Err(e) = res {
+            return Err(ah::format_err!(
+                "Failed to read {} at offset {}: {e}",
+                prettybytes(length, true, true, true),
+                prettybytes(offset, true, true, true)
+            ));
+        }
+
+        if let Some(origin) = classify_fill(&block) {
+            return Ok(origin);
+        }
+        let inverted_block: Vec<u8> = block.iter().map(|x| !x).collect();
+        self.set_memory_budget(1, 1);
+        let chunk_size = self.stream_agg.activate(0, sector_size)?.chunk_size;
+
+        // Check the expected position and the tagged position first.
+        let mut candidates = vec![offset];
+        if self.tag_info.is_some() {
+            if let Some(tag) = parse_sector_tag(&block) {
+                candidates.insert(0, tag.offset);
+            }
+        }
+        for candidate in candidates {
+            if let Some(inverted) = self.analyze_compare_at(
+                candidate,
+                sector_size,
+                chunk_size,
+                &block,
+                &inverted_block,
+            )? {
+                return Ok(DisktestBlockOrigin::Stream {
+                    offset: candidate,
+                    round_id: self.round_id,
+                    inverted,
+                });
+            }
+        }
+
+        Ok(
+            match self.analyze_search(
+                sector_size,
+                chunk_size,
+                &block,
+                &inverted_block,
+                max_bytes,
+            )? {
+                Some((offset, inverted)) => DisktestBlockOrigin::Stream {
+                    offset,
+                    round_id: self.round_id,
+                    inverted,
+                },
+                None => DisktestBlockOrigin::Unrelated,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::DisktestBlockOrigin;
+    use crate::generator::{
+        GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCrc, GeneratorPattern,
+        GeneratorTagged, GeneratorXoshiro,
+    };
+    use std::fs::OpenOptions;
+    use std::io::{Read as _, Seek as _, SeekFrom};
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    /// Open the test image at `path` for reading and writing.
+    /// The image is created, if it does not exist.
+    fn open_image(path: &Path) -> DisktestFile {
+        DisktestFile::open(path, true, true).unwrap()
+    }
+
+    /// Create a disktest instance with the seed and settings that most tests use.
+    fn new_disktest(algorithm: DtStreamType, round_id: u64, nr_threads: usize) -> Disktest {
+        Disktest::new(
+            algorithm,
+            &[1, 2, 3],
+            round_id,
+            false,
+            nr_threads,
+            DisktestQuiet::Normal,
+            None,
+        )
+    }
 
     fn run_test(algorithm: DtStreamType, base_size: usize, chunk_factor: usize) {
         let tdir = tempdir().unwrap();
@@ -630,9 +2704,12 @@ mod tests {
                 read: true,
                 write: true,
                 io: Some(io),
+                direct: false,
+                queue_depth: 1,
                 drop_offset: 0,
                 drop_count: 0,
                 quiet_level: DisktestQuiet::Normal,
+                read_errors: InjectedReadErrors::default(),
             }
         };
 
@@ -718,6 +2795,99 @@ mod tests {
             serial += 1;
         }
 
+        // Modify the written data and collect all bad ranges.
+        {
+            let nr_bytes = 8192;
+            assert_eq!(
+                dt.write(mk_file(serial, true), 0, nr_bytes).unwrap(),
+                nr_bytes
+            );
+            {
+                let path = mk_filepath(serial);
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)
+                    .unwrap();
+                for (pos, len) in [(10, 2), (600, 1), (1100, 1), (5000, 3)] {
+                    let mut data = vec![0; len];
+                    file.seek(SeekFrom::Start(pos)).unwrap();
+                    file.read_exact(&mut data).unwrap();
+                    for x in &mut data {
+                        *x ^= 0xFF;
+                    }
+                    file.seek(SeekFrom::Start(pos)).unwrap();
+                    file.write_all(&data).unwrap();
+                }
+            }
+            dt.set_max_errors(Some(Disktest::UNLIMITED));
+            let hexdump_path = tdir_path.join(format!("hexdump-{serial}.txt"));
+            dt.set_hexdump(Some(DisktestHexdump::File(hexdump_path.clone())), 1);
+            match dt.verify(mk_file(serial, false), 0, nr_bytes) {
+                Ok(_) => panic!("Verify of modified data did not fail!"),
+                Err(e) => assert_eq!(
+                    e.to_string(),
+                    "Data MISMATCH in 4 bad sectors (2 bad ranges, 7 bytes affected)!"
+                ),
+            }
+            assert_eq!(
+                dt.bad_ranges(),
+                &[
+                    DisktestBadRange {
+                        offset: 0,
+                        length: 1536,
+                        bytes_affected: 4,
+                        bit_errors: dt.bad_ranges()[0].bit_errors,
+                        tag: dt.bad_ranges()[0].tag,
+                    },
+                    DisktestBadRange {
+                        offset: 4608,
+                        length: 512,
+                        bytes_affected: 3,
+                        bit_errors: dt.bad_ranges()[1].bit_errors,
+                        tag: dt.bad_ranges()[1].tag,
+                    },
+                ]
+            );
+            assert_eq!(dt.bad_ranges()[0].bit_errors.flipped(), 4 * 8);
+            assert_eq!(dt.bad_ranges()[1].bit_errors.flipped(), 3 * 8);
+            assert_eq!(dt.bit_errors().flipped(), 7 * 8);
+            let hexdump = std::fs::read_to_string(&hexdump_path).unwrap();
+            assert!(hexdump.contains("Data MISMATCH in 2.0 kiB at offset 0 bytes:"));
+            assert!(hexdump.contains("Data MISMATCH in 1.5 kiB at offset 4.0 kiB"));
+            assert_eq!(hexdump.matches("^^").count(), 7);
+
+            // The hex dump of the next run is appended.
+            assert!(dt.verify(mk_file(serial, false), 0, nr_bytes).is_err());
+            let hexdump = std::fs::read_to_string(&hexdump_path).unwrap();
+            assert_eq!(hexdump.matches("^^").count(), 14);
+            dt.set_hexdump(None, 0);
+            assert!((dt.bit_error_rate() - (7.0 * 8.0) / (8192.0 * 8.0)).abs() < 1e-12);
+            let badblocks_path = tdir_path.join(format!("badblocks-{serial}.txt"));
+            dt.export_badblocks(&badblocks_path, 1024).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&badblocks_path).unwrap(),
+                "0\n1\n4\n"
+            );
+            let dmsetup_path = tdir_path.join(format!("dmsetup-{serial}.txt"));
+            dt.export_dmsetup_table(&dmsetup_path).unwrap();
+            let dev = mk_filepath(serial);
+            assert_eq!(
+                std::fs::read_to_string(&dmsetup_path).unwrap(),
+                format!("0 6 linear {0} 3\n6 6 linear {0} 10\n", dev.display())
+            );
+            dt.set_max_errors(Some(2));
+            match dt.verify(mk_file(serial, false), 0, nr_bytes) {
+                Ok(_) => panic!("Verify of modified data did not fail!"),
+                Err(e) => assert_eq!(
+                    e.to_string(),
+                    "Data MISMATCH in 2 bad sectors (1 bad ranges, 3 bytes affected)!"
+                ),
+            }
+            dt.set_max_errors(None);
+            serial += 1;
+        }
+
         // Check verify with seek.
         {
             let nr_bytes = (base_size * chunk_factor * nr_threads * 10) as u64;
@@ -789,6 +2959,811 @@ mod tests {
             GeneratorCrc::DEFAULT_CHUNK_FACTOR,
         );
     }
+
+    #[test]
+    fn test_xoshiro() {
+        run_test(
+            DtStreamType::Xoshiro,
+            GeneratorXoshiro::BASE_SIZE,
+            GeneratorXoshiro::DEFAULT_CHUNK_FACTOR,
+        );
+    }
+
+    #[test]
+    fn test_tagged() {
+        run_test(
+            DtStreamType::Tagged,
+            GeneratorTagged::BASE_SIZE,
+            GeneratorTagged::DEFAULT_CHUNK_FACTOR,
+        );
+    }
+
+    #[test]
+    fn test_pattern() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("pattern.img");
+        let chunk_size = GeneratorPattern::BASE_SIZE * GeneratorPattern::DEFAULT_CHUNK_FACTOR;
+        let nr_bytes = (chunk_size * 3 + 1024) as u64;
+        let pattern = DtPattern::from_hex("0A0B0C").unwrap();
+
+        for layout in [DtStreamLayout::Thread, DtStreamLayout::Chunk] {
+            let mk_dt = |pattern, nr_threads| {
+                let mut dt = new_disktest(DtStreamType::Pattern(pattern), 0, nr_threads);
+                dt.set_stream_layout(layout);
+                dt
+            };
+
+            let _ = std::fs::remove_file(&path);
+            assert_eq!(
+                mk_dt(pattern, 2)
+                    .write(open_image(&path), 0, nr_bytes)
+                    .unwrap(),
+                nr_bytes
+            );
+
+            // The pattern continues over the chunk boundaries.
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(data.len() as u64, nr_bytes);
+            for (i, x) in data.iter().enumerate() {
+                assert_eq!(*x, [0x0A, 0x0B, 0x0C][i % 3]);
+            }
+
+            // The pattern does not depend on the seed and the number of threads.
+            assert_eq!(
+                mk_dt(pattern, 3)
+                    .verify(open_image(&path), 0, u64::MAX)
+                    .unwrap(),
+                nr_bytes
+            );
+            assert!(
+                mk_dt(DtPattern::Byte(0x0A), 2)
+                    .verify(open_image(&path), 0, u64::MAX)
+                    .is_err()
+            );
+        }
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_direct_io() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("direct.img");
+        let mk_file = || {
+            let mut file = open_image(&path);
+            file.set_direct_io(true);
+            file
+        };
+        let mk_dt = || new_disktest(DtStreamType::Crc, 0, 2);
+        // The tail is not aligned to the sector size.
+        let chunk_size = GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR;
+        let nr_bytes = (chunk_size * 2 + 1000) as u64;
+
+        assert_eq!(mk_dt().write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), nr_bytes);
+        assert_eq!(mk_dt().verify(mk_file(), 0, u64::MAX).unwrap(), nr_bytes);
+
+        // Corruption in the aligned part and in the tail is detected.
+        for offset in [4096, nr_bytes - 1] {
+            let mut f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut buf = [0_u8; 1];
+            f.seek(SeekFrom::Start(offset)).unwrap();
+            f.read_exact(&mut buf).unwrap();
+            buf[0] ^= 1;
+            f.seek(SeekFrom::Start(offset)).unwrap();
+            std::io::Write::write_all(&mut f, &buf).unwrap();
+            drop(f);
+            assert!(mk_dt().verify(mk_file(), 0, u64::MAX).is_err());
+            assert_eq!(mk_dt().write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        }
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("chunksize.img");
+        let mk_file = || open_image(&path);
+        let mk_dt = |chunk_size| {
+            let mut dt = Disktest::new(
+                DtStreamType::Crc,
+                &[1, 2, 3],
+                0,
+                false,
+                2,
+                DisktestQuiet::NoWarn,
+                None,
+            );
+            dt.set_stream_layout(DtStreamLayout::Chunk);
+            dt.set_chunk_size(chunk_size);
+            dt
+        };
+        let nr_bytes = 1024 * 1024;
+
+        // The chunk size is rounded to a multiple of the base size and the sector size.
+        let mut dt = mk_dt(Some(100_000));
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(dt.active_chunk_size, 100_352);
+        let mut dt = mk_dt(Some(1));
+        assert!(dt.verify(mk_file(), 0, 4096).is_err());
+        assert_eq!(dt.active_chunk_size, GeneratorCrc::BASE_SIZE as u64);
+
+        // The data depends on the chunk size.
+        assert_eq!(
+            mk_dt(Some(100_352)).verify(mk_file(), 0, nr_bytes).unwrap(),
+            nr_bytes
+        );
+        assert!(
+            mk_dt(Some(128 * 1024))
+                .verify(mk_file(), 0, nr_bytes)
+                .is_err()
+        );
+        assert!(mk_dt(None).verify(mk_file(), 0, nr_bytes).is_err());
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_queued_io() {
+        let tdir = tempdir().unwrap();
+        let ref_path = tdir.path().join("sync.img");
+        let path = tdir.path().join("queued.img");
+        let mk_file = |path: &Path, direct, queue_depth| {
+            let mut file = open_image(path);
+            file.set_direct_io(direct);
+            file.set_queue_depth(queue_depth);
+            file
+        };
+        let mk_dt = || new_disktest(DtStreamType::Crc, 0, 2);
+        // More chunks than the queue depth and a tail that is not aligned to the sector size.
+        let chunk_size = GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR;
+        let nr_bytes = (chunk_size * 5 + 1000) as u64;
+
+        let reference = mk_dt().write(mk_file(&ref_path, false, 1), 0, nr_bytes);
+        assert_eq!(reference.unwrap(), nr_bytes);
+        for direct in [false, true] {
+            let res = mk_dt().write(mk_file(&path, direct, 4), 0, nr_bytes);
+            assert_eq!(res.unwrap(), nr_bytes);
+            assert_eq!(
+                std::fs::read(&path).unwrap(),
+                std::fs::read(&ref_path).unwrap()
+            );
+            let res = mk_dt().verify(mk_file(&path, direct, 4), 0, u64::MAX);
+            assert_eq!(res.unwrap(), nr_bytes);
+            let res = mk_dt().verify(mk_file(&path, direct, 3), chunk_size as u64, 5000);
+            assert_eq!(res.unwrap(), 5000);
+
+            // Corruption in a queued chunk and in the tail is detected.
+            for offset in [chunk_size as u64 * 3 + 10, nr_bytes - 1] {
+                let mut f = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .unwrap();
+                let mut buf = [0_u8; 1];
+                f.seek(SeekFrom::Start(offset)).unwrap();
+                f.read_exact(&mut buf).unwrap();
+                buf[0] ^= 1;
+                f.seek(SeekFrom::Start(offset)).unwrap();
+                std::io::Write::write_all(&mut f, &buf).unwrap();
+                drop(f);
+                assert!(
+                    mk_dt()
+                        .verify(mk_file(&path, direct, 4), 0, u64::MAX)
+                        .is_err()
+                );
+                let mut dt = mk_dt();
+                dt.set_max_errors(Some(100));
+                assert!(dt.verify(mk_file(&path, direct, 4), 0, u64::MAX).is_err());
+                assert_eq!(dt.bad_ranges().len(), 1);
+                assert_eq!(dt.bad_ranges()[0].offset, offset - offset % 512);
+                assert_eq!(dt.verified_bytes, nr_bytes);
+                let res = mk_dt().write(mk_file(&path, direct, 4), 0, nr_bytes);
+                assert_eq!(res.unwrap(), nr_bytes);
+            }
+        }
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_tagged_sectors() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("tagged.img");
+        let mk_dt = |round_id| new_disktest(DtStreamType::Tagged, round_id, 2);
+
+        let nr_bytes = 16 * 1024;
+        let mut dt = mk_dt(1);
+        assert_eq!(dt.write(open_image(&path), 0, nr_bytes).unwrap(), nr_bytes);
+
+        // Write round 0 data to another file.
+        let other_path = tdir.path().join("tagged-round0.img");
+        let other_file = DisktestFile::open(&other_path, false, true).unwrap();
+        mk_dt(0).write(other_file, 0, nr_bytes).unwrap();
+        let other = std::fs::read(&other_path).unwrap();
+
+        // Alias the sector at 8 kiB to 512, wipe the sector at 2 kiB
+        // and put the sector of round 0 to 4 kiB.
+        {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut sector = vec![0; 512];
+            file.seek(SeekFrom::Start(8192)).unwrap();
+            file.read_exact(&mut sector).unwrap();
+            file.seek(SeekFrom::Start(512)).unwrap();
+            file.write_all(&sector).unwrap();
+            file.seek(SeekFrom::Start(2048)).unwrap();
+            file.write_all(&[0; 512]).unwrap();
+            file.seek(SeekFrom::Start(4096)).unwrap();
+            file.write_all(&other[4096..4608]).unwrap();
+        }
+
+        dt.set_max_errors(Some(Disktest::UNLIMITED));
+        assert!(dt.verify(open_image(&path), 0, nr_bytes).is_err());
+        let ranges = dt.bad_ranges();
+        assert_eq!(ranges.len(), 3);
+
+        assert_eq!(ranges[0].offset, 512);
+        let tag = ranges[0].tag.unwrap();
+        assert_eq!(tag.offset, 8192);
+        assert_eq!(tag.round_id, 1);
+        assert!(tag.checksum_ok);
+
+        assert_eq!(ranges[1].offset, 2048);
+        assert_eq!(ranges[1].tag, None);
+
+        assert_eq!(ranges[2].offset, 4096);
+        let tag = ranges[2].tag.unwrap();
+        assert_eq!(tag.offset, 4096);
+        assert_eq!(tag.round_id, 0);
+        assert!(tag.checksum_ok);
+        assert_eq!(
+            dt.describe_sector(Some(&tag)),
+            "contains data written for offset 4.0 kiB (4.1 kB, 4096 bytes), \
+             round 0 (expected round 1)"
+        );
+    }
+
+    #[test]
+    fn test_analyze() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("analyze.img");
+        let mk_dt = |round_id| new_disktest(DtStreamType::ChaCha8, round_id, 2);
+
+        let mib = 1024 * 1024;
+        let nr_bytes = 7 * mib;
+        let mut dt = mk_dt(0);
+        assert_eq!(dt.write(open_image(&path), 0, nr_bytes).unwrap(), nr_bytes);
+
+        // Put foreign data into some sectors.
+        {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut read_sector = |offset| {
+                let mut sector = vec![0; 512];
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.read_exact(&mut sector).unwrap();
+                sector
+            };
+            let aliased = read_sector(5 * mib + 512);
+            let inverted: Vec<u8> = read_sector(6 * mib).iter().map(|x| !x).collect();
+            let unrelated: Vec<u8> = (0..=255_u8).cycle().step_by(7).take(512).collect();
+            for (offset, data) in [
+                (1024, aliased),
+                (2048, vec![0; 512]),
+                (3072, vec![0xFF; 512]),
+                (8192, unrelated),
+                (10240, inverted),
+            ] {
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.write_all(&data).unwrap();
+            }
+        }
+
+        let stream = |offset, round_id, inverted| DisktestBlockOrigin::Stream {
+            offset,
+            round_id,
+            inverted,
+        };
+        assert_eq!(
+            dt.analyze(open_image(&path), 1024, 0, Disktest::UNLIMITED)
+                .unwrap(),
+            stream(5 * mib + 512, 0, false)
+        );
+        assert_eq!(
+            dt.analyze(open_image(&path), 4096, 1024, Disktest::UNLIMITED)
+                .unwrap(),
+            stream(4096, 0, false)
+        );
+        assert_eq!(
+            dt.analyze(open_image(&path), 10240, 0, Disktest::UNLIMITED)
+                .unwrap(),
+            stream(6 * mib, 0, true)
+        );
+        assert_eq!(
+            dt.analyze(open_image(&path), 2048, 0, Disktest::UNLIMITED)
+                .unwrap(),
+            DisktestBlockOrigin::Zero
+        );
+        assert_eq!(
+            dt.analyze(open_image(&path), 3072, 0, Disktest::UNLIMITED)
+                .unwrap(),
+            DisktestBlockOrigin::Ones
+        );
+        assert_eq!(
+            dt.analyze(open_image(&path), 8192, 0, Disktest::UNLIMITED)
+                .unwrap(),
+            DisktestBlockOrigin::Unrelated
+        );
+
+        // The search range is limited.
+        assert_eq!(
+            dt.analyze(open_image(&path), 1024, 0, 5 * mib).unwrap(),
+            DisktestBlockOrigin::Unrelated
+        );
+
+        // Data of another round.
+        assert_eq!(
+            mk_dt(1)
+                .analyze(open_image(&path), 1024, 0, Disktest::UNLIMITED)
+                .unwrap(),
+            DisktestBlockOrigin::Unrelated
+        );
+    }
+
+    #[test]
+    fn test_probe() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("probe.img");
+        let size = 1024 * 1024 + 1000;
+
+        let mk_file = || {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .unwrap();
+            file.set_len(size).unwrap();
+            open_image(&path)
+        };
+
+        let mut dt = new_disktest(DtStreamType::ChaCha20, 0, 1);
+
+        // Probe the whole file.
+        let res = dt.probe(mk_file(), Disktest::UNLIMITED).unwrap();
+        assert!(res.is_good());
+        assert_eq!(res.advertised_size, size);
+        assert_eq!(res.block_size, 4096);
+        assert_eq!(res.highest_good, Some(1024 * 1024 - 4096));
+        assert_eq!(res.usable_size(), 1024 * 1024);
+        assert!(res.aliases.is_empty());
+        assert_eq!(res.wrap_size, None);
+
+        // Probe a smaller advertised size.
+        let res = dt.probe(mk_file(), 64 * 1024).unwrap();
+        assert!(res.is_good());
+        assert_eq!(res.highest_good, Some(60 * 1024));
+
+        // Too small.
+        assert!(dt.probe(mk_file(), 4095).is_err());
+    }
+
+    #[test]
+    fn test_chunk_layout() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("layout.img");
+        let chunk_len = GeneratorTagged::BASE_SIZE * GeneratorTagged::DEFAULT_CHUNK_FACTOR;
+        let chunk_size = chunk_len as u64;
+        let nr_bytes = chunk_size * 5 + 4096;
+
+        let mk_dt = |nr_threads, layout| {
+            let mut dt = new_disktest(DtStreamType::Tagged, 0, nr_threads);
+            dt.set_stream_layout(layout);
+            dt
+        };
+
+        // Write with 4 threads and verify with a different number of threads.
+        let mut dt = mk_dt(4, DtStreamLayout::Chunk);
+        assert_eq!(dt.write(open_image(&path), 0, nr_bytes).unwrap(), nr_bytes);
+        for nr_threads in [1, 3] {
+            let mut dt = mk_dt(nr_threads, DtStreamLayout::Chunk);
+            assert_eq!(dt.verify(open_image(&path), 0, u64::MAX).unwrap(), nr_bytes);
+            let mut dt = mk_dt(nr_threads, DtStreamLayout::Chunk);
+            assert_eq!(
+                dt.verify(open_image(&path), chunk_size * 3, u64::MAX)
+                    .unwrap(),
+                nr_bytes - chunk_size * 3
+            );
+        }
+
+        // The sector headers contain the absolute offsets.
+        let data = std::fs::read(&path).unwrap();
+        for offset in [0, 512, chunk_len, chunk_len * 5 + 512] {
+            let tag = parse_sector_tag(&data[offset..offset + 512]).unwrap();
+            assert_eq!(tag.offset, offset as u64);
+        }
+
+        // The thread layout does not match.
+        let mut dt = mk_dt(4, DtStreamLayout::Thread);
+        assert!(dt.verify(open_image(&path), 0, u64::MAX).is_err());
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_resume() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("resume.img");
+        let state_path = tdir.path().join("resume.state");
+        let chunk_size = (GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR) as u64;
+        let nr_bytes = chunk_size * 4;
+
+        let mk_dt = |seed: &[u8], abort: bool| {
+            let mut dt = Disktest::new(
+                DtStreamType::Crc,
+                seed,
+                0,
+                false,
+                2,
+                DisktestQuiet::Normal,
+                Some(Arc::new(AtomicBool::new(abort))),
+            );
+            dt.set_state_file(Some(state_path.clone()));
+            dt
+        };
+
+        // Abort writing after the first chunk.
+        let mut dt = mk_dt(&[1, 2, 3], true);
+        assert_eq!(
+            dt.write(open_image(&path), 0, nr_bytes)
+                .unwrap_err()
+                .to_string(),
+            "Aborted by signal!"
+        );
+        let state = DisktestState::load(&state_path).unwrap();
+        assert_eq!(state.phase, DisktestPhase::Write);
+        assert_eq!(state.round_id, 0);
+        assert_eq!(state.offset, chunk_size);
+        assert_eq!(state.bytes_left(), nr_bytes - chunk_size);
+        assert_eq!(state.algorithm, DtStreamType::Crc);
+        assert_eq!(state.threads, 2);
+        assert_eq!(state.chunk_size, chunk_size);
+        assert!(mk_dt(&[1, 2, 4], false).check_state(&state).is_err());
+
+        // The chunk size must be set to the recorded one.
+        let mut dt = mk_dt(&[1, 2, 3], false);
+        assert!(dt.check_state(&state).is_err());
+        dt.set_chunk_size(Some(chunk_size / 2));
+        assert!(dt.check_state(&state).is_err());
+
+        // Resume writing.
+        dt.set_chunk_size(Some(state.chunk_size));
+        dt.check_state(&state).unwrap();
+        assert_eq!(
+            dt.write(open_image(&path), state.offset, state.bytes_left())
+                .unwrap(),
+            nr_bytes - chunk_size
+        );
+
+        // Abort verifying after the first chunk and resume it.
+        let mut dt = mk_dt(&[1, 2, 3], true);
+        assert!(dt.verify(open_image(&path), 0, nr_bytes).is_err());
+        let state = DisktestState::load(&state_path).unwrap();
+        assert_eq!(state.phase, DisktestPhase::Verify);
+        assert_eq!(state.offset, chunk_size);
+        let mut dt = mk_dt(&[1, 2, 3], false);
+        assert_eq!(
+            dt.verify(open_image(&path), state.offset, state.bytes_left())
+                .unwrap(),
+            nr_bytes - chunk_size
+        );
+
+        // The whole device has been written correctly.
+        let mut dt = mk_dt(&[1, 2, 3], false);
+        assert_eq!(dt.verify(open_image(&path), 0, u64::MAX).unwrap(), nr_bytes);
+
+        // The state records the rounded chunk size.
+        let mut dt = mk_dt(&[1, 2, 3], true);
+        dt.set_chunk_size(Some(100_000));
+        assert!(dt.write(open_image(&path), 0, nr_bytes).is_err());
+        let state = DisktestState::load(&state_path).unwrap();
+        assert_eq!(state.chunk_size, 100_352);
+        assert_eq!(state.offset, 100_352);
+        let mut dt = mk_dt(&[1, 2, 3], false);
+        dt.set_chunk_size(Some(state.chunk_size));
+        dt.check_state(&state).unwrap();
+        assert_eq!(
+            dt.write(open_image(&path), state.offset, state.bytes_left())
+                .unwrap(),
+            nr_bytes - 100_352
+        );
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_sampled() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("sampled.img");
+        let chunk_size = (GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR) as u64;
+        let nr_bytes = chunk_size * 10 + chunk_size / 2;
+
+        for layout in [DtStreamLayout::Thread, DtStreamLayout::Chunk] {
+            let mk_dt = || {
+                let mut dt = new_disktest(DtStreamType::Crc, 0, 2);
+                dt.set_stream_layout(layout);
+                dt
+            };
+
+            // Sampled verify of a fully written device.
+            let _ = std::fs::remove_file(&path);
+            mk_dt().write(open_image(&path), 0, nr_bytes).unwrap();
+            let cov = mk_dt()
+                .verify_sampled(
+                    open_image(&path),
+                    0,
+                    u64::MAX,
+                    DisktestSampling::EveryNth(3),
+                )
+                .unwrap();
+            assert_eq!(cov.begin, 0);
+            assert_eq!(cov.end, nr_bytes);
+            assert_eq!(cov.nr_chunks, 11);
+            assert_eq!(cov.nr_sampled, 4);
+            assert_eq!(cov.bytes_sampled, chunk_size * 4);
+            assert_eq!(cov.max_gap, chunk_size * 2);
+            // The partial last chunk.
+            let cov = mk_dt()
+                .verify_sampled(
+                    open_image(&path),
+                    0,
+                    u64::MAX,
+                    DisktestSampling::Percent(50.0),
+                )
+                .unwrap();
+            assert_eq!(cov.nr_sampled, 6);
+            assert_eq!(cov.bytes_sampled, chunk_size * 5 + chunk_size / 2);
+            let cov = mk_dt()
+                .verify_sampled(
+                    open_image(&path),
+                    chunk_size * 2,
+                    chunk_size * 4,
+                    DisktestSampling::Random(50.0),
+                )
+                .unwrap();
+            assert_eq!(cov.begin, chunk_size * 2);
+            assert_eq!(cov.nr_chunks, 4);
+
+            // Sampled write and verify.
+            let _ = std::fs::remove_file(&path);
+            let sampling = DisktestSampling::Random(30.0);
+            let cov = mk_dt()
+                .write_sampled(open_image(&path), 0, nr_bytes, sampling)
+                .unwrap();
+            assert!(cov.nr_sampled > 0 && cov.nr_sampled < 11);
+            assert_eq!(
+                mk_dt()
+                    .verify_sampled(open_image(&path), 0, nr_bytes, sampling)
+                    .unwrap(),
+                cov
+            );
+            // The gaps have not been written.
+            assert!(mk_dt().verify(open_image(&path), 0, nr_bytes).is_err());
+
+            // Corrupt the chunk 3.
+            let _ = std::fs::remove_file(&path);
+            mk_dt().write(open_image(&path), 0, nr_bytes).unwrap();
+            {
+                let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+                file.seek(SeekFrom::Start(chunk_size * 3 + 1000)).unwrap();
+                file.write_all(&[0x55; 1]).unwrap();
+            }
+            assert!(
+                mk_dt()
+                    .verify_sampled(
+                        open_image(&path),
+                        0,
+                        u64::MAX,
+                        DisktestSampling::EveryNth(2)
+                    )
+                    .is_ok()
+            );
+            let mut dt = mk_dt();
+            assert!(
+                dt.verify_sampled(
+                    open_image(&path),
+                    0,
+                    u64::MAX,
+                    DisktestSampling::EveryNth(3)
+                )
+                .unwrap_err()
+                .to_string()
+                .starts_with("Data MISMATCH in 1 bad sectors")
+            );
+            let mut dt = mk_dt();
+            dt.set_max_errors(Some(Disktest::UNLIMITED));
+            assert!(
+                dt.verify_sampled(
+                    open_image(&path),
+                    0,
+                    u64::MAX,
+                    DisktestSampling::EveryNth(1)
+                )
+                .is_err()
+            );
+            assert_eq!(dt.bad_ranges().len(), 1);
+            assert_eq!(dt.bad_ranges()[0].offset, chunk_size * 3 + 512);
+            assert_eq!(dt.verified_bytes, nr_bytes);
+        }
+
+        // Invalid sampling.
+        let mut dt = new_disktest(DtStreamType::Crc, 0, 1);
+        assert!(
+            dt.verify_sampled(
+                open_image(&path),
+                0,
+                u64::MAX,
+                DisktestSampling::EveryNth(0)
+            )
+            .is_err()
+        );
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_read_error() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("readerror.img");
+        let chunk_size = (GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR) as u64;
+        let nr_bytes = chunk_size * 3 + chunk_size / 2;
+        let mk_dt = || new_disktest(DtStreamType::Crc, 0, 2);
+        let mk_file = |queue_depth| {
+            let mut file = open_image(&path);
+            file.set_queue_depth(queue_depth);
+            // Two sectors in the chunk 1 and one sector in the partial last chunk.
+            file.read_errors.ranges = vec![
+                chunk_size + 1024..chunk_size + 2024,
+                chunk_size * 3 + 512..chunk_size * 3 + 513,
+            ];
+            file
+        };
+        let unreadable = [(chunk_size + 1024, 1024), (chunk_size * 3 + 512, 512)];
+
+        mk_dt().write(open_image(&path), 0, nr_bytes).unwrap();
+        // Corrupt the chunk 2 after the first read error.
+        {
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(chunk_size * 2 + 5000)).unwrap();
+            file.write_all(&[0x55; 1]).unwrap();
+        }
+
+        // Without an error limit the read error fails the verification.
+        assert!(
+            mk_dt()
+                .verify(mk_file(1), 0, u64::MAX)
+                .unwrap_err()
+                .to_string()
+                .starts_with(&format!(
+                    "Read error at {}",
+                    prettybytes(chunk_size, true, true, true)
+                ))
+        );
+
+        // The failed queued reads are re-read sector by sector
+        // and the data after them is still compared.
+        // Both with the I/O thread and with io_uring.
+        for queue_depth in [1, 4] {
+            let mut dt = mk_dt();
+            dt.set_max_errors(Some(Disktest::UNLIMITED));
+            assert!(dt.verify(mk_file(queue_depth), 0, u64::MAX).is_err());
+            let ranges: Vec<_> = dt
+                .unreadable_ranges()
+                .iter()
+                .map(|r| (r.offset, r.length))
+                .collect();
+            assert_eq!(ranges, unreadable);
+            assert_eq!(dt.bad_ranges().len(), 1);
+            assert_eq!(dt.bad_ranges()[0].offset, chunk_size * 2 + 4608);
+            assert_eq!(dt.bad_ranges()[0].length, 512);
+            assert_eq!(dt.verified_bytes, nr_bytes);
+        }
+
+        // Sampled verify reads every chunk synchronously with verify_read_at().
+        let mut dt = mk_dt();
+        dt.set_max_errors(Some(Disktest::UNLIMITED));
+        assert!(
+            dt.verify_sampled(mk_file(1), 0, u64::MAX, DisktestSampling::EveryNth(1))
+                .is_err()
+        );
+        let ranges: Vec<_> = dt
+            .unreadable_ranges()
+            .iter()
+            .map(|r| (r.offset, r.length))
+            .collect();
+        assert_eq!(ranges, unreadable);
+        assert_eq!(dt.bad_ranges().len(), 1);
+        assert_eq!(dt.bad_ranges()[0].offset, chunk_size * 2 + 4608);
+        assert_eq!(dt.verified_bytes, nr_bytes);
+
+        // The rest of the chunk after the unreadable sectors matches.
+        let mut dt = mk_dt();
+        dt.set_max_errors(Some(Disktest::UNLIMITED));
+        assert!(dt.verify(mk_file(1), chunk_size, chunk_size).is_err());
+        let ranges: Vec<_> = dt
+            .unreadable_ranges()
+            .iter()
+            .map(|r| (r.offset, r.length))
+            .collect();
+        assert_eq!(ranges, unreadable[..1]);
+        assert!(dt.bad_ranges().is_empty());
+        assert_eq!(dt.verified_bytes, chunk_size);
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_write_verify() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("write_verify.img");
+        let chunk_size = (GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR) as u64;
+        let nr_bytes = chunk_size * 5 + chunk_size / 2;
+
+        let mk_dt = || new_disktest(DtStreamType::Crc, 0, 2);
+
+        // Verify after every two chunks.
+        let mut dt = mk_dt();
+        assert_eq!(
+            dt.write_verify(open_image(&path), 0, nr_bytes, chunk_size * 2)
+                .unwrap(),
+            nr_bytes
+        );
+        assert_eq!(dt.verified_bytes, nr_bytes);
+        assert_eq!(
+            mk_dt().verify(open_image(&path), 0, u64::MAX).unwrap(),
+            nr_bytes
+        );
+
+        // The lag is rounded up to whole chunks. Start at an offset.
+        let mut dt = mk_dt();
+        assert_eq!(
+            dt.write_verify(open_image(&path), chunk_size, chunk_size * 3, 1)
+                .unwrap(),
+            chunk_size * 3
+        );
+        assert_eq!(
+            mk_dt().verify(open_image(&path), 0, u64::MAX).unwrap(),
+            nr_bytes
+        );
+
+        // Verify once at the end with another seed.
+        let mut dt = Disktest::new(
+            DtStreamType::Crc,
+            &[1, 2, 4],
+            0,
+            false,
+            2,
+            DisktestQuiet::Normal,
+            None,
+        );
+        dt.write_verify(open_image(&path), 0, nr_bytes, u64::MAX)
+            .unwrap();
+        assert!(mk_dt().verify(open_image(&path), 0, u64::MAX).is_err());
+
+        tdir.close().unwrap();
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
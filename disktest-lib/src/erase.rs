@@ -0,0 +1,607 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::generator::{DtPattern, DtPatternBytes};
+use crate::kdf::seed_fingerprint;
+use crate::stream_aggregator::DtStreamType;
+use anyhow as ah;
+use chrono::prelude::*;
+use ring::rand::SystemRandom;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair as _, UnparsedPublicKey};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::Path;
+
+/// Magic first line of the erase report.
+const REPORT_MAGIC: &str = "disktest-erase-report-v1";
+/// Key of the public key line.
+const PUBLIC_KEY_KEY: &str = "public_key=";
+/// Key of the signature line. This is always the last line.
+const SIGNATURE_KEY: &str = "signature=";
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Identity of a device or file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisktestDeviceIdentity {
+    /// The path the device has been opened with.
+    pub path: String,
+    /// The size of the device, in bytes.
+    pub size: u64,
+    /// The physical sector size of the device, if known.
+    pub sector_size: Option<u32>,
+    /// The model name reported by the device, if known.
+    pub model: Option<String>,
+    /// The serial number or WWID reported by the device, if known.
+    pub serial: Option<String>,
+}
+
+impl DisktestDeviceIdentity {
+    /// Collect the identity of the device at `path`.
+    /// The model and serial number are read from sysfs, if available.
+    pub(crate) fn new(path: &Path, size: u64, sector_size: Option<u32>) -> DisktestDeviceIdentity {
+        let mut identity = DisktestDeviceIdentity {
+            path: path.display().to_string(),
+            size,
+            sector_size,
+            ..Default::default()
+        };
+        #[cfg(target_os = "linux")]
+        identity.read_sysfs(path);
+        identity
+    }
+
+    #[cfg(target_os = "linux")]
+    fn read_sysfs(&mut self, path: &Path) {
+        let Ok(path) = path.canonicalize() else {
+            return;
+        };
+        let Some(name) = path.file_name() else {
+            return;
+        };
+        let Ok(mut block) = Path::new("/sys/class/block").join(name).canonicalize() else {
+            return;
+        };
+        // A partition inherits the identity of its disk.
+        if block.join("partition").exists() {
+            block.pop();
+        }
+        let read = |attr: &str| {
+            std::fs::read_to_string(block.join("device").join(attr))
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        self.model = read("model");
+        self.serial = read("serial").or_else(|| read("wwid"));
+    }
+}
+
+/// One pass of a wipe method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisktestWipePass {
+    /// The data to write.
+    pub algorithm: DtStreamType,
+    /// Write the bitwise complement of the data.
+    pub invert: bool,
+    /// Verify the pass after writing it.
+    pub verify: bool,
+}
+
+impl DisktestWipePass {
+    fn pattern(bytes: &[u8]) -> DisktestWipePass {
+        let pattern = if let [byte] = bytes {
+            DtPattern::Byte(*byte)
+        } else {
+            DtPattern::Bytes(DtPatternBytes::new(bytes).expect("Invalid wipe pattern"))
+        };
+        DisktestWipePass {
+            algorithm: DtStreamType::Pattern(pattern),
+            invert: false,
+            verify: false,
+        }
+    }
+
+    fn complement(self) -> DisktestWipePass {
+        DisktestWipePass {
+            invert: true,
+            ..self
+        }
+    }
+
+    fn random() -> DisktestWipePass {
+        DisktestWipePass {
+            algorithm: DtStreamType::ChaCha20,
+            invert: false,
+            verify: false,
+        }
+    }
+
+    fn verified(self) -> DisktestWipePass {
+        DisktestWipePass {
+            verify: true,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for DisktestWipePass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.algorithm, self.invert) {
+            (DtStreamType::Pattern(_), false) => write!(f, "{}", self.algorithm),
+            (DtStreamType::Pattern(_), true) => write!(f, "complement of {}", self.algorithm),
+            (_, false) => write!(f, "random ({})", self.algorithm),
+            (_, true) => write!(f, "random (inverted {})", self.algorithm),
+        }
+    }
+}
+
+/// Named wipe method that expands into a sequence of passes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisktestWipeMethod {
+    /// NIST SP 800-88 Clear: One verified pass of zeros.
+    NistClear,
+    /// `DoD` 5220.22-M: Zeros, their complement and random data.
+    /// The last pass is verified.
+    Dod,
+    /// `DoD` 5220.22-M ECE: The `DoD` 5220.22-M passes, random data
+    /// and the `DoD` 5220.22-M passes again. The last pass is verified.
+    DodEce,
+    /// Gutmann-style: 4 random passes, the 27 patterns of Peter Gutmann's method
+    /// and 4 random passes. The last pass is verified.
+    Gutmann,
+}
+
+impl DisktestWipeMethod {
+    /// Get the passes of the method, in the order they are run.
+    pub fn passes(&self) -> Vec<DisktestWipePass> {
+        let dod = || {
+            let zeros = DisktestWipePass::pattern(&[0x00]);
+            [zeros, zeros.complement(), DisktestWipePass::random()]
+        };
+        let mut passes = match self {
+            DisktestWipeMethod::NistClear => vec![DisktestWipePass::pattern(&[0x00])],
+            DisktestWipeMethod::Dod => dod().to_vec(),
+            DisktestWipeMethod::DodEce => {
+                let mut passes = dod().to_vec();
+                passes.push(DisktestWipePass::random());
+                passes.extend(dod());
+                passes
+            }
+            DisktestWipeMethod::Gutmann => {
+                const PATTERNS: [&[u8]; 27] = [
+                    &[0x55],
+                    &[0xAA],
+                    &[0x92, 0x49, 0x24],
+                    &[0x49, 0x24, 0x92],
+                    &[0x24, 0x92, 0x49],
+                    &[0x00],
+                    &[0x11],
+                    &[0x22],
+                    &[0x33],
+                    &[0x44],
+                    &[0x55],
+                    &[0x66],
+                    &[0x77],
+                    &[0x88],
+                    &[0x99],
+                    &[0xAA],
+                    &[0xBB],
+                    &[0xCC],
+                    &[0xDD],
+                    &[0xEE],
+                    &[0xFF],
+                    &[0x92, 0x49, 0x24],
+                    &[0x49, 0x24, 0x92],
+                    &[0x24, 0x92, 0x49],
+                    &[0x6D, 0xB6, 0xDB],
+                    &[0xB6, 0xDB, 0x6D],
+                    &[0xDB, 0x6D, 0xB6],
+                ];
+                let mut passes = vec![DisktestWipePass::random(); 4];
+                passes.extend(PATTERNS.iter().map(|p| DisktestWipePass::pattern(p)));
+                passes.extend([DisktestWipePass::random(); 4]);
+                passes
+            }
+        };
+        if let Some(last) = passes.last_mut() {
+            *last = last.verified();
+        }
+        passes
+    }
+}
+
+impl std::fmt::Display for DisktestWipeMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisktestWipeMethod::NistClear => write!(f, "NIST 800-88 Clear"),
+            DisktestWipeMethod::Dod => write!(f, "DoD 5220.22-M"),
+            DisktestWipeMethod::DodEce => write!(f, "DoD 5220.22-M ECE"),
+            DisktestWipeMethod::Gutmann => write!(f, "Gutmann"),
+        }
+    }
+}
+
+/// Ed25519 key to sign erase reports with.
+pub struct DisktestSigningKey {
+    key_pair: Ed25519KeyPair,
+}
+
+impl DisktestSigningKey {
+    /// Generate a new key and store it as PKCS#8 document in a new file.
+    /// An existing file is never overwritten.
+    pub fn generate(path: &Path) -> ah::Result<DisktestSigningKey> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| ah::format_err!("Failed to generate the signing key."))?;
+        let mut opts = std::fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+        opts.open(path)
+            .and_then(|mut file| file.write_all(pkcs8.as_ref()))
+            .map_err(|e| ah::format_err!("Failed to write signing key {}: {e}", path.display()))?;
+        Self::from_pkcs8(pkcs8.as_ref())
+    }
+
+    /// Load a key from a PKCS#8 (DER) file,
+    /// e.g. as generated by `openssl genpkey -algorithm ed25519 -outform DER`.
+    pub fn load(path: &Path) -> ah::Result<DisktestSigningKey> {
+        let pkcs8 = std::fs::read(path)
+            .map_err(|e| ah::format_err!("Failed to read signing key {}: {e}", path.display()))?;
+        Self::from_pkcs8(&pkcs8).map_err(|e| ah::format_err!("Signing key {}: {e}", path.display()))
+    }
+
+    /// Create the key from a PKCS#8 document.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> ah::Result<DisktestSigningKey> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|e| ah::format_err!("Invalid Ed25519 PKCS#8 key: {e}"))?;
+        Ok(DisktestSigningKey { key_pair })
+    }
+
+    /// Get the public key as hex string.
+    pub fn public_key_hex(&self) -> String {
+        to_hex(self.key_pair.public_key().as_ref())
+    }
+}
+
+/// One overwrite pass of an erase run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisktestErasePass {
+    /// The data that has been written.
+    pub algorithm: DtStreamType,
+    /// The bitwise complement of the data has been written.
+    pub invert: bool,
+    /// The number of bytes that have been written.
+    pub bytes: u64,
+    /// The number of bytes that have been verified after writing the pass, if any.
+    pub verified_bytes: Option<u64>,
+    /// The time the pass has been started.
+    pub start: DateTime<Utc>,
+    /// The time the pass has been finished.
+    pub end: DateTime<Utc>,
+    /// The error that stopped the pass, if any.
+    pub error: Option<String>,
+}
+
+/// Report of an erase run.
+///
+/// The report is a `key=value` text that is signed with an Ed25519 key.
+/// The public key and the signature are appended as the last two lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisktestEraseReport {
+    /// The erased device.
+    pub device: DisktestDeviceIdentity,
+    /// The name of the erase method.
+    pub method: String,
+    /// Fingerprint of the seed of the random passes. The seed itself is not stored.
+    pub seed_fingerprint: u64,
+    /// The overwrite passes, in the order they have been run.
+    pub passes: Vec<DisktestErasePass>,
+    /// The final verification of the last pass.
+    /// The verification is run over the full range of the last pass.
+    pub verification: Option<DisktestErasePass>,
+}
+
+impl DisktestEraseReport {
+    /// Create an empty report.
+    pub fn new(device: DisktestDeviceIdentity, method: &str, seed: &[u8]) -> DisktestEraseReport {
+        DisktestEraseReport {
+            device,
+            method: method.to_string(),
+            seed_fingerprint: seed_fingerprint(seed),
+            passes: vec![],
+            verification: None,
+        }
+    }
+
+    /// Check whether the erase has been successful.
+    /// All passes must have written the same number of bytes without error
+    /// and the verification must have read back all of it.
+    pub fn is_success(&self) -> bool {
+        let Some(last) = self.passes.last() else {
+            return false;
+        };
+        let Some(verification) = &self.verification else {
+            return false;
+        };
+        last.bytes > 0
+            && self.passes.iter().all(|pass| {
+                pass.error.is_none()
+                    && pass.bytes == last.bytes
+                    && pass.verified_bytes.is_none_or(|bytes| bytes == pass.bytes)
+            })
+            && verification.error.is_none()
+            && verification.algorithm == last.algorithm
+            && verification.invert == last.invert
+            && verification.bytes == last.bytes
+    }
+
+    /// Serialize the unsigned report into `key=value` lines.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{REPORT_MAGIC}");
+        let _ = writeln!(text, "tool=disktest {}", env!("CARGO_PKG_VERSION"));
+        let _ = writeln!(text, "device={}", self.device.path);
+        if let Some(model) = &self.device.model {
+            let _ = writeln!(text, "device_model={model}");
+        }
+        if let Some(serial) = &self.device.serial {
+            let _ = writeln!(text, "device_serial={serial}");
+        }
+        let _ = writeln!(text, "device_size={}", self.device.size);
+        if let Some(sector_size) = self.device.sector_size {
+            let _ = writeln!(text, "sector_size={sector_size}");
+        }
+        let _ = writeln!(text, "method={}", self.method);
+        let _ = writeln!(text, "seed_fingerprint={:016X}", self.seed_fingerprint);
+        let _ = writeln!(text, "passes={}", self.passes.len());
+        let write_pass = |text: &mut String, prefix: &str, pass: &DisktestErasePass| {
+            let _ = writeln!(text, "{prefix}.algorithm={}", pass.algorithm);
+            if pass.invert {
+                let _ = writeln!(text, "{prefix}.invert=1");
+            }
+            let _ = writeln!(text, "{prefix}.bytes={}", pass.bytes);
+            if let Some(verified_bytes) = pass.verified_bytes {
+                let _ = writeln!(text, "{prefix}.verified_bytes={verified_bytes}");
+            }
+            let _ = writeln!(text, "{prefix}.start={}", timestamp(&pass.start));
+            let _ = writeln!(text, "{prefix}.end={}", timestamp(&pass.end));
+            let result = match &pass.error {
+                None => "ok".to_string(),
+                Some(e) => format!("error: {}", e.replace('\n', " ")),
+            };
+            let _ = writeln!(text, "{prefix}.result={result}");
+        };
+        for (i, pass) in self.passes.iter().enumerate() {
+            write_pass(&mut text, &format!("pass.{}", i + 1), pass);
+        }
+        if let Some(verification) = &self.verification {
+            write_pass(&mut text, "verify", verification);
+        }
+        let result = if self.is_success() {
+            "erased"
+        } else {
+            "FAILED"
+        };
+        let _ = writeln!(text, "result={result}");
+        text
+    }
+
+    /// Serialize and sign the report.
+    pub fn sign(&self, key: &DisktestSigningKey) -> String {
+        let mut text = self.to_text();
+        let _ = writeln!(text, "{PUBLIC_KEY_KEY}{}", key.public_key_hex());
+        let signature = key.key_pair.sign(text.as_bytes());
+        let _ = writeln!(text, "{SIGNATURE_KEY}{}", to_hex(signature.as_ref()));
+        text
+    }
+
+    /// Sign the report and write it to a file.
+    pub fn save_signed(&self, path: &Path, key: &DisktestSigningKey) -> ah::Result<()> {
+        std::fs::write(path, self.sign(key))
+            .map_err(|e| ah::format_err!("Failed to write erase report {}: {e}", path.display()))
+    }
+
+    /// Check the signature of a signed report text.
+    /// If `public_key_hex` is given, then the report must have been signed with this key.
+    /// Otherwise, only the integrity of the report is checked against the included public key.
+    pub fn check_signed(text: &str, public_key_hex: Option<&str>) -> ah::Result<()> {
+        if !text.starts_with(REPORT_MAGIC) {
+            return Err(ah::format_err!("Not a disktest erase report."));
+        }
+        let Some(sig_pos) = text.rfind(&format!("\n{SIGNATURE_KEY}")) else {
+            return Err(ah::format_err!("The erase report is not signed."));
+        };
+        let (signed, signature) = text.split_at(sig_pos + 1);
+        let signature = signature[SIGNATURE_KEY.len()..].trim_end_matches('\n');
+        let signature = from_hex(signature)
+            .ok_or_else(|| ah::format_err!("Invalid erase report signature."))?;
+
+        let public_key = signed
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix(PUBLIC_KEY_KEY))
+            .ok_or_else(|| ah::format_err!("The erase report has no public key."))?;
+        if let Some(expected) = public_key_hex {
+            if !expected.trim().eq_ignore_ascii_case(public_key) {
+                return Err(ah::format_err!(
+                    "The erase report has been signed with another key: {public_key}"
+                ));
+            }
+        }
+        let public_key = from_hex(public_key)
+            .ok_or_else(|| ah::format_err!("Invalid erase report public key."))?;
+
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(signed.as_bytes(), &signature)
+            .map_err(|_| ah::format_err!("The erase report signature is invalid."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(algorithm: DtStreamType, bytes: u64) -> DisktestErasePass {
+        DisktestErasePass {
+            algorithm,
+            invert: false,
+            bytes,
+            verified_bytes: None,
+            start: Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+            end: Utc.with_ymd_and_hms(2026, 1, 2, 4, 5, 6).unwrap(),
+            error: None,
+        }
+    }
+
+    fn report() -> DisktestEraseReport {
+        let device = DisktestDeviceIdentity {
+            path: "/dev/sdx".to_string(),
+            size: 4096,
+            sector_size: Some(512),
+            model: Some("Foo".to_string()),
+            serial: None,
+        };
+        let mut report = DisktestEraseReport::new(device, "custom", b"seed");
+        let zero = DtStreamType::Pattern(DtPattern::Byte(0));
+        report.passes.push(pass(DtStreamType::ChaCha20, 4096));
+        report.passes.push(pass(zero, 4096));
+        report.verification = Some(pass(zero, 4096));
+        report
+    }
+
+    #[test]
+    fn test_success() {
+        let mut a = report();
+        assert!(a.is_success());
+        a.verification.as_mut().unwrap().bytes = 2048;
+        assert!(!a.is_success());
+        let mut a = report();
+        a.verification.as_mut().unwrap().error = Some("Data MISMATCH".to_string());
+        assert!(!a.is_success());
+        let mut a = report();
+        a.passes[0].bytes = 1024;
+        assert!(!a.is_success());
+        let mut a = report();
+        a.verification = None;
+        assert!(!a.is_success());
+        let mut a = report();
+        a.passes[0].verified_bytes = Some(4096);
+        assert!(a.is_success());
+        a.passes[0].verified_bytes = Some(0);
+        assert!(!a.is_success());
+        let mut a = report();
+        a.verification.as_mut().unwrap().invert = true;
+        assert!(!a.is_success());
+    }
+
+    #[test]
+    fn test_wipe_methods() {
+        let passes = DisktestWipeMethod::NistClear.passes();
+        assert_eq!(passes.len(), 1);
+        assert_eq!(
+            passes[0].algorithm,
+            DtStreamType::Pattern(DtPattern::Byte(0))
+        );
+        assert!(passes[0].verify);
+
+        let passes = DisktestWipeMethod::Dod.passes();
+        assert_eq!(passes.len(), 3);
+        assert_eq!(passes[0].to_string(), "pattern:00");
+        assert_eq!(passes[1].to_string(), "complement of pattern:00");
+        assert_eq!(passes[2].to_string(), "random (chacha20)");
+        assert!(!passes[0].verify && !passes[1].verify && passes[2].verify);
+
+        let passes = DisktestWipeMethod::DodEce.passes();
+        assert_eq!(passes.len(), 7);
+        assert_eq!(passes[3].algorithm, DtStreamType::ChaCha20);
+        assert!(passes[5].invert);
+        assert_eq!(passes.iter().filter(|p| p.verify).count(), 1);
+
+        let passes = DisktestWipeMethod::Gutmann.passes();
+        assert_eq!(passes.len(), 35);
+        assert_eq!(passes[4].to_string(), "pattern:55");
+        assert_eq!(passes[6].to_string(), "pattern:924924");
+        assert_eq!(passes[30].to_string(), "pattern:db6db6");
+        assert_eq!(passes[34].algorithm, DtStreamType::ChaCha20);
+        assert!(passes[34].verify);
+    }
+
+    #[test]
+    fn test_text() {
+        let text = report().to_text();
+        assert!(text.starts_with("disktest-erase-report-v1\ntool=disktest "));
+        assert!(text.contains("\ndevice=/dev/sdx\ndevice_model=Foo\ndevice_size=4096\n"));
+        assert!(text.contains("\npass.1.algorithm=chacha20\npass.1.bytes=4096\n"));
+        assert!(text.contains("\npass.2.algorithm=pattern:00\n"));
+        assert!(text.contains("\nverify.start=2026-01-02T03:04:05Z\n"));
+        assert!(text.contains("\nverify.result=ok\n"));
+        assert!(text.ends_with("\nresult=erased\n"));
+    }
+
+    #[test]
+    fn test_signature() {
+        let key = DisktestSigningKey::from_pkcs8(
+            Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .unwrap()
+                .as_ref(),
+        )
+        .unwrap();
+        let other = DisktestSigningKey::from_pkcs8(
+            Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .unwrap()
+                .as_ref(),
+        )
+        .unwrap();
+        let text = report().sign(&key);
+        assert!(DisktestEraseReport::check_signed(&text, None).is_ok());
+        assert!(DisktestEraseReport::check_signed(&text, Some(&key.public_key_hex())).is_ok());
+        assert!(DisktestEraseReport::check_signed(&text, Some(&other.public_key_hex())).is_err());
+        let forged = text.replace("result=erased", "result=FAILED");
+        assert!(DisktestEraseReport::check_signed(&forged, None).is_err());
+        let unsigned = report().to_text();
+        assert!(DisktestEraseReport::check_signed(&unsigned, None).is_err());
+    }
+
+    #[test]
+    fn test_key_file() {
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("key");
+        let a = DisktestSigningKey::generate(&path).unwrap();
+        let b = DisktestSigningKey::load(&path).unwrap();
+        assert_eq!(a.public_key_hex(), b.public_key_hex());
+        assert!(DisktestSigningKey::generate(&path).is_err());
+        std::fs::write(&path, b"foo").unwrap();
+        assert!(DisktestSigningKey::load(&path).is_err());
+        tdir.close().unwrap();
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,197 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::badmap::DisktestBadRange;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Unit of the device-mapper table, in bytes.
+/// Device-mapper always counts in 512 byte sectors, regardless of the device's sector size.
+const DM_SECTOR_SIZE: u64 = 512;
+
+/// Merge bad ranges from several sources into
+/// sorted and non-overlapping (offset, length) pairs.
+pub fn merge_ranges<'a>(ranges: impl IntoIterator<Item = &'a DisktestBadRange>) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = ranges
+        .into_iter()
+        .map(|range| (range.offset, range.offset + range.length))
+        .collect();
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (begin, end) in ranges {
+        if let Some(last) = merged.last_mut() {
+            if begin <= last.1 {
+                last.1 = last.1.max(end);
+                continue;
+            }
+        }
+        merged.push((begin, end));
+    }
+    merged
+        .into_iter()
+        .map(|(begin, end)| (begin, end - begin))
+        .collect()
+}
+
+/// Write the numbers of all blocks of `block_size` bytes that overlap a bad range.
+/// One block number per line.
+/// This is the format that `badblocks -o` writes and that `mke2fs -l` and `e2fsck -l` read.
+/// The block size must match the block size of the file system.
+pub fn write_badblocks(
+    out: &mut dyn Write,
+    ranges: &[(u64, u64)],
+    block_size: u64,
+) -> io::Result<()> {
+    debug_assert!(block_size > 0);
+    let mut next_block = 0;
+    for &(offset, length) in ranges {
+        if length == 0 {
+            continue;
+        }
+        let first = (offset / block_size).max(next_block);
+        let last = (offset + length - 1) / block_size;
+        for block in first..=last {
+            writeln!(out, "{block}")?;
+        }
+        next_block = next_block.max(last + 1);
+    }
+    Ok(())
+}
+
+/// Write a device-mapper table that maps around the bad ranges.
+/// The good areas of `device` are mapped back to back with `linear` targets.
+/// All areas are aligned to the `sector_size` of the device.
+/// The table can be loaded with `dmsetup create NAME FILE`.
+pub fn write_dmsetup_table(
+    out: &mut dyn Write,
+    ranges: &[(u64, u64)],
+    device: &Path,
+    device_size: u64,
+    sector_size: u32,
+) -> io::Result<()> {
+    let align = u64::from(sector_size).max(DM_SECTOR_SIZE);
+    let device_end = device_size - device_size % align;
+
+    let mut good = vec![];
+    let mut pos = 0;
+    for &(offset, length) in ranges {
+        let bad_begin = offset - offset % align;
+        let bad_end = (offset + length).next_multiple_of(align);
+        if bad_begin > pos {
+            good.push((pos, bad_begin.min(device_end)));
+        }
+        pos = pos.max(bad_end);
+    }
+    good.push((pos, device_end));
+
+    let mut mapped = 0;
+    for (begin, end) in good {
+        if begin >= end {
+            continue;
+        }
+        writeln!(
+            out,
+            "{} {} linear {} {}",
+            mapped / DM_SECTOR_SIZE,
+            (end - begin) / DM_SECTOR_SIZE,
+            device.display(),
+            begin / DM_SECTOR_SIZE
+        )?;
+        mapped += end - begin;
+    }
+    if mapped == 0 {
+        return Err(io::Error::other("The device has no good area to map."));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::badmap::DisktestBitErrors;
+
+    fn range(offset: u64, length: u64) -> DisktestBadRange {
+        DisktestBadRange {
+            offset,
+            length,
+            bytes_affected: length,
+            bit_errors: DisktestBitErrors::default(),
+            tag: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_ranges() {
+        let bad = [range(1024, 512), range(8192, 1024)];
+        let unreadable = [range(512, 512), range(4096, 512), range(8704, 1024)];
+        assert_eq!(
+            merge_ranges(bad.iter().chain(unreadable.iter())),
+            vec![(512, 1024), (4096, 512), (8192, 1536)]
+        );
+        assert!(merge_ranges([].iter()).is_empty());
+    }
+
+    #[test]
+    fn test_badblocks() {
+        let mut out = vec![];
+        write_badblocks(&mut out, &[(512, 512), (4096, 512), (8192, 8704)], 4096).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0\n1\n2\n3\n4\n");
+
+        let mut out = vec![];
+        write_badblocks(&mut out, &[(1024, 1536), (8192, 512)], 1024).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n2\n8\n");
+
+        let mut out = vec![];
+        write_badblocks(&mut out, &[], 1024).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_dmsetup_table() {
+        let dev = Path::new("/dev/sdx");
+        let mut out = vec![];
+        write_dmsetup_table(
+            &mut out,
+            &[(4096, 512), (16384, 8192)],
+            dev,
+            65536 + 100,
+            512,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0 8 linear /dev/sdx 0\n\
+             8 23 linear /dev/sdx 9\n\
+             31 80 linear /dev/sdx 48\n"
+        );
+
+        // Bad ranges are extended to the sector size.
+        let mut out = vec![];
+        write_dmsetup_table(&mut out, &[(0, 512), (12288, 512)], dev, 32768, 4096).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0 16 linear /dev/sdx 8\n\
+             16 32 linear /dev/sdx 32\n"
+        );
+
+        // No bad ranges.
+        let mut out = vec![];
+        write_dmsetup_table(&mut out, &[], dev, 4096, 512).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0 8 linear /dev/sdx 0\n");
+
+        // Nothing left.
+        let mut out = vec![];
+        assert!(write_dmsetup_table(&mut out, &[(0, 4096)], dev, 4096, 512).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
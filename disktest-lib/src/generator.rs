@@ -11,6 +11,9 @@
 
 mod chacha;
 mod crc;
+mod pattern;
+mod tagged;
+mod xoshiro;
 
 use crate::util::prettybytes;
 use anyhow as ah;
@@ -19,6 +22,9 @@ pub use crate::generator::chacha::GeneratorChaCha8;
 pub use crate::generator::chacha::GeneratorChaCha12;
 pub use crate::generator::chacha::GeneratorChaCha20;
 pub use crate::generator::crc::GeneratorCrc;
+pub use crate::generator::pattern::{DtPattern, DtPatternBytes, GeneratorPattern};
+pub use crate::generator::tagged::{DisktestSectorTag, GeneratorTagged, parse_sector_tag};
+pub use crate::generator::xoshiro::GeneratorXoshiro;
 
 pub trait NextRandom {
     /// Get the size of the `next()` output with count = 1, in bytes.
@@ -45,6 +45,13 @@ fn crc64(lut: &[u64; 256], mut crc: u64, data: &[u8]) -> u64 {
     crc
 }
 
+/// Continue a CRC64-ECMA calculation over `data`.
+/// Start with `!0` and invert the final result.
+pub fn crc64_ecma(crc: u64, data: &[u8]) -> u64 {
+    let lut = CRC64_ECMA_LUT.get_or_init(|| crc64_gen_lut(CRC64_ECMA_POLY));
+    crc64(lut, crc, data)
+}
+
 pub struct GeneratorCrc {
     folded_seed: [u8; GeneratorCrc::FOLDED_SEED_SIZE],
     counter: u64,
@@ -58,6 +65,10 @@ impl GeneratorCrc {
 
     const CRC_SIZE: usize = 64 / 8;
     const FOLDED_SEED_SIZE: usize = 64 / 8;
+    /// Number of chunks generated in parallel.
+    /// Each chunk is one long dependency chain of table lookups.
+    /// Interleaving independent chunks keeps the CPU busy.
+    const LANES: usize = 4;
 
     pub fn new(seed: &[u8]) -> GeneratorCrc {
         let _ = CRC64_ECMA_LUT.get_or_init(|| crc64_gen_lut(CRC64_ECMA_POLY));
@@ -70,6 +81,46 @@ impl GeneratorCrc {
             counter: 0,
         }
     }
+
+    /// Generate `N` consecutive chunks of size `BASE_SIZE` into `buf`.
+    #[inline(always)]
+    #[allow(clippy::inline_always)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn next_lanes<const N: usize>(&mut self, lut: &[u64; 256], buf: &mut [u8]) {
+        debug_assert_eq!(buf.len(), GeneratorCrc::BASE_SIZE * N);
+
+        // Initialize CRC based on the seed and current counter.
+        let mut crc = [!0_u64; N];
+        for c in &mut crc {
+            *c = crc64(lut, *c, &self.folded_seed);
+            *c = crc64(lut, *c, &self.counter.to_le_bytes());
+            self.counter += 1;
+        }
+
+        // Output words of each chunk.
+        let mut words: [_; N] = {
+            let mut chunks = buf.chunks_exact_mut(GeneratorCrc::BASE_SIZE);
+            std::array::from_fn(|_| {
+                chunks
+                    .next()
+                    .unwrap()
+                    .chunks_exact_mut(GeneratorCrc::CRC_SIZE)
+            })
+        };
+
+        // Fast inner loop:
+        // Generate the next chunks with size = BASE_SIZE.
+        for offs in 0..(GeneratorCrc::BASE_SIZE / GeneratorCrc::CRC_SIZE) {
+            debug_assert!(offs <= 0xFF);
+            for (c, w) in crc.iter_mut().zip(words.iter_mut()) {
+                // Advance CRC state.
+                *c = lut[((*c as u8) ^ (offs as u8)) as usize] ^ (*c >> 8);
+
+                // Write CRC output to output buffer.
+                w.next().unwrap().copy_from_slice(&(!*c).to_le_bytes());
+            }
+        }
+    }
 }
 
 impl NextRandom for GeneratorCrc {
@@ -77,36 +128,20 @@ impl NextRandom for GeneratorCrc {
         GeneratorCrc::BASE_SIZE
     }
 
-    #[allow(clippy::cast_possible_truncation)]
     fn next(&mut self, buf: &mut [u8], count: usize) {
         debug_assert_eq!(buf.len(), GeneratorCrc::BASE_SIZE * count);
 
         let lut = CRC64_ECMA_LUT.get().unwrap();
 
-        for i in 0..count {
-            let chunk_offs = i * GeneratorCrc::BASE_SIZE;
-
-            // Initialize CRC based on the seed and current counter.
-            let mut crc = !0_u64;
-            crc = crc64(lut, crc, &self.folded_seed);
-            crc = crc64(lut, crc, &self.counter.to_le_bytes());
-            self.counter += 1;
-
-            // Fast inner loop:
-            // Generate the next chunk with size = BASE_SIZE.
-            for offs in 0..(GeneratorCrc::BASE_SIZE / GeneratorCrc::CRC_SIZE) {
-                // Advance CRC state.
-                debug_assert!(offs <= 0xFF);
-                crc = crc64(lut, crc, &(offs as u8).to_le_bytes());
-
-                // Get CRC output.
-                let crc_bytes = (!crc).to_le_bytes();
-
-                // Write CRC output to output buffer.
-                let begin = chunk_offs + (offs * GeneratorCrc::CRC_SIZE);
-                let end = chunk_offs + ((offs + 1) * GeneratorCrc::CRC_SIZE);
-                buf[begin..end].copy_from_slice(&crc_bytes);
-            }
+        let mut chunks = buf.chunks_exact_mut(GeneratorCrc::BASE_SIZE * GeneratorCrc::LANES);
+        for chunk in &mut chunks {
+            self.next_lanes::<{ GeneratorCrc::LANES }>(lut, chunk);
+        }
+        for chunk in chunks
+            .into_remainder()
+            .chunks_exact_mut(GeneratorCrc::BASE_SIZE)
+        {
+            self.next_lanes::<1>(lut, chunk);
         }
     }
 
@@ -144,6 +179,37 @@ mod tests {
         assert_eq!(buf.iter().enumerate().fold(0, reduce), 17_260_884);
         a.next(&mut buf[0..GeneratorCrc::BASE_SIZE * 3], 3);
         assert_eq!(buf.iter().enumerate().fold(0, reduce), 581_162_875);
+
+        // Several parallel lanes and a remainder.
+        let mut a = GeneratorCrc::new(&[1, 2, 3]);
+        let mut buf = vec![0_u8; GeneratorCrc::BASE_SIZE * 11];
+        a.next(&mut buf, 11);
+        assert_eq!(buf.iter().enumerate().fold(0, reduce), 2_752_243_106);
+        a.next(&mut buf[0..GeneratorCrc::BASE_SIZE * 8], 8);
+        assert_eq!(buf.iter().enumerate().fold(0, reduce), 543_271_036);
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_reference() {
+        // Simple byte-wise implementation of the CRC stream.
+        fn reference(seed: &[u8], counter: u64) -> Vec<u8> {
+            let mut crc = crc64_ecma(!0, &fold(seed, GeneratorCrc::FOLDED_SEED_SIZE));
+            crc = crc64_ecma(crc, &counter.to_le_bytes());
+            let mut buf = vec![];
+            for offs in 0..(GeneratorCrc::BASE_SIZE / GeneratorCrc::CRC_SIZE) {
+                crc = crc64_ecma(crc, &[offs as u8]);
+                buf.extend_from_slice(&(!crc).to_le_bytes());
+            }
+            buf
+        }
+        let mut a = GeneratorCrc::new(&[5, 6, 7]);
+        a.seek(GeneratorCrc::BASE_SIZE as u64 * 3).unwrap();
+        let mut buf = vec![0_u8; GeneratorCrc::BASE_SIZE * 6];
+        a.next(&mut buf, 6);
+        for (i, chunk) in buf.chunks_exact(GeneratorCrc::BASE_SIZE).enumerate() {
+            assert_eq!(chunk, reference(&[5, 6, 7], 3 + i as u64));
+        }
     }
 
     #[test]
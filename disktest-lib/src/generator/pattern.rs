@@ -0,0 +1,291 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::generator::NextRandom;
+use anyhow as ah;
+
+/// Sector size of the checkerboard pattern.
+const CHECKERBOARD_SECTOR_SIZE: u64 = 512;
+
+/// Deterministic test pattern.
+///
+/// Fixed patterns catch stuck bits and charge related flash faults
+/// that random data may hide.
+/// The pattern is a function of the absolute byte offset on the device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DtPattern {
+    /// Every byte has the same value, e.g. 0x00, 0xFF, 0xAA or 0x55.
+    Byte(u8),
+    /// A user-supplied sequence of bytes that is repeated.
+    Bytes(DtPatternBytes),
+    /// Alternating 0x55 and 0xAA bytes.
+    /// The phase is inverted in every other 512 byte sector.
+    Checkerboard,
+    /// Every byte has exactly one bit set: 0x01, 0x02, 0x04, ..., 0x80, 0x01, ...
+    WalkingOnes,
+    /// Every byte has exactly one bit cleared: 0xFE, 0xFD, 0xFB, ..., 0x7F, 0xFE, ...
+    WalkingZeros,
+}
+
+/// User-supplied pattern of up to [`DtPatternBytes::MAX_LEN`] bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DtPatternBytes {
+    data: [u8; DtPatternBytes::MAX_LEN],
+    len: usize,
+}
+
+impl DtPatternBytes {
+    /// Maximum length of a user-supplied pattern, in bytes.
+    pub const MAX_LEN: usize = 32;
+
+    /// Create a pattern from 1 to `MAX_LEN` bytes.
+    pub fn new(bytes: &[u8]) -> Option<DtPatternBytes> {
+        if bytes.is_empty() || bytes.len() > DtPatternBytes::MAX_LEN {
+            return None;
+        }
+        let mut data = [0; DtPatternBytes::MAX_LEN];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Some(DtPatternBytes {
+            data,
+            len: bytes.len(),
+        })
+    }
+
+    /// Get the pattern bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl DtPattern {
+    /// Parse a hexadecimal byte sequence, e.g. `AA55` or `0xDEADBEEF`.
+    /// A single byte results in [`DtPattern::Byte`].
+    pub fn from_hex(hex: &str) -> Option<DtPattern> {
+        let hex = hex.trim();
+        let hex = hex
+            .strip_prefix("0x")
+            .or_else(|| hex.strip_prefix("0X"))
+            .unwrap_or(hex);
+        if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        if let [byte] = bytes[..] {
+            Some(DtPattern::Byte(byte))
+        } else {
+            DtPatternBytes::new(&bytes).map(DtPattern::Bytes)
+        }
+    }
+
+    /// Get the pattern byte at the absolute byte offset.
+    #[allow(clippy::cast_possible_truncation)]
+    fn byte_at(&self, offset: u64) -> u8 {
+        match self {
+            DtPattern::Byte(byte) => *byte,
+            DtPattern::Bytes(bytes) => {
+                let bytes = bytes.as_slice();
+                bytes[(offset % bytes.len() as u64) as usize]
+            }
+            DtPattern::Checkerboard => {
+                if (offset + offset / CHECKERBOARD_SECTOR_SIZE) % 2 == 0 {
+                    0x55
+                } else {
+                    0xAA
+                }
+            }
+            DtPattern::WalkingOnes => 1 << (offset % 8),
+            DtPattern::WalkingZeros => !(1 << (offset % 8)),
+        }
+    }
+}
+
+impl std::fmt::Display for DtPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DtPattern::Byte(byte) => write!(f, "pattern:{byte:02x}"),
+            DtPattern::Bytes(bytes) => {
+                write!(f, "pattern:")?;
+                for byte in bytes.as_slice() {
+                    write!(f, "{byte:02x}")?;
+                }
+                Ok(())
+            }
+            DtPattern::Checkerboard => write!(f, "checkerboard"),
+            DtPattern::WalkingOnes => write!(f, "walking1"),
+            DtPattern::WalkingZeros => write!(f, "walking0"),
+        }
+    }
+}
+
+/// Fixed pattern generator.
+/// The seed is not used.
+pub struct GeneratorPattern {
+    pattern: DtPattern,
+    thread_id: u64,
+    num_threads: u64,
+    chunk_size: u64,
+    stream_offset: u64,
+}
+
+impl GeneratorPattern {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 4;
+    /// Default chunk size multiplicator.
+    pub const DEFAULT_CHUNK_FACTOR: usize = 768;
+
+    /// Create a new pattern generator for one stream thread.
+    /// `thread_id`, `num_threads` and `chunk_factor` describe the stream layout
+    /// and are needed to calculate the absolute byte offsets.
+    pub fn new(
+        pattern: DtPattern,
+        thread_id: u32,
+        num_threads: u32,
+        chunk_factor: usize,
+    ) -> GeneratorPattern {
+        GeneratorPattern {
+            pattern,
+            thread_id: thread_id.into(),
+            num_threads: num_threads.into(),
+            chunk_size: (GeneratorPattern::BASE_SIZE * chunk_factor) as u64,
+            stream_offset: 0,
+        }
+    }
+
+    /// Convert a byte offset within this thread's stream into an absolute byte offset.
+    fn absolute_offset(&self, stream_offset: u64) -> u64 {
+        let chunk_index = stream_offset / self.chunk_size;
+        let chunk_offset = stream_offset % self.chunk_size;
+        (chunk_index * self.num_threads + self.thread_id) * self.chunk_size + chunk_offset
+    }
+}
+
+impl NextRandom for GeneratorPattern {
+    fn get_base_size(&self) -> usize {
+        GeneratorPattern::BASE_SIZE
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        debug_assert!(buf.len() == GeneratorPattern::BASE_SIZE * count);
+
+        // A base block never crosses a chunk boundary.
+        for block in buf.chunks_exact_mut(GeneratorPattern::BASE_SIZE) {
+            let offset = self.absolute_offset(self.stream_offset);
+            for (i, x) in block.iter_mut().enumerate() {
+                *x = self.pattern.byte_at(offset + i as u64);
+            }
+            self.stream_offset += GeneratorPattern::BASE_SIZE as u64;
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if byte_offset % GeneratorPattern::BASE_SIZE as u64 != 0 {
+            return Err(ah::format_err!(
+                "Pattern seek: Byte offset is not a \
+                 multiple of the base size ({} bytes).",
+                GeneratorPattern::BASE_SIZE
+            ));
+        }
+
+        self.stream_offset = byte_offset;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(pattern: DtPattern, len: usize) -> Vec<u8> {
+        let mut a = GeneratorPattern::new(pattern, 0, 1, 1);
+        let mut buf = vec![0_u8; len.next_multiple_of(GeneratorPattern::BASE_SIZE)];
+        let count = buf.len() / GeneratorPattern::BASE_SIZE;
+        a.next(&mut buf, count);
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert!(
+            generate(DtPattern::Byte(0xAA), 8192)
+                .iter()
+                .all(|x| *x == 0xAA)
+        );
+        let bytes = DtPatternBytes::new(&[1, 2, 3]).unwrap();
+        assert_eq!(
+            generate(DtPattern::Bytes(bytes), 7),
+            vec![1, 2, 3, 1, 2, 3, 1]
+        );
+        let data = generate(DtPattern::Checkerboard, 1024);
+        assert_eq!(data[0..4], [0x55, 0xAA, 0x55, 0xAA]);
+        assert_eq!(data[510..514], [0x55, 0xAA, 0xAA, 0x55]);
+        assert_eq!(
+            generate(DtPattern::WalkingOnes, 9),
+            vec![0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x01]
+        );
+        assert_eq!(generate(DtPattern::WalkingZeros, 3), vec![0xFE, 0xFD, 0xFB]);
+    }
+
+    #[test]
+    fn test_thread_layout() {
+        // Thread 1 of 2 generates every other chunk, starting at chunk 1.
+        let bytes = DtPatternBytes::new(&[1, 2, 3]).unwrap();
+        let mut a = GeneratorPattern::new(DtPattern::Bytes(bytes), 1, 2, 1);
+        let mut buf = vec![0_u8; GeneratorPattern::BASE_SIZE];
+        a.next(&mut buf, 1);
+        let offset = GeneratorPattern::BASE_SIZE;
+        assert_eq!(buf[0], [1, 2, 3][offset % 3]);
+        a.next(&mut buf, 1);
+        let offset = GeneratorPattern::BASE_SIZE * 3;
+        assert_eq!(buf[0], [1, 2, 3][offset % 3]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorPattern::new(DtPattern::Checkerboard, 0, 1, 4);
+        let mut b = GeneratorPattern::new(DtPattern::Checkerboard, 0, 1, 4);
+        b.seek(GeneratorPattern::BASE_SIZE as u64).unwrap();
+        let mut adata = vec![0_u8; GeneratorPattern::BASE_SIZE * 2];
+        a.next(&mut adata, 2);
+        let mut bdata = vec![0_u8; GeneratorPattern::BASE_SIZE];
+        b.next(&mut bdata, 1);
+        assert_eq!(adata[GeneratorPattern::BASE_SIZE..], bdata[..]);
+        assert!(b.seek(100).is_err());
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(DtPattern::from_hex("aa"), Some(DtPattern::Byte(0xAA)));
+        assert_eq!(DtPattern::from_hex("0x00"), Some(DtPattern::Byte(0)));
+        assert_eq!(
+            DtPattern::from_hex("DEADbeef"),
+            Some(DtPattern::Bytes(
+                DtPatternBytes::new(&[0xDE, 0xAD, 0xBE, 0xEF]).unwrap()
+            ))
+        );
+        assert_eq!(DtPattern::from_hex(""), None);
+        assert_eq!(DtPattern::from_hex("abc"), None);
+        assert_eq!(DtPattern::from_hex("zz"), None);
+        assert_eq!(DtPattern::from_hex("ä0"), None);
+        assert_eq!(DtPattern::from_hex(&"00".repeat(33)), None);
+        assert_eq!(
+            DtPattern::from_hex("aa55").unwrap().to_string(),
+            "pattern:aa55"
+        );
+        assert_eq!(DtPattern::Byte(0xF).to_string(), "pattern:0f");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,245 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::generator::NextRandom;
+use crate::generator::crc::crc64_ecma;
+use crate::util::fold;
+use anyhow as ah;
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+
+/// Magic marker at the start of each tagged sector.
+const TAG_MAGIC: &[u8; 8] = b"DTSECTOR";
+/// Size of the sector header.
+const TAG_HDR_SIZE: usize = 40;
+/// Byte range of the checksum within the header.
+const TAG_CSUM: std::ops::Range<usize> = 32..TAG_HDR_SIZE;
+
+/// Header of a sector that has been written by the self-describing
+/// [`DtStreamType::Tagged`](crate::DtStreamType::Tagged) stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisktestSectorTag {
+    /// The absolute byte offset the sector has been written to.
+    pub offset: u64,
+    /// The round the sector has been written in.
+    pub round_id: u64,
+    /// Fingerprint of the seed the sector has been written with.
+    pub seed_fingerprint: u64,
+    /// The checksum over the sector is correct.
+    pub checksum_ok: bool,
+    /// The sector has been written with inverted pattern.
+    pub inverted: bool,
+}
+
+/// Calculate the checksum over a tagged sector, excluding the checksum field.
+fn sector_checksum(sector: &[u8]) -> u64 {
+    let crc = crc64_ecma(!0, &sector[..TAG_CSUM.start]);
+    !crc64_ecma(crc, &sector[TAG_CSUM.end..])
+}
+
+/// Parse the header of a tagged sector.
+/// Returns None, if the data does not start with a sector header.
+pub fn parse_sector_tag(sector: &[u8]) -> Option<DisktestSectorTag> {
+    if sector.len() < GeneratorTagged::SECTOR_SIZE {
+        return None;
+    }
+    let inverted = if sector[0..8] == *TAG_MAGIC {
+        false
+    } else if sector[0..8].iter().zip(TAG_MAGIC).all(|(a, b)| *a == !*b) {
+        true
+    } else {
+        return None;
+    };
+
+    let mut sector = sector[..GeneratorTagged::SECTOR_SIZE].to_vec();
+    if inverted {
+        for x in &mut sector {
+            *x ^= 0xFF;
+        }
+    }
+    let field = |begin: usize| u64::from_le_bytes(sector[begin..begin + 8].try_into().unwrap());
+
+    Some(DisktestSectorTag {
+        offset: field(8),
+        round_id: field(16),
+        seed_fingerprint: field(24),
+        checksum_ok: field(TAG_CSUM.start) == sector_checksum(&sector),
+        inverted,
+    })
+}
+
+/// Self-describing generator.
+/// Every sector starts with a header containing its absolute byte offset,
+/// the round, a seed fingerprint and a checksum over the sector.
+/// The rest of the sector is `ChaCha20` payload.
+pub struct GeneratorTagged {
+    rng: ChaCha20Rng,
+    seed_fingerprint: u64,
+    round_id: u64,
+    thread_id: u64,
+    num_threads: u64,
+    chunk_size: u64,
+    stream_offset: u64,
+}
+
+impl GeneratorTagged {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 2;
+    /// Default chunk size multiplicator.
+    pub const DEFAULT_CHUNK_FACTOR: usize = 1024 + 512;
+    /// Size of one tagged sector.
+    pub const SECTOR_SIZE: usize = 512;
+
+    /// Create a new tagged generator for one stream thread.
+    /// `thread_id`, `num_threads` and `chunk_factor` describe the stream layout
+    /// and are needed to calculate the absolute byte offsets.
+    pub fn new(
+        seed: &[u8],
+        seed_fingerprint: u64,
+        round_id: u64,
+        thread_id: u32,
+        num_threads: u32,
+        chunk_factor: usize,
+    ) -> GeneratorTagged {
+        assert!(!seed.is_empty());
+        let folded_seed = fold(seed, 32).try_into().unwrap();
+        GeneratorTagged {
+            rng: ChaCha20Rng::from_seed(folded_seed),
+            seed_fingerprint,
+            round_id,
+            thread_id: thread_id.into(),
+            num_threads: num_threads.into(),
+            chunk_size: (GeneratorTagged::BASE_SIZE * chunk_factor) as u64,
+            stream_offset: 0,
+        }
+    }
+
+    /// Convert a byte offset within this thread's stream into an absolute byte offset.
+    fn absolute_offset(&self, stream_offset: u64) -> u64 {
+        let chunk_index = stream_offset / self.chunk_size;
+        let chunk_offset = stream_offset % self.chunk_size;
+        (chunk_index * self.num_threads + self.thread_id) * self.chunk_size + chunk_offset
+    }
+}
+
+impl NextRandom for GeneratorTagged {
+    fn get_base_size(&self) -> usize {
+        GeneratorTagged::BASE_SIZE
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        debug_assert!(buf.len() == GeneratorTagged::BASE_SIZE * count);
+        self.rng.fill(buf);
+
+        for sector in buf.chunks_exact_mut(GeneratorTagged::SECTOR_SIZE) {
+            let offset = self.absolute_offset(self.stream_offset);
+            sector[0..8].copy_from_slice(TAG_MAGIC);
+            sector[8..16].copy_from_slice(&offset.to_le_bytes());
+            sector[16..24].copy_from_slice(&self.round_id.to_le_bytes());
+            sector[24..32].copy_from_slice(&self.seed_fingerprint.to_le_bytes());
+            let csum = sector_checksum(sector);
+            sector[TAG_CSUM].copy_from_slice(&csum.to_le_bytes());
+            self.stream_offset += GeneratorTagged::SECTOR_SIZE as u64;
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if byte_offset % GeneratorTagged::BASE_SIZE as u64 != 0 {
+            return Err(ah::format_err!(
+                "Tagged seek: Byte offset is not a \
+                 multiple of the base size ({} bytes).",
+                GeneratorTagged::BASE_SIZE
+            ));
+        }
+
+        self.rng.set_word_pos((byte_offset / 4).into());
+        self.stream_offset = byte_offset;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_FACTOR: usize = 4;
+    const CHUNK_SIZE: usize = GeneratorTagged::BASE_SIZE * CHUNK_FACTOR;
+
+    fn new_gen(thread_id: u32) -> GeneratorTagged {
+        GeneratorTagged::new(&[1, 2, 3], 0x1122, 7, thread_id, 2, CHUNK_FACTOR)
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut a = new_gen(1);
+        let mut buf = vec![0_u8; CHUNK_SIZE * 2];
+        a.next(&mut buf, CHUNK_FACTOR * 2);
+
+        for (i, sector) in buf.chunks(GeneratorTagged::SECTOR_SIZE).enumerate() {
+            let tag = parse_sector_tag(sector).unwrap();
+            // Thread 1 of 2 generates every other chunk, starting at chunk 1.
+            let stream_offset = i * GeneratorTagged::SECTOR_SIZE;
+            let chunk = stream_offset / CHUNK_SIZE;
+            let expected = (chunk * 2 + 1) * CHUNK_SIZE + stream_offset % CHUNK_SIZE;
+            assert_eq!(tag.offset, expected as u64);
+            assert_eq!(tag.round_id, 7);
+            assert_eq!(tag.seed_fingerprint, 0x1122);
+            assert!(tag.checksum_ok);
+            assert!(!tag.inverted);
+        }
+
+        // Payload corruption.
+        let mut sector = buf[0..512].to_vec();
+        sector[100] ^= 1;
+        let tag = parse_sector_tag(&sector).unwrap();
+        assert_eq!(tag.offset, CHUNK_SIZE as u64);
+        assert!(!tag.checksum_ok);
+
+        // Inverted pattern.
+        let sector: Vec<u8> = buf[512..1024].iter().map(|x| !x).collect();
+        let tag = parse_sector_tag(&sector).unwrap();
+        assert_eq!(tag.offset, CHUNK_SIZE as u64 + 512);
+        assert!(tag.checksum_ok);
+        assert!(tag.inverted);
+
+        // No header.
+        assert_eq!(parse_sector_tag(&[0; 512]), None);
+        assert_eq!(parse_sector_tag(&buf[0..511]), None);
+    }
+
+    #[test]
+    fn test_payload_differs() {
+        let mut a = new_gen(0);
+        let mut buf = vec![0_u8; GeneratorTagged::BASE_SIZE];
+        a.next(&mut buf, 1);
+        assert_ne!(buf[TAG_HDR_SIZE..512], buf[512 + TAG_HDR_SIZE..1024]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = new_gen(0);
+        let mut b = new_gen(0);
+        b.seek(GeneratorTagged::BASE_SIZE as u64 * 2).unwrap();
+        let mut bdata = vec![0_u8; GeneratorTagged::BASE_SIZE];
+        b.next(&mut bdata, 1);
+        let mut adata = vec![0_u8; GeneratorTagged::BASE_SIZE];
+        a.next(&mut adata, 1);
+        assert_ne!(adata, bdata);
+        a.next(&mut adata, 1);
+        assert_ne!(adata, bdata);
+        a.next(&mut adata, 1);
+        assert_eq!(adata, bdata);
+        assert!(b.seek(100).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
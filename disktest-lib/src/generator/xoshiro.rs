@@ -0,0 +1,344 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::generator::NextRandom;
+use crate::util::fold;
+use anyhow as ah;
+
+const WORD_SIZE: usize = 64 / 8;
+
+/// `SplitMix64` step.
+#[inline(always)]
+#[allow(clippy::inline_always)]
+fn splitmix64(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// xoshiro256++ step of all streams.
+/// `s[i][lane]` is the state word `i` of the stream `lane`.
+#[inline(always)]
+#[allow(clippy::inline_always)]
+fn xoshiro256pp<const N: usize>(s: &mut [[u64; N]; 4]) -> [u64; N] {
+    let mut result = [0; N];
+    for (lane, r) in result.iter_mut().enumerate() {
+        *r = s[0][lane]
+            .wrapping_add(s[3][lane])
+            .rotate_left(23)
+            .wrapping_add(s[0][lane]);
+        let t = s[1][lane] << 17;
+        s[2][lane] ^= s[0][lane];
+        s[3][lane] ^= s[1][lane];
+        s[1][lane] ^= s[2][lane];
+        s[0][lane] ^= s[3][lane];
+        s[2][lane] ^= t;
+        s[3][lane] = s[3][lane].rotate_left(45);
+    }
+    result
+}
+
+/// Fast non-cryptographic generator.
+///
+/// Every block of `BASE_SIZE` bytes is generated by `LANES` interleaved xoshiro256++ streams.
+/// The stream states are derived from the seed, the block index and the lane with `SplitMix64`.
+/// Therefore, seeking to any word offset is cheap.
+pub struct GeneratorXoshiro {
+    key: [u64; 4],
+    block: u64,
+    word: usize,
+}
+
+impl GeneratorXoshiro {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 4;
+    /// Default chunk size multiplicator.
+    pub const DEFAULT_CHUNK_FACTOR: usize = 768;
+
+    const BLOCK_WORDS: usize = GeneratorXoshiro::BASE_SIZE / WORD_SIZE;
+    const FOLDED_SEED_SIZE: usize = 4 * WORD_SIZE;
+    /// Number of xoshiro256++ streams per block.
+    /// The word `i` of a block is generated by the stream `i % LANES`.
+    /// One step of all streams produces consecutive words,
+    /// so that the compiler can compute and store them with vector instructions.
+    const LANES: usize = 8;
+
+    pub fn new(seed: &[u8]) -> GeneratorXoshiro {
+        assert!(!seed.is_empty());
+        let folded_seed = fold(seed, GeneratorXoshiro::FOLDED_SEED_SIZE);
+        let mut key = [0_u64; 4];
+        for (k, bytes) in key.iter_mut().zip(folded_seed.chunks_exact(WORD_SIZE)) {
+            *k = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        GeneratorXoshiro {
+            key,
+            block: 0,
+            word: 0,
+        }
+    }
+
+    /// Get the initial xoshiro256++ states of the streams of the block.
+    fn block_state(&self, block: u64) -> [[u64; GeneratorXoshiro::LANES]; 4] {
+        let mut state = [[0; GeneratorXoshiro::LANES]; 4];
+        for lane in 0..GeneratorXoshiro::LANES {
+            let mut x =
+                self.key[0] ^ block.wrapping_mul(GeneratorXoshiro::LANES as u64) ^ lane as u64;
+            for (s, k) in state.iter_mut().zip(self.key) {
+                s[lane] = splitmix64(&mut x) ^ k;
+            }
+            if state.iter().all(|s| s[lane] == 0) {
+                // The all-zero state is a fixed point.
+                state[0][lane] = 1;
+            }
+        }
+        state
+    }
+
+    /// Generate the block into `buf`.
+    fn gen_block(&self, block: u64, buf: &mut [u8]) {
+        debug_assert_eq!(buf.len(), GeneratorXoshiro::BASE_SIZE);
+
+        let mut state = self.block_state(block);
+        for words in buf.chunks_exact_mut(WORD_SIZE * GeneratorXoshiro::LANES) {
+            let result = xoshiro256pp(&mut state);
+            for (r, w) in result.iter().zip(words.chunks_exact_mut(WORD_SIZE)) {
+                w.copy_from_slice(&r.to_le_bytes());
+            }
+        }
+    }
+
+    /// Fill `buf` with the next words of the current block.
+    /// `buf` must not be longer than the rest of the block.
+    fn fill_partial(&mut self, buf: &mut [u8]) {
+        let mut data = [0; GeneratorXoshiro::BASE_SIZE];
+        self.gen_block(self.block, &mut data);
+        let begin = self.word * WORD_SIZE;
+        buf.copy_from_slice(&data[begin..begin + buf.len()]);
+
+        self.word += buf.len() / WORD_SIZE;
+        if self.word == GeneratorXoshiro::BLOCK_WORDS {
+            self.block += 1;
+            self.word = 0;
+        }
+    }
+}
+
+impl NextRandom for GeneratorXoshiro {
+    fn get_base_size(&self) -> usize {
+        GeneratorXoshiro::BASE_SIZE
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        debug_assert!(buf.len() == GeneratorXoshiro::BASE_SIZE * count);
+
+        // Finish the current block, if seek() stopped in the middle of it.
+        let head = ((GeneratorXoshiro::BLOCK_WORDS - self.word) % GeneratorXoshiro::BLOCK_WORDS)
+            * WORD_SIZE;
+        let (head, tail) = buf.split_at_mut(head.min(buf.len()));
+        if !head.is_empty() {
+            self.fill_partial(head);
+        }
+
+        let mut blocks = tail.chunks_exact_mut(GeneratorXoshiro::BASE_SIZE);
+        for block in &mut blocks {
+            self.gen_block(self.block, block);
+            self.block += 1;
+        }
+
+        // Start the last block, if seek() stopped in the middle of a block.
+        let rest = blocks.into_remainder();
+        if !rest.is_empty() {
+            self.fill_partial(rest);
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if byte_offset % WORD_SIZE as u64 != 0 {
+            return Err(ah::format_err!(
+                "Xoshiro seek: Byte offset is not a \
+                 multiple of the word size ({WORD_SIZE} bytes)."
+            ));
+        }
+
+        self.block = byte_offset / GeneratorXoshiro::BASE_SIZE as u64;
+        self.word = ((byte_offset % GeneratorXoshiro::BASE_SIZE as u64) / WORD_SIZE as u64)
+            .try_into()
+            .unwrap();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_answer() {
+        // Reference values of SplitMix64 and xoshiro256++.
+        let mut x = 1_234_567;
+        let out: Vec<u64> = (0..5).map(|_| splitmix64(&mut x)).collect();
+        assert_eq!(
+            out,
+            [
+                6_457_827_717_110_365_317,
+                3_203_168_211_198_807_973,
+                9_817_491_932_198_370_423,
+                4_593_380_528_125_082_431,
+                16_408_922_859_458_223_821,
+            ]
+        );
+        let mut s = [[1], [2], [3], [4]];
+        let out: Vec<u64> = (0..6).map(|_| xoshiro256pp(&mut s)[0]).collect();
+        assert_eq!(
+            out,
+            [
+                41_943_041,
+                58_720_359,
+                3_588_806_011_781_223,
+                3_591_011_842_654_386,
+                9_228_616_714_210_784_205,
+                9_973_669_472_204_895_162,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cmp_result() {
+        fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
+            acc.rotate_left(u32::try_from(i).unwrap()) ^ (u32::from(*x))
+        }
+        let mut a = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut buf = vec![0_u8; GeneratorXoshiro::BASE_SIZE * 3];
+        a.next(&mut buf[0..GeneratorXoshiro::BASE_SIZE], 1);
+        assert_eq!(buf.iter().enumerate().fold(0, reduce), 2_030_021_727);
+        a.next(&mut buf[0..GeneratorXoshiro::BASE_SIZE], 1);
+        assert_eq!(buf.iter().enumerate().fold(0, reduce), 3_392_536_043);
+        a.next(&mut buf[0..GeneratorXoshiro::BASE_SIZE * 2], 2);
+        assert_eq!(buf.iter().enumerate().fold(0, reduce), 2_917_549_629);
+        a.next(&mut buf[0..GeneratorXoshiro::BASE_SIZE * 3], 3);
+        assert_eq!(buf.iter().enumerate().fold(0, reduce), 1_513_847_484);
+    }
+
+    #[test]
+    fn test_layout() {
+        // The word `i` of a block is the next word of the stream `i % LANES`.
+        let mut a = GeneratorXoshiro::new(&[5, 6, 7]);
+        let mut adata = vec![0_u8; GeneratorXoshiro::BASE_SIZE * 3];
+        a.next(&mut adata, 3);
+        for (block, data) in adata.chunks_exact(GeneratorXoshiro::BASE_SIZE).enumerate() {
+            let mut state = a.block_state(block as u64);
+            for words in data.chunks_exact(WORD_SIZE * GeneratorXoshiro::LANES) {
+                let expected = xoshiro256pp(&mut state);
+                for (word, value) in words.chunks_exact(WORD_SIZE).zip(expected) {
+                    assert_eq!(word, value.to_le_bytes());
+                }
+            }
+        }
+        assert_ne!(a.block_state(0), a.block_state(1));
+    }
+
+    #[test]
+    fn test_seed_equal() {
+        let mut a = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut b = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut res_a: Vec<Vec<u8>> = vec![];
+        let mut res_b: Vec<Vec<u8>> = vec![];
+        for _ in 0..2 {
+            let mut buf = vec![0_u8; GeneratorXoshiro::BASE_SIZE];
+            a.next(&mut buf, 1);
+            res_a.push(buf);
+            let mut buf = vec![0_u8; GeneratorXoshiro::BASE_SIZE];
+            b.next(&mut buf, 1);
+            res_b.push(buf);
+        }
+        assert_eq!(res_a[0], res_b[0]);
+        assert_eq!(res_a[1], res_b[1]);
+        assert_ne!(res_a[0], res_a[1]);
+        assert_ne!(res_b[0], res_b[1]);
+    }
+
+    #[test]
+    fn test_seed_diff() {
+        let mut a = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut b = GeneratorXoshiro::new(&[1, 2, 4]);
+        let mut res_a: Vec<Vec<u8>> = vec![];
+        let mut res_b: Vec<Vec<u8>> = vec![];
+        for _ in 0..2 {
+            let mut buf = vec![0_u8; GeneratorXoshiro::BASE_SIZE];
+            a.next(&mut buf, 1);
+            res_a.push(buf);
+            let mut buf = vec![0_u8; GeneratorXoshiro::BASE_SIZE];
+            b.next(&mut buf, 1);
+            res_b.push(buf);
+        }
+        assert_ne!(res_a[0], res_b[0]);
+        assert_ne!(res_a[1], res_b[1]);
+        assert_ne!(res_a[0], res_a[1]);
+        assert_ne!(res_b[0], res_b[1]);
+    }
+
+    #[test]
+    fn test_concat_equal() {
+        let mut a = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut b = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut buf_a = vec![0_u8; GeneratorXoshiro::BASE_SIZE * 2];
+        a.next(&mut buf_a[0..GeneratorXoshiro::BASE_SIZE], 1);
+        a.next(
+            &mut buf_a[GeneratorXoshiro::BASE_SIZE..GeneratorXoshiro::BASE_SIZE * 2],
+            1,
+        );
+        let mut buf_b = vec![0_u8; GeneratorXoshiro::BASE_SIZE * 2];
+        b.next(&mut buf_b, 2);
+        assert_eq!(buf_a, buf_b);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut b = GeneratorXoshiro::new(&[1, 2, 3]);
+        b.seek(GeneratorXoshiro::BASE_SIZE as u64 * 2).unwrap();
+        let mut bdata = vec![0_u8; GeneratorXoshiro::BASE_SIZE];
+        b.next(&mut bdata, 1);
+        let mut adata = vec![0_u8; GeneratorXoshiro::BASE_SIZE];
+        a.next(&mut adata, 1);
+        assert_ne!(adata, bdata);
+        a.next(&mut adata, 1);
+        assert_ne!(adata, bdata);
+        a.next(&mut adata, 1);
+        assert_eq!(adata, bdata);
+        a.next(&mut adata, 1);
+        assert_ne!(adata, bdata);
+    }
+
+    #[test]
+    fn test_seek_word() {
+        // Seeking into the middle of a block continues the stream across the block boundary.
+        let mut a = GeneratorXoshiro::new(&[1, 2, 3]);
+        let mut adata = vec![0_u8; GeneratorXoshiro::BASE_SIZE * 3];
+        a.next(&mut adata, 3);
+        for offset in [8, 24, 1000, GeneratorXoshiro::BASE_SIZE - 8] {
+            let mut b = GeneratorXoshiro::new(&[1, 2, 3]);
+            b.seek(offset as u64).unwrap();
+            let mut bdata = vec![0_u8; GeneratorXoshiro::BASE_SIZE * 2];
+            b.next(&mut bdata, 2);
+            assert_eq!(
+                adata[offset..offset + GeneratorXoshiro::BASE_SIZE * 2],
+                bdata[..]
+            );
+        }
+        assert!(a.seek(4).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
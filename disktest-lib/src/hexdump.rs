@@ -0,0 +1,170 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use std::cmp::min;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Number of bytes per hex dump line.
+const BYTES_PER_LINE: usize = 16;
+
+/// Destination of the hex dump of mismatching data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisktestHexdump {
+    /// Print the hex dump to the console.
+    Console,
+    /// Append the hex dump to a file.
+    File(PathBuf),
+}
+
+/// Find the regions of mismatching sectors plus `context` sectors around them.
+/// Overlapping and adjacent regions are merged.
+/// At most `max_sectors` mismatching sectors are considered.
+/// Returns a list of buffer ranges.
+pub fn hexdump_regions(
+    sector_size: usize,
+    context: usize,
+    read: &[u8],
+    expected: &[u8],
+    max_sectors: u64,
+) -> Vec<(usize, usize)> {
+    debug_assert_eq!(read.len(), expected.len());
+    let len = read.len();
+    let mut regions: Vec<(usize, usize)> = vec![];
+    let mut count = 0;
+    for (i, (r, e)) in read
+        .chunks(sector_size)
+        .zip(expected.chunks(sector_size))
+        .enumerate()
+    {
+        if count >= max_sectors {
+            break;
+        }
+        if r == e {
+            continue;
+        }
+        count += 1;
+
+        let begin = i.saturating_sub(context) * sector_size;
+        let end = min((i + 1 + context) * sector_size, len);
+        if let Some(last) = regions.last_mut() {
+            if begin <= last.1 {
+                last.1 = end;
+                continue;
+            }
+        }
+        regions.push((begin, end));
+    }
+    regions
+}
+
+/// Write a side by side hex dump of the expected and the read data.
+/// Differing bytes are marked with `^^` in the line below.
+pub fn hexdump_diff(
+    out: &mut dyn Write,
+    offset: u64,
+    expected: &[u8],
+    read: &[u8],
+) -> io::Result<()> {
+    debug_assert_eq!(read.len(), expected.len());
+    let hex_width = BYTES_PER_LINE * 3 - 1;
+    writeln!(out, "{:<16}  {:<hex_width$}  |  Read", "Offset", "Expected")?;
+    for (i, (e, r)) in expected
+        .chunks(BYTES_PER_LINE)
+        .zip(read.chunks(BYTES_PER_LINE))
+        .enumerate()
+    {
+        let mut exp_hex = String::with_capacity(hex_width);
+        let mut read_hex = String::with_capacity(hex_width);
+        let mut marker = String::with_capacity(hex_width);
+        for (j, (e, r)) in e.iter().zip(r).enumerate() {
+            let sep = if j == 0 { "" } else { " " };
+            let _ = write!(exp_hex, "{sep}{e:02X}");
+            let _ = write!(read_hex, "{sep}{r:02X}");
+            marker.push_str(sep);
+            marker.push_str(if e == r { "  " } else { "^^" });
+        }
+        let line_offset = offset + (i * BYTES_PER_LINE) as u64;
+        writeln!(
+            out,
+            "{line_offset:016X}  {exp_hex:<hex_width$}  |  {read_hex}"
+        )?;
+        if e != r {
+            writeln!(
+                out,
+                "{:<16}  {:<hex_width$}     {}",
+                "",
+                "",
+                marker.trim_end()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        let expected = vec![0_u8; 8 * 4];
+        let mut read = expected.clone();
+        assert!(hexdump_regions(4, 1, &read, &expected, u64::MAX).is_empty());
+
+        read[1] = 1;
+        read[13] = 1;
+        read[28] = 1;
+        assert_eq!(
+            hexdump_regions(4, 0, &read, &expected, u64::MAX),
+            vec![(0, 4), (12, 16), (28, 32)]
+        );
+        assert_eq!(
+            hexdump_regions(4, 1, &read, &expected, u64::MAX),
+            vec![(0, 20), (24, 32)]
+        );
+        assert_eq!(
+            hexdump_regions(4, 2, &read, &expected, u64::MAX),
+            vec![(0, 32)]
+        );
+        assert_eq!(hexdump_regions(4, 1, &read, &expected, 2), vec![(0, 20)]);
+    }
+
+    #[test]
+    fn test_hexdump_diff() {
+        let expected: Vec<u8> = (0..20).collect();
+        let mut read = expected.clone();
+        read[2] = 0xFF;
+        read[17] = 0xAA;
+        let mut out = vec![];
+        hexdump_diff(&mut out, 0x200, &expected, &read).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("Offset "));
+        assert_eq!(
+            lines[1],
+            "0000000000000200  00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F  \
+             |  00 01 FF 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F"
+        );
+        assert_eq!(lines[2].trim(), "^^");
+        assert_eq!(lines[2].find('^'), lines[1].find("FF"));
+        assert_eq!(
+            lines[3],
+            "0000000000000210  10 11 12 13                                      \
+             |  10 AA 12 13"
+        );
+        assert_eq!(lines[4].find('^'), lines[3].find("AA"));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
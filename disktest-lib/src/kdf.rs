@@ -24,6 +24,48 @@ fn derive_salt(key: &[u8]) -> [u8; 512 / 8] {
     salt_hash.finish().as_ref().try_into().unwrap()
 }
 
+/// Calculate the DK (derived key).
+fn derive_key(key: &[u8]) -> Vec<u8> {
+    let mut dk = vec![0; DK_SIZE];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        ITERATIONS.try_into().unwrap(),
+        &derive_salt(key),
+        key,
+        &mut dk,
+    );
+    dk
+}
+
+/// Calculate a short fingerprint of the user supplied seed.
+/// The fingerprint identifies the seed, but does not reveal it.
+pub fn seed_fingerprint(seed: &[u8]) -> u64 {
+    let mut hash = digest::Context::new(&digest::SHA512);
+    hash.update(b"disktest fingerprint");
+    hash.update(seed);
+    u64::from_le_bytes(hash.finish().as_ref()[0..8].try_into().unwrap())
+}
+
+/// Key derivation function for the per-round key of the chunk layout.
+pub fn kdf_chunk_layout(seed: &[u8], round_id: u64) -> Vec<u8> {
+    // The key is:
+    //  SEED | "C" | ROUND_ID_le64
+    let mut key = seed.to_vec();
+    key.extend_from_slice(b"C");
+    key.extend_from_slice(&round_id.to_le_bytes());
+    derive_key(&key)
+}
+
+/// Calculate the seed of one chunk of the chunk layout from the per-round key.
+/// This is cheap enough to be done for every chunk.
+pub fn chunk_seed(round_key: &[u8], chunk_index: u64) -> Vec<u8> {
+    let mut hash = digest::Context::new(&digest::SHA256);
+    hash.update(b"disktest chunk");
+    hash.update(round_key);
+    hash.update(&chunk_index.to_le_bytes());
+    hash.finish().as_ref().to_vec()
+}
+
 /// Key derivation function for the user supplied seed.
 pub fn kdf(seed: &[u8], thread_id: u32, round_id: u64) -> Vec<u8> {
     // For the first round the key is:
@@ -37,16 +79,7 @@ pub fn kdf(seed: &[u8], thread_id: u32, round_id: u64) -> Vec<u8> {
         key.extend_from_slice(&round_id.to_le_bytes());
     }
 
-    // Calculated the DK (derived key).
-    let mut dk = vec![0; DK_SIZE];
-    pbkdf2::derive(
-        pbkdf2::PBKDF2_HMAC_SHA512,
-        ITERATIONS.try_into().unwrap(),
-        &derive_salt(&key),
-        &key,
-        &mut dk,
-    );
-    dk
+    derive_key(&key)
 }
 
 #[cfg(test)]
@@ -66,6 +99,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_seed_fingerprint() {
+        assert_eq!(seed_fingerprint(&[1, 2, 3]), seed_fingerprint(&[1, 2, 3]));
+        assert_ne!(seed_fingerprint(&[1, 2, 3]), seed_fingerprint(&[1, 2, 4]));
+    }
+
+    #[test]
+    fn test_chunk_layout() {
+        let a = kdf_chunk_layout(&[1, 2, 3], 0);
+        assert_eq!(a.len(), DK_SIZE);
+        assert_ne!(a, kdf_chunk_layout(&[1, 2, 3], 1));
+        assert_ne!(a, kdf_chunk_layout(&[1, 2, 4], 0));
+        assert_ne!(a, kdf(&[1, 2, 3], 0, 0));
+
+        assert_eq!(chunk_seed(&a, 7), chunk_seed(&a, 7));
+        assert_ne!(chunk_seed(&a, 7), chunk_seed(&a, 8));
+        assert_eq!(chunk_seed(&a, 7).len(), 32);
+    }
+
     #[test]
     fn test_kdf() {
         // round 0
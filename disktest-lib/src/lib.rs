@@ -9,17 +9,40 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 //
 
+mod analyze;
+mod badmap;
+mod bench;
 mod bufcache;
 mod disktest;
+mod erase;
+mod export;
 mod generator;
+mod hexdump;
 mod kdf;
+mod probe;
+mod sample;
 mod seed;
+mod state;
 mod stream;
 mod stream_aggregator;
 mod util;
 
-pub use disktest::{Disktest, DisktestFile, DisktestQuiet, DtStreamType};
+pub use analyze::DisktestBlockOrigin;
+pub use badmap::{DisktestBadRange, DisktestBitErrors};
+pub use bench::DisktestBench;
+pub use disktest::{
+    Disktest, DisktestFile, DisktestQuiet, DtPattern, DtStreamLayout, DtStreamType,
+};
+pub use erase::{
+    DisktestDeviceIdentity, DisktestErasePass, DisktestEraseReport, DisktestSigningKey,
+    DisktestWipeMethod, DisktestWipePass,
+};
+pub use generator::{DisktestSectorTag, DtPatternBytes};
+pub use hexdump::DisktestHexdump;
+pub use probe::DisktestProbeResult;
+pub use sample::{DisktestCoverage, DisktestSampling};
 pub use seed::gen_seed_string;
-pub use util::parsebytes;
+pub use state::{DisktestPhase, DisktestState};
+pub use util::{parsebytes, prettybytes};
 
 // vim: ts=4 sw=4 expandtab
@@ -0,0 +1,312 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::util::gcd;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::collections::BTreeMap;
+
+/// Magic marker at the start of each probe block.
+const PROBE_MAGIC: &[u8; 8] = b"DTPROBE\0";
+/// Size of the probe block header: Magic, nonce and offset.
+const PROBE_HDR_SIZE: usize = PROBE_MAGIC.len() + 8 + 8;
+
+/// Minimum size of one probe block, in bytes.
+pub const PROBE_BLOCK_SIZE: u64 = 1024 * 4;
+/// Number of probe blocks at random offsets.
+pub const PROBE_NR_RANDOM: usize = 256;
+
+/// Result of reading back one probe block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeStatus {
+    /// The block has been read back correctly.
+    Good,
+    /// The block could not be written.
+    WriteError,
+    /// The block could not be read.
+    ReadError,
+    /// The block did not contain any valid probe data.
+    Corrupt,
+    /// The block contained the probe data of the block at another offset.
+    Alias(u64),
+}
+
+/// Result of a fake capacity probe run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisktestProbeResult {
+    /// The advertised size of the device, in bytes.
+    pub advertised_size: u64,
+    /// The size of one probe block, in bytes.
+    pub block_size: u64,
+    /// The number of probed blocks.
+    pub nr_probes: usize,
+    /// Offset of the highest block below `lowest_bad` that has been read back correctly.
+    pub highest_good: Option<u64>,
+    /// Offset of the lowest block that could not be written or read back correctly.
+    pub lowest_bad: Option<u64>,
+    /// Aliased addresses: Pairs of (offset, offset of the block it aliases to).
+    pub aliases: Vec<(u64, u64)>,
+    /// Address wrap-around period, if aliasing has been detected.
+    pub wrap_size: Option<u64>,
+}
+
+impl DisktestProbeResult {
+    /// Get the usable capacity that is known to be good, in bytes.
+    pub fn usable_size(&self) -> u64 {
+        self.highest_good
+            .map_or(0, |offset| offset + self.block_size)
+    }
+
+    /// Returns true, if the whole advertised capacity appears to be usable.
+    pub fn is_good(&self) -> bool {
+        self.lowest_bad.is_none()
+    }
+}
+
+/// Fill the payload of a probe block.
+fn probe_payload(nonce: u64, offset: u64, buf: &mut [u8]) {
+    let mut rng = ChaCha8Rng::seed_from_u64(nonce ^ offset.rotate_left(32));
+    rng.fill(buf);
+}
+
+/// Generate the probe block that is written to `offset`.
+/// The `nonce` uniquely identifies this probe run.
+pub fn probe_block(nonce: u64, offset: u64, buf: &mut [u8]) {
+    debug_assert!(buf.len() > PROBE_HDR_SIZE);
+    let (hdr, payload) = buf.split_at_mut(PROBE_HDR_SIZE);
+    hdr[0..8].copy_from_slice(PROBE_MAGIC);
+    hdr[8..16].copy_from_slice(&nonce.to_le_bytes());
+    hdr[16..24].copy_from_slice(&offset.to_le_bytes());
+    probe_payload(nonce, offset, payload);
+}
+
+/// Parse a probe block that has been read back.
+/// Returns the offset the block has been written to,
+/// or None, if the block is not a valid probe block of this run.
+pub fn probe_block_parse(nonce: u64, buf: &[u8]) -> Option<u64> {
+    if buf.len() <= PROBE_HDR_SIZE {
+        return None;
+    }
+    let (hdr, payload) = buf.split_at(PROBE_HDR_SIZE);
+    if hdr[0..8] != *PROBE_MAGIC || hdr[8..16] != nonce.to_le_bytes() {
+        return None;
+    }
+    let offset = u64::from_le_bytes(hdr[16..24].try_into().ok()?);
+    let mut expected = vec![0; payload.len()];
+    probe_payload(nonce, offset, &mut expected);
+    if payload == expected {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+/// Calculate the probe block offsets for a device of `size` bytes.
+/// Blocks are placed at the start and the end of the device,
+/// at and right below every power of two and at `nr_random` random offsets.
+/// The returned offsets are sorted and unique.
+pub fn probe_offsets(size: u64, block_size: u64, nr_random: usize, rng: &mut impl Rng) -> Vec<u64> {
+    let nr_blocks = size / block_size;
+    if nr_blocks == 0 {
+        return vec![];
+    }
+    let last = (nr_blocks - 1) * block_size;
+
+    let mut offsets = vec![0, last];
+    let mut pow = block_size;
+    while pow <= last {
+        offsets.push(pow - block_size);
+        offsets.push(pow);
+        let Some(next) = pow.checked_mul(2) else {
+            break;
+        };
+        pow = next;
+    }
+    for _ in 0..nr_random {
+        offsets.push(rng.random_range(0..nr_blocks) * block_size);
+    }
+
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+/// Evaluate the read back status of all probe blocks.
+///
+/// Blocks that contain the data of the same other block share the same
+/// physical storage. The blocks are written in ascending order, so the
+/// highest block of such a group is the one that has been read back.
+/// The lowest address of the group is assumed to be real storage,
+/// but it cannot be verified. All other addresses of the group are bad.
+pub fn probe_evaluate(
+    advertised_size: u64,
+    block_size: u64,
+    probes: &[(u64, ProbeStatus)],
+) -> DisktestProbeResult {
+    let mut bad = vec![];
+    let mut groups: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let mut aliases = vec![];
+    let mut wrap_size: Option<u64> = None;
+
+    for &(offset, status) in probes {
+        match status {
+            ProbeStatus::Good => (),
+            ProbeStatus::WriteError | ProbeStatus::ReadError | ProbeStatus::Corrupt => {
+                bad.push(offset);
+            }
+            ProbeStatus::Alias(other) => {
+                groups.entry(other).or_default().push(offset);
+                aliases.push((offset, other));
+                let distance = offset.abs_diff(other);
+                wrap_size = Some(wrap_size.map_or(distance, |w| gcd(w, distance)));
+            }
+        }
+    }
+
+    let mut unverified = vec![];
+    for (target, mut members) in groups {
+        members.push(target);
+        members.sort_unstable();
+        unverified.push(members[0]);
+        bad.extend_from_slice(&members[1..]);
+    }
+    let lowest_bad = bad.into_iter().min();
+
+    let highest_good = probes
+        .iter()
+        .filter(|(offset, status)| {
+            *status == ProbeStatus::Good
+                && lowest_bad.is_none_or(|lowest| *offset < lowest)
+                && !unverified.contains(offset)
+        })
+        .map(|(offset, _)| *offset)
+        .max();
+
+    DisktestProbeResult {
+        advertised_size,
+        block_size,
+        nr_probes: probes.len(),
+        highest_good,
+        lowest_bad,
+        aliases,
+        wrap_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block() {
+        let mut buf = vec![0; 512];
+        probe_block(0x1234, 0x10_0000, &mut buf);
+        assert_eq!(&buf[0..8], PROBE_MAGIC);
+        assert_eq!(probe_block_parse(0x1234, &buf), Some(0x10_0000));
+        // Wrong nonce (e.g. stale data from a previous run).
+        assert_eq!(probe_block_parse(0x1235, &buf), None);
+        // Corrupted payload.
+        buf[300] ^= 0x10;
+        assert_eq!(probe_block_parse(0x1234, &buf), None);
+        assert_eq!(probe_block_parse(0x1234, &[0; 512]), None);
+
+        // Blocks at different offsets have different payloads.
+        let mut buf2 = vec![0; 512];
+        probe_block(0x1234, 0x20_0000, &mut buf2);
+        assert_ne!(buf[PROBE_HDR_SIZE..], buf2[PROBE_HDR_SIZE..]);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let mut rng = rand::rng();
+        let offsets = probe_offsets(64 * 1024 + 100, 4096, 0, &mut rng);
+        assert_eq!(
+            offsets,
+            vec![
+                0,
+                4096,
+                2 * 4096,
+                3 * 4096,
+                4 * 4096,
+                7 * 4096,
+                8 * 4096,
+                15 * 4096
+            ]
+        );
+
+        let offsets = probe_offsets(1024 * 1024 * 1024, 4096, 100, &mut rng);
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]));
+        assert!(offsets.iter().all(|o| o % 4096 == 0));
+        assert_eq!(*offsets.last().unwrap(), 1024 * 1024 * 1024 - 4096);
+        assert!(offsets.len() > 100);
+
+        assert!(probe_offsets(4095, 4096, 10, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let bs = 4096;
+        let good = probe_evaluate(
+            16 * bs,
+            bs,
+            &[
+                (0, ProbeStatus::Good),
+                (bs, ProbeStatus::Good),
+                (15 * bs, ProbeStatus::Good),
+            ],
+        );
+        assert!(good.is_good());
+        assert_eq!(good.highest_good, Some(15 * bs));
+        assert_eq!(good.usable_size(), 16 * bs);
+        assert_eq!(good.wrap_size, None);
+
+        // Real capacity of 4 blocks that wraps around.
+        let fake = probe_evaluate(
+            16 * bs,
+            bs,
+            &[
+                (0, ProbeStatus::Alias(8 * bs)),
+                (bs, ProbeStatus::Good),
+                (2 * bs, ProbeStatus::Good),
+                (3 * bs, ProbeStatus::Alias(15 * bs)),
+                (4 * bs, ProbeStatus::Alias(8 * bs)),
+                (7 * bs, ProbeStatus::Alias(15 * bs)),
+                (8 * bs, ProbeStatus::Good),
+                (15 * bs, ProbeStatus::Good),
+            ],
+        );
+        assert!(!fake.is_good());
+        assert_eq!(fake.lowest_bad, Some(4 * bs));
+        assert_eq!(fake.highest_good, Some(2 * bs));
+        assert_eq!(fake.usable_size(), 3 * bs);
+        assert_eq!(fake.wrap_size, Some(4 * bs));
+        assert_eq!(fake.aliases.len(), 4);
+        assert_eq!(fake.aliases[0], (0, 8 * bs));
+
+        // Real capacity of 2 blocks that drops writes above.
+        let fake = probe_evaluate(
+            16 * bs,
+            bs,
+            &[
+                (0, ProbeStatus::Good),
+                (bs, ProbeStatus::Good),
+                (2 * bs, ProbeStatus::Corrupt),
+                (3 * bs, ProbeStatus::ReadError),
+                (15 * bs, ProbeStatus::Good),
+            ],
+        );
+        assert_eq!(fake.lowest_bad, Some(2 * bs));
+        assert_eq!(fake.highest_good, Some(bs));
+        assert_eq!(fake.wrap_size, None);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,228 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::util::prettybytes;
+use anyhow as ah;
+
+/// Selection of the generator chunks that are written and verified
+/// by [`Disktest::write_sampled`](crate::Disktest::write_sampled)
+/// and [`Disktest::verify_sampled`](crate::Disktest::verify_sampled).
+///
+/// The selection is based on the absolute chunk index.
+/// Therefore, write and verify select the same chunks,
+/// as long as the seed, round and sampling are the same.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisktestSampling {
+    /// The given percentage of the chunks, evenly spread over the range.
+    Percent(f64),
+    /// Every Nth chunk.
+    EveryNth(u64),
+    /// A pseudo random subset of the given percentage of the chunks.
+    /// The subset is derived from the seed and the round.
+    Random(f64),
+}
+
+/// `SplitMix64` finalizer.
+fn mix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+impl DisktestSampling {
+    /// Check the sampling parameters.
+    pub fn check(&self) -> ah::Result<()> {
+        match *self {
+            DisktestSampling::Percent(percent) | DisktestSampling::Random(percent) => {
+                if !(percent > 0.0 && percent <= 100.0) {
+                    return Err(ah::format_err!(
+                        "The sampling percentage {percent} is not in the range (0, 100]."
+                    ));
+                }
+            }
+            DisktestSampling::EveryNth(n) => {
+                if n == 0 {
+                    return Err(ah::format_err!("Sampling every 0th chunk is not possible."));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the chunk with the absolute index `chunk_index` is sampled.
+    pub fn is_selected(&self, chunk_index: u64, seed_fingerprint: u64, round_id: u64) -> bool {
+        match *self {
+            DisktestSampling::Percent(percent) => {
+                // Select a chunk each time the accumulated fraction crosses an integer.
+                let fraction = percent / 100.0;
+                ((chunk_index + 1) as f64 * fraction).ceil()
+                    > (chunk_index as f64 * fraction).ceil()
+            }
+            DisktestSampling::EveryNth(n) => chunk_index % n == 0,
+            DisktestSampling::Random(percent) => {
+                let hash = mix64(mix64(seed_fingerprint ^ mix64(round_id)) ^ chunk_index);
+                // Uniformly distributed value in [0, 1).
+                let value = (hash >> 11) as f64 / (1_u64 << 53) as f64;
+                value < percent / 100.0
+            }
+        }
+    }
+}
+
+/// Coverage of a sampled write or verify run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisktestCoverage {
+    /// The absolute byte offset of the start of the range.
+    pub begin: u64,
+    /// The absolute byte offset of the end of the range.
+    pub end: u64,
+    /// The size of one generator chunk, in bytes.
+    pub chunk_size: u64,
+    /// The number of chunks in the range.
+    pub nr_chunks: u64,
+    /// The number of chunks that have been written or verified.
+    pub nr_sampled: u64,
+    /// The number of bytes that have been written or verified.
+    pub bytes_sampled: u64,
+    /// The largest distance between two sampled areas
+    /// or between a sampled area and the range boundaries, in bytes.
+    pub max_gap: u64,
+    last_end: u64,
+}
+
+impl DisktestCoverage {
+    /// Create the coverage of an empty run over the range `begin` to `end`.
+    pub fn new(begin: u64, end: u64, chunk_size: u64) -> DisktestCoverage {
+        DisktestCoverage {
+            begin,
+            end,
+            chunk_size,
+            nr_chunks: (end - begin).div_ceil(chunk_size),
+            last_end: begin,
+            ..Default::default()
+        }
+    }
+
+    /// Account for a sampled area.
+    pub fn add(&mut self, offset: u64, length: u64) {
+        debug_assert!(offset >= self.last_end);
+        self.max_gap = self.max_gap.max(offset - self.last_end);
+        self.last_end = offset + length;
+        self.nr_sampled += 1;
+        self.bytes_sampled += length;
+    }
+
+    /// Account for the gap up to the end of the range.
+    pub fn finish(&mut self) {
+        self.max_gap = self.max_gap.max(self.end.saturating_sub(self.last_end));
+    }
+
+    /// The fraction of the range that has been sampled, in percent.
+    pub fn percent(&self) -> f64 {
+        let size = self.end - self.begin;
+        if size == 0 {
+            0.0
+        } else {
+            self.bytes_sampled as f64 * 100.0 / size as f64
+        }
+    }
+}
+
+impl std::fmt::Display for DisktestCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} chunks ({}, {:.2} % of the range from {} to {}), largest gap {}",
+            self.nr_sampled,
+            self.nr_chunks,
+            prettybytes(self.bytes_sampled, true, false, false),
+            self.percent(),
+            prettybytes(self.begin, true, false, false),
+            prettybytes(self.end, true, false, false),
+            prettybytes(self.max_gap, true, false, false),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected(sampling: DisktestSampling, count: u64) -> Vec<u64> {
+        (0..count)
+            .filter(|i| sampling.is_selected(*i, 0x1234, 0))
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(DisktestSampling::Percent(1.0).check().is_ok());
+        assert!(DisktestSampling::Percent(100.0).check().is_ok());
+        assert!(DisktestSampling::Percent(0.0).check().is_err());
+        assert!(DisktestSampling::Random(100.1).check().is_err());
+        assert!(DisktestSampling::Random(f64::NAN).check().is_err());
+        assert!(DisktestSampling::EveryNth(1).check().is_ok());
+        assert!(DisktestSampling::EveryNth(0).check().is_err());
+    }
+
+    #[test]
+    fn test_selection() {
+        assert_eq!(
+            selected(DisktestSampling::Percent(10.0), 35),
+            vec![0, 10, 20, 30]
+        );
+        assert_eq!(selected(DisktestSampling::Percent(25.0), 12), vec![0, 4, 8]);
+        assert_eq!(selected(DisktestSampling::Percent(100.0), 5).len(), 5);
+        assert!((1000..=1001).contains(&selected(DisktestSampling::Percent(1.0), 100_000).len()));
+        assert_eq!(
+            selected(DisktestSampling::EveryNth(3), 10),
+            vec![0, 3, 6, 9]
+        );
+        assert_eq!(selected(DisktestSampling::EveryNth(1), 3), vec![0, 1, 2]);
+
+        // The random subset is reproducible, but depends on the seed and the round.
+        let sampling = DisktestSampling::Random(10.0);
+        let a = selected(sampling, 10_000);
+        assert_eq!(a, selected(sampling, 10_000));
+        assert!((900..1100).contains(&a.len()));
+        let b: Vec<u64> = (0..10_000)
+            .filter(|i| sampling.is_selected(*i, 0x1235, 0))
+            .collect();
+        assert_ne!(a, b);
+        let c: Vec<u64> = (0..10_000)
+            .filter(|i| sampling.is_selected(*i, 0x1234, 1))
+            .collect();
+        assert_ne!(a, c);
+        assert_eq!(selected(DisktestSampling::Random(100.0), 100).len(), 100);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut cov = DisktestCoverage::new(1000, 11_500, 1000);
+        assert_eq!(cov.nr_chunks, 11);
+        cov.add(1000, 1000);
+        cov.add(5000, 1000);
+        cov.add(11_000, 500);
+        cov.finish();
+        assert_eq!(cov.nr_sampled, 3);
+        assert_eq!(cov.bytes_sampled, 2500);
+        assert_eq!(cov.max_gap, 5000);
+        assert!((cov.percent() - 2500.0 * 100.0 / 10_500.0).abs() < 1e-9);
+
+        let mut cov = DisktestCoverage::new(0, 10_000, 1000);
+        cov.add(0, 1000);
+        cov.finish();
+        assert_eq!(cov.max_gap, 9000);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,238 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use crate::stream_aggregator::{DtStreamLayout, DtStreamType};
+use anyhow as ah;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Magic first line of the state file.
+const STATE_MAGIC: &str = "disktest-state-v1";
+
+/// The operation a state file has been written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisktestPhase {
+    /// Writing the random data stream to the device.
+    Write,
+    /// Verifying the random data stream on the device.
+    Verify,
+}
+
+/// Checkpoint of a running write or verify operation.
+/// It contains everything needed to resume the operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisktestState {
+    /// The operation that has been running.
+    pub phase: DisktestPhase,
+    /// The round that has been running.
+    pub round_id: u64,
+    /// The absolute byte offset up to which the operation has been completed.
+    /// When writing, all data below this offset has been synced to the device.
+    /// This is always a multiple of the generator chunk size.
+    pub offset: u64,
+    /// The seek offset the operation has been started at.
+    pub seek: u64,
+    /// The `max_bytes` the operation has been started with.
+    pub max_bytes: u64,
+    /// Fingerprint of the seed. The seed itself is not stored.
+    pub seed_fingerprint: u64,
+    /// The random data stream algorithm.
+    pub algorithm: DtStreamType,
+    /// The random data stream layout.
+    pub layout: DtStreamLayout,
+    /// The number of stream threads. This affects the [`DtStreamLayout::Thread`] layout.
+    pub threads: usize,
+    /// The size of the generator chunks. This affects both layouts.
+    pub chunk_size: u64,
+}
+
+impl DisktestState {
+    /// Serialize the state into `key=value` lines.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{STATE_MAGIC}");
+        let phase = match self.phase {
+            DisktestPhase::Write => "write",
+            DisktestPhase::Verify => "verify",
+        };
+        let _ = writeln!(text, "phase={phase}");
+        let _ = writeln!(text, "round={}", self.round_id);
+        let _ = writeln!(text, "offset={}", self.offset);
+        let _ = writeln!(text, "seek={}", self.seek);
+        let _ = writeln!(text, "max_bytes={}", self.max_bytes);
+        let _ = writeln!(text, "seed_fingerprint={:016X}", self.seed_fingerprint);
+        let _ = writeln!(text, "algorithm={}", self.algorithm);
+        let layout = match self.layout {
+            DtStreamLayout::Thread => "thread",
+            DtStreamLayout::Chunk => "chunk",
+        };
+        let _ = writeln!(text, "layout={layout}");
+        let _ = writeln!(text, "threads={}", self.threads);
+        let _ = writeln!(text, "chunk_size={}", self.chunk_size);
+        text
+    }
+
+    /// Parse the state from `key=value` lines.
+    fn from_text(text: &str) -> ah::Result<DisktestState> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(STATE_MAGIC) {
+            return Err(ah::format_err!("Not a disktest state file."));
+        }
+
+        let mut phase = None;
+        let mut round_id = None;
+        let mut offset = None;
+        let mut seek = None;
+        let mut max_bytes = None;
+        let mut seed_fingerprint = None;
+        let mut algorithm = None;
+        let mut layout = None;
+        let mut threads = None;
+        let mut chunk_size = None;
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(ah::format_err!("Invalid state file line: {line}"));
+            };
+            let invalid = || ah::format_err!("Invalid state file value: {line}");
+            match key {
+                "phase" => {
+                    phase = Some(match value {
+                        "write" => DisktestPhase::Write,
+                        "verify" => DisktestPhase::Verify,
+                        _ => return Err(invalid()),
+                    });
+                }
+                "round" => round_id = Some(value.parse().map_err(|_| invalid())?),
+                "offset" => offset = Some(value.parse().map_err(|_| invalid())?),
+                "seek" => seek = Some(value.parse().map_err(|_| invalid())?),
+                "max_bytes" => max_bytes = Some(value.parse().map_err(|_| invalid())?),
+                "seed_fingerprint" => {
+                    seed_fingerprint = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?);
+                }
+                "algorithm" => algorithm = Some(value.parse().map_err(|_| invalid())?),
+                "layout" => {
+                    layout = Some(match value {
+                        "thread" => DtStreamLayout::Thread,
+                        "chunk" => DtStreamLayout::Chunk,
+                        _ => return Err(invalid()),
+                    });
+                }
+                "threads" => threads = Some(value.parse().map_err(|_| invalid())?),
+                "chunk_size" => chunk_size = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(ah::format_err!("Unknown state file key: {key}")),
+            }
+        }
+
+        let missing = |key: &str| ah::format_err!("State file: '{key}' is missing.");
+        Ok(DisktestState {
+            phase: phase.ok_or_else(|| missing("phase"))?,
+            round_id: round_id.ok_or_else(|| missing("round"))?,
+            offset: offset.ok_or_else(|| missing("offset"))?,
+            seek: seek.ok_or_else(|| missing("seek"))?,
+            max_bytes: max_bytes.ok_or_else(|| missing("max_bytes"))?,
+            seed_fingerprint: seed_fingerprint.ok_or_else(|| missing("seed_fingerprint"))?,
+            algorithm: algorithm.ok_or_else(|| missing("algorithm"))?,
+            layout: layout.ok_or_else(|| missing("layout"))?,
+            threads: threads.ok_or_else(|| missing("threads"))?,
+            chunk_size: chunk_size.ok_or_else(|| missing("chunk_size"))?,
+        })
+    }
+
+    /// Load the state from a file.
+    pub fn load(path: &Path) -> ah::Result<DisktestState> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ah::format_err!("Failed to read state file {}: {e}", path.display()))?;
+        Self::from_text(&text)
+            .map_err(|e| ah::format_err!("Failed to parse state file {}: {e}", path.display()))
+    }
+
+    /// Atomically replace the state file.
+    pub fn save(&self, path: &Path) -> ah::Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, self.to_text())
+            .and_then(|()| std::fs::rename(&tmp_path, path))
+            .map_err(|e| ah::format_err!("Failed to write state file {}: {e}", path.display()))
+    }
+
+    /// Get the number of bytes that are left to process from `offset`
+    /// to the end of the original range.
+    pub fn bytes_left(&self) -> u64 {
+        if self.max_bytes == u64::MAX {
+            u64::MAX
+        } else {
+            self.seek
+                .saturating_add(self.max_bytes)
+                .saturating_sub(self.offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> DisktestState {
+        DisktestState {
+            phase: DisktestPhase::Verify,
+            round_id: 3,
+            offset: 1024 * 1024 * 6,
+            seek: 1024 * 1024 * 3,
+            max_bytes: 1024 * 1024 * 9,
+            seed_fingerprint: 0x0123_4567_89AB_CDEF,
+            algorithm: DtStreamType::Crc,
+            layout: DtStreamLayout::Chunk,
+            threads: 4,
+            chunk_size: 1024 * 1024 * 3,
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let a = state();
+        let text = a.to_text();
+        assert!(text.starts_with("disktest-state-v1\nphase=verify\nround=3\n"));
+        assert!(text.contains("seed_fingerprint=0123456789ABCDEF\n"));
+        assert!(text.contains("chunk_size=3145728\n"));
+        assert_eq!(DisktestState::from_text(&text).unwrap(), a);
+
+        assert!(DisktestState::from_text("foo\nphase=write\n").is_err());
+        assert!(DisktestState::from_text(&text.replace("phase=verify\n", "")).is_err());
+        assert!(DisktestState::from_text(&text.replace("chunk_size=", "chunk_size=x")).is_err());
+        assert!(DisktestState::from_text(&text.replace("=crc", "=foo")).is_err());
+        assert!(DisktestState::from_text(&text.replace("round=", "foo=")).is_err());
+    }
+
+    #[test]
+    fn test_bytes_left() {
+        let mut a = state();
+        assert_eq!(a.bytes_left(), 1024 * 1024 * 6);
+        a.max_bytes = u64::MAX;
+        assert_eq!(a.bytes_left(), u64::MAX);
+    }
+
+    #[test]
+    fn test_file() {
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("state");
+        let a = state();
+        a.save(&path).unwrap();
+        assert_eq!(DisktestState::load(&path).unwrap(), a);
+        assert!(DisktestState::load(&tdir.path().join("foo")).is_err());
+        tdir.close().unwrap();
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -9,17 +9,15 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 //
 
-use crate::bufcache::{BufCache, BufCacheCons};
+use crate::bufcache::{AlignedBuf, BufCache, BufCacheCons};
 use crate::generator::{
-    GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCrc, NextRandom,
+    DtPattern, GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCrc,
+    GeneratorPattern, GeneratorTagged, GeneratorXoshiro, NextRandom,
 };
-use crate::kdf::kdf;
+use crate::kdf::{chunk_seed, kdf, kdf_chunk_layout, seed_fingerprint};
 use anyhow as ah;
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
-use std::sync::mpsc::{Receiver, Sender, channel};
-use std::sync::{Arc, Condvar, Mutex};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread;
 
 /// Random data stream algorithm type.
@@ -34,15 +32,197 @@ pub enum DtStreamType {
     ChaCha20,
     /// Very fast by cryptographically unsecure CRC based random number generator.
     Crc,
+    /// Even faster, cryptographically unsecure xoshiro256++ based random number generator.
+    Xoshiro,
+    /// Self-describing sectors: Every 512 byte sector starts with a header
+    /// containing its absolute byte offset, the round, a seed fingerprint
+    /// and a checksum, followed by `ChaCha20` payload.
+    /// This allows verification to tell what a bad sector contains instead.
+    Tagged,
+    /// Deterministic fixed test pattern. The seed is not used.
+    Pattern(DtPattern),
 }
 
+impl std::fmt::Display for DtStreamType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DtStreamType::ChaCha8 => write!(f, "chacha8"),
+            DtStreamType::ChaCha12 => write!(f, "chacha12"),
+            DtStreamType::ChaCha20 => write!(f, "chacha20"),
+            DtStreamType::Crc => write!(f, "crc"),
+            DtStreamType::Xoshiro => write!(f, "xoshiro"),
+            DtStreamType::Tagged => write!(f, "tagged"),
+            DtStreamType::Pattern(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+
+impl std::str::FromStr for DtStreamType {
+    type Err = ah::Error;
+
+    /// Parse the algorithm name, as written by `Display`.
+    /// The name is case insensitive.
+    fn from_str(s: &str) -> ah::Result<DtStreamType> {
+        let name = s.trim().to_ascii_lowercase();
+        Ok(match name.as_str() {
+            "chacha8" => DtStreamType::ChaCha8,
+            "chacha12" => DtStreamType::ChaCha12,
+            "chacha20" => DtStreamType::ChaCha20,
+            "crc" => DtStreamType::Crc,
+            "xoshiro" => DtStreamType::Xoshiro,
+            "tagged" => DtStreamType::Tagged,
+            "checkerboard" => DtStreamType::Pattern(DtPattern::Checkerboard),
+            "walking1" => DtStreamType::Pattern(DtPattern::WalkingOnes),
+            "walking0" => DtStreamType::Pattern(DtPattern::WalkingZeros),
+            _ => {
+                let pattern = name
+                    .strip_prefix("pattern:")
+                    .ok_or_else(|| ah::format_err!("Unknown algorithm '{s}'."))?;
+                DtStreamType::Pattern(DtPattern::from_hex(pattern).ok_or_else(|| {
+                    ah::format_err!(
+                        "Invalid pattern '{pattern}'. \
+                         Expected 1 to {} hexadecimal bytes.",
+                        crate::generator::DtPatternBytes::MAX_LEN
+                    )
+                })?)
+            }
+        })
+    }
+}
+
+/// Layout of the random data stream on the device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum DtStreamLayout {
+    /// Every thread generates its own stream from a per-thread key.
+    /// The threads' streams are interleaved chunk by chunk.
+    /// The data depends on the number of threads.
+    /// This is the layout of disktest versions before the chunk layout was introduced.
+    #[default]
+    Thread,
+    /// Every chunk is generated from a per-chunk key.
+    /// The data only depends on the seed, the round and the chunk index.
+    /// The threads only decide who computes which chunk.
+    /// Therefore, the number of threads may differ between write and verify.
+    Chunk,
+}
+
+/// Work, that the worker thread runs in between the computation of the chunks.
+pub type DtStreamJob = Box<dyn FnOnce() + Send>;
+
 /// Data chunk that contains the computed PRNG data.
 pub struct DtStreamChunk {
-    pub data: Option<Vec<u8>>,
+    pub data: Option<AlignedBuf>,
     #[cfg(test)]
     pub index: u8,
 }
 
+/// State of the hand-off between the worker thread and the main thread.
+#[derive(Default)]
+struct RingState {
+    /// The computed chunks, that have not been taken by the main thread, yet.
+    chunks: VecDeque<DtStreamChunk>,
+    /// The jobs, that the worker thread shall run.
+    jobs: VecDeque<DtStreamJob>,
+    /// The maximum number of chunks in the ring.
+    max_level: usize,
+    /// The low watermark for thread wakeup.
+    lo_level: usize,
+    /// The ring has been filled up.
+    /// The worker thread waits until the fill level drops to the low watermark.
+    full: bool,
+    /// The worker thread shall terminate.
+    abort: bool,
+    /// The worker thread terminated due to an error.
+    error: bool,
+}
+
+/// Bounded ring of chunks between the worker thread and the main thread.
+#[derive(Default)]
+struct Ring {
+    state: Mutex<RingState>,
+    /// Wakes the main thread, if a chunk has been pushed or the worker thread failed.
+    chunk_avail: Condvar,
+    /// Wakes the worker thread, if it has work to do or shall terminate.
+    wake: Condvar,
+}
+
+impl Ring {
+    fn lock(&self) -> MutexGuard<'_, RingState> {
+        self.state.lock().expect("Ring lock poison")
+    }
+
+    /// Reset the ring for a new worker thread with up to `max_level` chunks.
+    fn reset(&self, max_level: usize, lo_level: usize) {
+        let mut state = self.lock();
+        state.chunks.clear();
+        state.max_level = max_level;
+        state.lo_level = lo_level;
+        state.full = false;
+        state.abort = false;
+        state.error = false;
+    }
+
+    /// Tell the worker thread to terminate.
+    fn abort(&self) {
+        self.lock().abort = true;
+        self.wake.notify_one();
+    }
+
+    /// Mark the worker thread as failed and wake the main thread.
+    fn set_error(&self) {
+        self.lock().error = true;
+        self.chunk_avail.notify_all();
+    }
+
+    /// Push a computed chunk and wake the main thread.
+    fn push_chunk(&self, chunk: DtStreamChunk) {
+        let mut state = self.lock();
+        state.chunks.push_back(chunk);
+        if state.chunks.len() >= state.max_level {
+            state.full = true;
+        }
+        drop(state);
+        self.chunk_avail.notify_one();
+    }
+
+    /// Take the oldest chunk from the locked `state`.
+    /// Wakes the worker thread, if the fill level is low.
+    fn pop_chunk(&self, state: &mut RingState) -> Option<DtStreamChunk> {
+        let chunk = state.chunks.pop_front()?;
+        if state.full && state.chunks.len() <= state.lo_level {
+            state.full = false;
+            self.wake.notify_one();
+        }
+        Some(chunk)
+    }
+}
+
+/// Work for the worker thread.
+enum WorkerTask {
+    Job(DtStreamJob),
+    Chunk,
+    Abort,
+}
+
+/// Wait until the worker thread has something to do.
+fn wait_worker_task(ring: &Ring) -> WorkerTask {
+    let mut state = ring.lock();
+    loop {
+        if state.abort {
+            break WorkerTask::Abort;
+        }
+        // Run the pending jobs first. The main thread is waiting for them.
+        if let Some(job) = state.jobs.pop_front() {
+            break WorkerTask::Job(job);
+        }
+        if !state.full {
+            break WorkerTask::Chunk;
+        }
+        // The ring is full. Wait...
+        state = ring.wake.wait(state).expect("Ring Condvar wait poison");
+    }
+}
+
 /// Try to lower the thread priority.
 fn try_lower_thread_priority() {
     // SAFETY: nice (2) does not affect memory safety.
@@ -52,43 +232,120 @@ fn try_lower_thread_priority() {
     }
 }
 
+/// Get the base size of the generator algorithm.
+fn generator_base_size(stype: DtStreamType) -> usize {
+    match stype {
+        DtStreamType::ChaCha8 => GeneratorChaCha8::BASE_SIZE,
+        DtStreamType::ChaCha12 => GeneratorChaCha12::BASE_SIZE,
+        DtStreamType::ChaCha20 => GeneratorChaCha20::BASE_SIZE,
+        DtStreamType::Crc => GeneratorCrc::BASE_SIZE,
+        DtStreamType::Xoshiro => GeneratorXoshiro::BASE_SIZE,
+        DtStreamType::Tagged => GeneratorTagged::BASE_SIZE,
+        DtStreamType::Pattern(_) => GeneratorPattern::BASE_SIZE,
+    }
+}
+
+/// Construct the generator algorithm.
+fn new_generator(
+    stype: DtStreamType,
+    seed: &[u8],
+    fingerprint: u64,
+    round_id: u64,
+    thread_id: u32,
+    num_threads: u32,
+    chunk_factor: usize,
+) -> Box<dyn NextRandom> {
+    match stype {
+        DtStreamType::ChaCha8 => Box::new(GeneratorChaCha8::new(seed)),
+        DtStreamType::ChaCha12 => Box::new(GeneratorChaCha12::new(seed)),
+        DtStreamType::ChaCha20 => Box::new(GeneratorChaCha20::new(seed)),
+        DtStreamType::Crc => Box::new(GeneratorCrc::new(seed)),
+        DtStreamType::Xoshiro => Box::new(GeneratorXoshiro::new(seed)),
+        DtStreamType::Tagged => Box::new(GeneratorTagged::new(
+            seed,
+            fingerprint,
+            round_id,
+            thread_id,
+            num_threads,
+            chunk_factor,
+        )),
+        DtStreamType::Pattern(pattern) => Box::new(GeneratorPattern::new(
+            pattern,
+            thread_id,
+            num_threads,
+            chunk_factor,
+        )),
+    }
+}
+
+/// Construct the generator for one chunk of the chunk layout.
+fn new_chunk_generator(
+    stype: DtStreamType,
+    round_key: &[u8],
+    fingerprint: u64,
+    round_id: u64,
+    chunk_index: u64,
+    chunk_factor: usize,
+) -> ah::Result<Box<dyn NextRandom>> {
+    let seed = chunk_seed(round_key, chunk_index);
+    let mut generator = new_generator(stype, &seed, fingerprint, round_id, 0, 1, chunk_factor);
+    if matches!(stype, DtStreamType::Tagged | DtStreamType::Pattern(_)) {
+        // These generators derive the absolute offsets from their position.
+        let chunk_size = generator.get_base_size() * chunk_factor;
+        generator.seek(chunk_index * chunk_size as u64)?;
+    }
+    Ok(generator)
+}
+
 /// Thread worker function, that computes the chunks.
 #[allow(clippy::too_many_arguments)]
 fn thread_worker(
     stype: DtStreamType,
+    layout: DtStreamLayout,
     chunk_factor: usize,
     seed: Vec<u8>,
     thread_id: u32,
+    num_threads: u32,
     round_id: u64,
     mut cache_cons: BufCacheCons,
     byte_offset: u64,
     invert_pattern: bool,
-    abort: &AtomicBool,
-    error: &AtomicBool,
-    level: &AtomicIsize,
-    sleep: &(Mutex<bool>, Condvar),
-    tx: &Sender<DtStreamChunk>,
+    ring: &Ring,
 ) {
-    // Calculate the per-thread-seed from the global seed.
-    let thread_seed = kdf(&seed, thread_id, round_id);
-    drop(seed);
+    let fingerprint = seed_fingerprint(&seed);
+    let chunk_size = generator_base_size(stype) * chunk_factor;
+
+    let (mut generator, round_key) = match layout {
+        DtStreamLayout::Thread => {
+            // Calculate the per-thread-seed from the global seed.
+            let thread_seed = kdf(&seed, thread_id, round_id);
+            let mut generator = new_generator(
+                stype,
+                &thread_seed,
+                fingerprint,
+                round_id,
+                thread_id,
+                num_threads,
+                chunk_factor,
+            );
 
-    // Construct the generator algorithm.
-    let mut generator: Box<dyn NextRandom> = match stype {
-        DtStreamType::ChaCha8 => Box::new(GeneratorChaCha8::new(&thread_seed)),
-        DtStreamType::ChaCha12 => Box::new(GeneratorChaCha12::new(&thread_seed)),
-        DtStreamType::ChaCha20 => Box::new(GeneratorChaCha20::new(&thread_seed)),
-        DtStreamType::Crc => Box::new(GeneratorCrc::new(&thread_seed)),
+            // Seek the generator to the specified byte offset.
+            if let Err(e) = generator.seek(byte_offset) {
+                eprintln!("ERROR in generator thread {thread_id}: {e}");
+                ring.set_error();
+                return;
+            }
+            (Some(generator), None)
+        }
+        DtStreamLayout::Chunk => {
+            // The per-chunk seeds are derived from the per-round key.
+            (None, Some(kdf_chunk_layout(&seed, round_id)))
+        }
     };
+    drop(seed);
 
-    // Seek the generator to the specified byte offset.
-    if let Err(e) = generator.seek(byte_offset) {
-        eprintln!("ERROR in generator thread {thread_id}: {e}");
-        error.store(true, Ordering::Relaxed);
-        return;
-    }
-
-    let chunk_size = generator.get_base_size() * chunk_factor;
+    // The index of the next chunk within this thread's stream.
+    let mut iteration = byte_offset / chunk_size as u64;
 
     // Try to lower the thread priority
     // to give the main thread a better chance to run.
@@ -97,42 +354,60 @@ fn thread_worker(
     // Run the generator work loop.
     #[cfg(test)]
     let mut index = 0;
-    let mut cur_level = level.load(Ordering::Relaxed);
-    while !abort.load(Ordering::Acquire) {
-        if cur_level < DtStream::MAX_THRES {
-            // Get the next chunk from the generator.
-            let mut data = cache_cons.pull(chunk_size);
-            generator.next(&mut data, chunk_factor);
-            debug_assert_eq!(data.len(), chunk_size);
-
-            // Invert the bit pattern, if requested.
-            if invert_pattern {
-                for x in &mut data {
-                    *x ^= 0xFF_u8;
+    loop {
+        match wait_worker_task(ring) {
+            WorkerTask::Abort => break,
+            WorkerTask::Job(job) => job(),
+            WorkerTask::Chunk => {
+                // In chunk layout every chunk gets a fresh generator.
+                if let Some(round_key) = round_key.as_ref() {
+                    let chunk_index = iteration * u64::from(num_threads) + u64::from(thread_id);
+                    match new_chunk_generator(
+                        stype,
+                        round_key,
+                        fingerprint,
+                        round_id,
+                        chunk_index,
+                        chunk_factor,
+                    ) {
+                        Ok(g) => generator = Some(g),
+                        Err(e) => {
+                            eprintln!("ERROR in generator thread {thread_id}: {e}");
+                            ring.set_error();
+                            return;
+                        }
+                    }
+                }
+                iteration += 1;
+
+                // Get the next chunk from the generator.
+                let mut data = cache_cons.pull(chunk_size);
+                generator
+                    .as_mut()
+                    .expect("Worker thread: No generator.")
+                    .next(&mut data, chunk_factor);
+                debug_assert_eq!(data.len(), chunk_size);
+
+                // Invert the bit pattern, if requested.
+                if invert_pattern {
+                    for x in data.iter_mut() {
+                        *x ^= 0xFF_u8;
+                    }
                 }
-            }
 
-            let chunk = DtStreamChunk {
-                data: Some(data),
+                let chunk = DtStreamChunk {
+                    data: Some(data),
+                    #[cfg(test)]
+                    index,
+                };
                 #[cfg(test)]
-                index,
-            };
-            #[cfg(test)]
-            {
-                index = index.wrapping_add(1);
-            }
+                {
+                    index = index.wrapping_add(1);
+                }
 
-            // Send the chunk to the main thread.
-            tx.send(chunk).expect("Worker thread: Send failed.");
-            cur_level = level.fetch_add(1, Ordering::Relaxed) + 1;
-        } else {
-            // The chunk buffer is full. Wait...
-            let mut sleeping = sleep.0.lock().expect("Thread Condvar lock poison");
-            *sleeping = true;
-            while *sleeping {
-                sleeping = sleep.1.wait(sleeping).expect("Thread Condvar wait poison");
+                // Hand the chunk to the main thread.
+                ring.push_chunk(chunk);
             }
-            cur_level = level.load(Ordering::Relaxed);
         }
     }
 }
@@ -140,74 +415,79 @@ fn thread_worker(
 /// PRNG stream.
 pub struct DtStream {
     stype: DtStreamType,
+    layout: DtStreamLayout,
     seed: Vec<u8>,
     invert_pattern: bool,
     thread_id: u32,
+    num_threads: u32,
     round_id: u64,
-    rx: Option<Receiver<DtStreamChunk>>,
-    cache: Rc<RefCell<BufCache>>,
+    cache: Arc<Mutex<BufCache>>,
     is_active: bool,
     thread_join: Option<thread::JoinHandle<()>>,
-    abort: Arc<AtomicBool>,
-    error: Arc<AtomicBool>,
-    level: Arc<AtomicIsize>,
-    sleep: Arc<(Mutex<bool>, Condvar)>,
+    ring: Arc<Ring>,
+    queue_depth: usize,
 }
 
 impl DtStream {
     /// Maximum number of chunks that the thread will compute in advance.
-    const MAX_THRES: isize = 10;
+    pub const MAX_THRES: usize = 10;
     /// Low watermark for thread wakeup.
-    const LO_THRES: isize = 6;
+    const LO_THRES: usize = 6;
 
     pub fn new(
         stype: DtStreamType,
         seed: Vec<u8>,
         invert_pattern: bool,
         thread_id: u32,
+        num_threads: u32,
         round_id: u64,
-        cache: Rc<RefCell<BufCache>>,
+        cache: Arc<Mutex<BufCache>>,
     ) -> DtStream {
-        let abort = Arc::new(AtomicBool::new(false));
-        let error = Arc::new(AtomicBool::new(false));
-        let level = Arc::new(AtomicIsize::new(0));
-        let sleep = Arc::new((Mutex::new(false), Condvar::new()));
         DtStream {
             stype,
+            layout: DtStreamLayout::default(),
             seed,
             invert_pattern,
             thread_id,
+            num_threads,
             round_id,
-            rx: None,
             cache,
             is_active: false,
             thread_join: None,
-            abort,
-            error,
-            level,
-            sleep,
+            ring: Arc::new(Ring::default()),
+            queue_depth: DtStream::MAX_THRES,
         }
     }
 
-    /// Wake up the worker thread, if it is currently sleeping.
-    fn wake_thread(&self) {
-        let mut sleeping = self.sleep.0.lock().expect("Wake Condvar lock poison");
-        if *sleeping {
-            *sleeping = false;
-            self.sleep.1.notify_one();
-        }
+    /// Set the number of chunks that the thread will compute in advance.
+    /// The default is [`DtStream::MAX_THRES`].
+    /// The new depth takes effect on the next activation.
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.queue_depth = depth.max(1);
+    }
+
+    /// Select the stream layout.
+    /// The new layout takes effect on the next activation.
+    pub fn set_layout(&mut self, layout: DtStreamLayout) {
+        self.layout = layout;
     }
 
     /// Stop the worker thread.
     /// Does nothing, if the thread is not running.
     fn stop(&mut self) {
         self.is_active = false;
-        self.abort.store(true, Ordering::Release);
-        self.wake_thread();
+        self.ring.abort();
         if let Some(thread_join) = self.thread_join.take() {
             thread_join.join().expect("Thread join failed");
         }
-        self.abort.store(false, Ordering::Release);
+        // Run the remaining jobs in the calling thread.
+        loop {
+            let job = self.ring.lock().jobs.pop_front();
+            let Some(job) = job else {
+                break;
+            };
+            job();
+        }
     }
 
     /// Spawn the worker thread.
@@ -217,40 +497,38 @@ impl DtStream {
         assert!(self.thread_join.is_none());
 
         // Initialize thread communication
-        self.abort.store(false, Ordering::Release);
-        self.error.store(false, Ordering::Release);
-        self.level.store(0, Ordering::Release);
-        let (tx, rx) = channel();
-        self.rx = Some(rx);
+        let lo_level = self.queue_depth * DtStream::LO_THRES / DtStream::MAX_THRES;
+        self.ring.reset(self.queue_depth, lo_level);
 
         // Spawn the worker thread.
         let thread_stype = self.stype;
+        let thread_layout = self.layout;
         let thread_chunk_factor = chunk_factor;
         let thread_seed = self.seed.to_vec();
         let thread_id = self.thread_id;
+        let thread_num_threads = self.num_threads;
         let thread_round_id = self.round_id;
-        let thread_cache_cons = self.cache.borrow_mut().new_consumer(self.thread_id);
+        let thread_cache_cons = self
+            .cache
+            .lock()
+            .expect("BufCache lock poison")
+            .new_consumer(self.thread_id);
         let thread_byte_offset = byte_offset;
         let thread_invert_pattern = self.invert_pattern;
-        let thread_abort = Arc::clone(&self.abort);
-        let thread_error = Arc::clone(&self.error);
-        let thread_level = Arc::clone(&self.level);
-        let thread_sleep = Arc::clone(&self.sleep);
+        let thread_ring = Arc::clone(&self.ring);
         self.thread_join = Some(thread::spawn(move || {
             thread_worker(
                 thread_stype,
+                thread_layout,
                 thread_chunk_factor,
                 thread_seed,
                 thread_id,
+                thread_num_threads,
                 thread_round_id,
                 thread_cache_cons,
                 thread_byte_offset,
                 thread_invert_pattern,
-                &thread_abort,
-                &thread_error,
-                &thread_level,
-                &thread_sleep,
-                &tx,
+                &thread_ring,
             );
         }));
         self.is_active = true;
@@ -258,8 +536,8 @@ impl DtStream {
 
     /// Check if the thread exited due to an error.
     #[inline]
-    fn is_thread_error(&self) -> bool {
-        self.error.load(Ordering::Relaxed)
+    pub fn is_thread_error(&self) -> bool {
+        self.ring.lock().error
     }
 
     /// Activate the worker thread.
@@ -278,12 +556,7 @@ impl DtStream {
 
     /// Get the chunk base size.
     pub fn get_chunk_size(&self) -> usize {
-        match self.stype {
-            DtStreamType::ChaCha8 => GeneratorChaCha8::BASE_SIZE,
-            DtStreamType::ChaCha12 => GeneratorChaCha12::BASE_SIZE,
-            DtStreamType::ChaCha20 => GeneratorChaCha20::BASE_SIZE,
-            DtStreamType::Crc => GeneratorCrc::BASE_SIZE,
-        }
+        generator_base_size(self.stype)
     }
 
     /// Get the default chunk factor of the selected generator.
@@ -293,35 +566,44 @@ impl DtStream {
             DtStreamType::ChaCha12 => GeneratorChaCha12::DEFAULT_CHUNK_FACTOR,
             DtStreamType::ChaCha20 => GeneratorChaCha20::DEFAULT_CHUNK_FACTOR,
             DtStreamType::Crc => GeneratorCrc::DEFAULT_CHUNK_FACTOR,
+            DtStreamType::Xoshiro => GeneratorXoshiro::DEFAULT_CHUNK_FACTOR,
+            DtStreamType::Tagged => GeneratorTagged::DEFAULT_CHUNK_FACTOR,
+            DtStreamType::Pattern(_) => GeneratorPattern::DEFAULT_CHUNK_FACTOR,
         }
     }
 
-    /// Get the next chunk from the thread.
-    /// Returns None, if no chunk is available, yet.
-    #[inline]
-    pub fn get_chunk(&mut self) -> ah::Result<Option<DtStreamChunk>> {
+    /// Let the worker thread run a job before it computes the next chunk.
+    /// The job is run right away in the calling thread, if the worker thread is not running.
+    pub fn submit_job(&self, job: DtStreamJob) {
         if !self.is_active() {
-            return Err(ah::format_err!("Generator stream is not active."));
+            job();
+            return;
         }
-        if self.is_thread_error() {
-            return Err(ah::format_err!(
-                "Generator stream thread aborted with an error."
-            ));
+        self.ring.lock().jobs.push_back(job);
+        self.ring.wake.notify_one();
+    }
+
+    /// Wait for the next chunk from the thread.
+    pub fn wait_chunk(&mut self) -> ah::Result<DtStreamChunk> {
+        if !self.is_active() {
+            return Err(ah::format_err!("Generator stream is not active."));
         }
-        let Some(rx) = &self.rx else {
-            return Err(ah::format_err!("Generator stream RX channel not present."));
-        };
-        let Ok(chunk) = rx.try_recv() else {
-            // Queue is empty. Wake thread.
-            self.wake_thread();
-            return Ok(None);
-        };
-        if self.level.fetch_sub(1, Ordering::Relaxed) - 1 <= DtStream::LO_THRES {
-            // Queue fill level is low. Wake thread.
-            self.wake_thread();
+        let mut state = self.ring.lock();
+        loop {
+            if state.error {
+                return Err(ah::format_err!(
+                    "Generator stream thread aborted with an error."
+                ));
+            }
+            if let Some(chunk) = self.ring.pop_chunk(&mut state) {
+                break Ok(chunk);
+            }
+            state = self
+                .ring
+                .chunk_avail
+                .wait(state)
+                .expect("Ring Condvar wait poison");
         }
-        // We got a chunk.
-        Ok(Some(chunk))
     }
 }
 
@@ -337,21 +619,10 @@ mod tests {
     use crate::disktest::DisktestQuiet;
     use std::time::Duration;
 
-    impl DtStream {
-        pub fn wait_chunk(&mut self) -> DtStreamChunk {
-            loop {
-                if let Some(chunk) = self.get_chunk().unwrap() {
-                    break chunk;
-                }
-                thread::sleep(Duration::from_millis(1));
-            }
-        }
-    }
-
     fn run_base_test(algorithm: DtStreamType) {
         println!("stream base test");
-        let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut s = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 0, cache);
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
+        let mut s = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 1, 0, cache);
         s.activate(0, s.get_default_chunk_factor()).unwrap();
         assert!(s.is_active());
 
@@ -360,15 +631,15 @@ mod tests {
 
         let mut results_first = vec![];
         for count in 0..5 {
-            let chunk = s.wait_chunk();
+            let chunk = s.wait_chunk().unwrap();
             println!(
                 "{}: index={} data[0]={} (current level = {})",
                 count,
                 chunk.index,
-                chunk.data.as_ref().unwrap()[0],
-                s.level.load(Ordering::Relaxed)
+                chunk.data.as_deref().unwrap()[0],
+                s.ring.lock().chunks.len()
             );
-            results_first.push(chunk.data.as_ref().unwrap()[0]);
+            results_first.push(chunk.data.as_deref().unwrap()[0]);
             assert_eq!(chunk.index, count);
         }
         match algorithm {
@@ -384,43 +655,99 @@ mod tests {
             DtStreamType::Crc => {
                 assert_eq!(results_first, vec![108, 99, 114, 196, 213]);
             }
+            DtStreamType::Xoshiro => {
+                assert_eq!(results_first, vec![153, 1, 157, 225, 217]);
+            }
+            DtStreamType::Tagged => {
+                // Every sector starts with the magic.
+                assert_eq!(results_first, vec![b'D'; 5]);
+            }
+            DtStreamType::Pattern(pattern) => {
+                // Every chunk starts at a multiple of the pattern period.
+                let mut generator = GeneratorPattern::new(pattern, 0, 1, 1);
+                let mut buf = vec![0; GeneratorPattern::BASE_SIZE];
+                generator.next(&mut buf, 1);
+                assert_eq!(results_first, vec![buf[0]; 5]);
+            }
         }
     }
 
+    fn run_backpressure_test(algorithm: DtStreamType) {
+        println!("stream backpressure test");
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
+        let mut s = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 1, 0, cache);
+        s.activate(0, s.get_default_chunk_factor()).unwrap();
+        let wait_level = |s: &DtStream, level| {
+            while s.ring.lock().chunks.len() < level {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(50));
+            s.ring.lock().chunks.len()
+        };
+
+        // The thread fills the ring up to the maximum.
+        assert_eq!(wait_level(&s, DtStream::MAX_THRES), DtStream::MAX_THRES);
+
+        // It does not refill before the low watermark is reached.
+        for _ in 0..DtStream::MAX_THRES - DtStream::LO_THRES - 1 {
+            s.wait_chunk().unwrap();
+        }
+        assert_eq!(wait_level(&s, 0), DtStream::LO_THRES + 1);
+        s.wait_chunk().unwrap();
+        assert_eq!(wait_level(&s, DtStream::MAX_THRES), DtStream::MAX_THRES);
+
+        // The chunks continue in order.
+        let index = DtStream::MAX_THRES - DtStream::LO_THRES;
+        assert_eq!(usize::from(s.wait_chunk().unwrap().index), index);
+
+        // A reduced queue depth computes less chunks in advance.
+        s.set_queue_depth(2);
+        s.activate(0, s.get_default_chunk_factor()).unwrap();
+        assert_eq!(wait_level(&s, 2), 2);
+        s.wait_chunk().unwrap();
+        assert_eq!(wait_level(&s, 2), 2);
+    }
+
     fn run_offset_test(algorithm: DtStreamType) {
         println!("stream offset test");
         // a: start at chunk offset 0
-        let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut a = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 0, cache);
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
+        let mut a = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 1, 0, cache);
         a.activate(0, a.get_default_chunk_factor()).unwrap();
 
         // b: start at chunk offset 1
-        let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut b = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 0, cache);
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
+        let mut b = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 1, 0, cache);
         b.activate(
             a.get_chunk_size() as u64 * a.get_default_chunk_factor() as u64,
             a.get_default_chunk_factor(),
         )
         .unwrap();
 
-        let achunk = a.wait_chunk();
-        let bchunk = b.wait_chunk();
-        assert_ne!(achunk.data.as_ref().unwrap(), bchunk.data.as_ref().unwrap());
-        let achunk = a.wait_chunk();
-        assert_eq!(achunk.data.as_ref().unwrap(), bchunk.data.as_ref().unwrap());
+        let achunk = a.wait_chunk().unwrap();
+        let bchunk = b.wait_chunk().unwrap();
+        assert_ne!(
+            achunk.data.as_deref().unwrap(),
+            bchunk.data.as_deref().unwrap()
+        );
+        let achunk = a.wait_chunk().unwrap();
+        assert_eq!(
+            achunk.data.as_deref().unwrap(),
+            bchunk.data.as_deref().unwrap()
+        );
     }
 
     fn run_invert_test(algorithm: DtStreamType) {
         println!("stream invert test");
-        let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut a = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 0, cache);
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
+        let mut a = DtStream::new(algorithm, vec![1, 2, 3], false, 0, 1, 0, cache);
         a.activate(0, a.get_default_chunk_factor()).unwrap();
-        let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut b = DtStream::new(algorithm, vec![1, 2, 3], true, 0, 0, cache);
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
+        let mut b = DtStream::new(algorithm, vec![1, 2, 3], true, 0, 1, 0, cache);
         b.activate(0, a.get_default_chunk_factor()).unwrap();
 
-        let achunk = a.wait_chunk();
-        let bchunk = b.wait_chunk();
+        let achunk = a.wait_chunk().unwrap();
+        let bchunk = b.wait_chunk().unwrap();
         let inv_bchunk: Vec<u8> = bchunk
             .data
             .as_ref()
@@ -428,14 +755,18 @@ mod tests {
             .iter()
             .map(|x| x ^ 0xFF)
             .collect();
-        assert_ne!(achunk.data.as_ref().unwrap(), bchunk.data.as_ref().unwrap());
-        assert_eq!(achunk.data.as_ref().unwrap(), &inv_bchunk);
+        assert_ne!(
+            achunk.data.as_deref().unwrap(),
+            bchunk.data.as_deref().unwrap()
+        );
+        assert_eq!(achunk.data.as_deref().unwrap(), &inv_bchunk);
     }
 
     #[test]
     fn test_chacha8() {
         let alg = DtStreamType::ChaCha8;
         run_base_test(alg);
+        run_backpressure_test(alg);
         run_offset_test(alg);
         run_invert_test(alg);
     }
@@ -463,6 +794,66 @@ mod tests {
         run_offset_test(alg);
         run_invert_test(alg);
     }
+
+    #[test]
+    fn test_xoshiro() {
+        let alg = DtStreamType::Xoshiro;
+        run_base_test(alg);
+        run_offset_test(alg);
+        run_invert_test(alg);
+    }
+
+    #[test]
+    fn test_tagged() {
+        let alg = DtStreamType::Tagged;
+        run_base_test(alg);
+        run_offset_test(alg);
+        run_invert_test(alg);
+    }
+
+    #[test]
+    fn test_pattern() {
+        // The patterns repeat in every chunk. Therefore, there is no offset test.
+        for pattern in [
+            DtPattern::Byte(0xAA),
+            DtPattern::Checkerboard,
+            DtPattern::WalkingZeros,
+        ] {
+            let alg = DtStreamType::Pattern(pattern);
+            run_base_test(alg);
+            run_invert_test(alg);
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        for alg in [
+            DtStreamType::ChaCha8,
+            DtStreamType::ChaCha12,
+            DtStreamType::ChaCha20,
+            DtStreamType::Crc,
+            DtStreamType::Xoshiro,
+            DtStreamType::Tagged,
+            DtStreamType::Pattern(DtPattern::Byte(0x55)),
+            DtStreamType::Pattern(DtPattern::from_hex("0123456789").unwrap()),
+            DtStreamType::Pattern(DtPattern::Checkerboard),
+            DtStreamType::Pattern(DtPattern::WalkingOnes),
+            DtStreamType::Pattern(DtPattern::WalkingZeros),
+        ] {
+            assert_eq!(alg.to_string().parse::<DtStreamType>().unwrap(), alg);
+        }
+        assert_eq!(
+            "ChaCha8".parse::<DtStreamType>().unwrap(),
+            DtStreamType::ChaCha8
+        );
+        assert_eq!(
+            "PATTERN:FF".parse::<DtStreamType>().unwrap(),
+            DtStreamType::Pattern(DtPattern::Byte(0xFF))
+        );
+        assert!("foo".parse::<DtStreamType>().is_err());
+        assert!("pattern:".parse::<DtStreamType>().is_err());
+        assert!("pattern:f".parse::<DtStreamType>().is_err());
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
@@ -9,21 +9,23 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 //
 
-use crate::bufcache::BufCache;
+use crate::bufcache::{AlignedBuf, BufCache};
 use crate::disktest::DisktestQuiet;
 use crate::stream::{DtStream, DtStreamChunk};
-use crate::util::prettybytes;
+use crate::util::{gcd, prettybytes};
 use anyhow::{self as ah, Context as _};
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-pub use crate::stream::DtStreamType;
+pub use crate::generator::DtPattern;
+pub use crate::stream::{DtStreamLayout, DtStreamType};
 
 pub struct DtStreamAggChunk {
     chunk: DtStreamChunk,
     thread_id: usize,
-    cache: Rc<RefCell<BufCache>>,
+    cache: Arc<Mutex<BufCache>>,
 }
 
 impl DtStreamAggChunk {
@@ -35,6 +37,12 @@ impl DtStreamAggChunk {
     }
 }
 
+impl AsRef<[u8]> for DtStreamAggChunk {
+    fn as_ref(&self) -> &[u8] {
+        self.get_data()
+    }
+}
+
 impl Drop for DtStreamAggChunk {
     fn drop(&mut self) {
         // Recycle the buffer.
@@ -44,21 +52,55 @@ impl Drop for DtStreamAggChunk {
             .take()
             .expect("DtStreamChunk data was None during drop!");
         let thread_id: u32 = self.thread_id.try_into().expect("thread_id overflow");
-        self.cache.borrow_mut().push(thread_id, buf);
+        self.cache
+            .lock()
+            .expect("BufCache lock poison")
+            .push(thread_id, buf);
     }
 }
 
+/// Result of the comparison of read data to a generated chunk.
+pub struct DtStreamAggCompare {
+    /// The generated chunk.
+    pub chunk: DtStreamAggChunk,
+    /// The read data.
+    pub buffer: AlignedBuf,
+    /// The number of compared bytes at the start of the buffer.
+    pub len: usize,
+    /// The compared bytes are equal.
+    pub equal: bool,
+}
+
 pub struct DtStreamAggActivateResult {
     pub byte_offset: u64,
     pub chunk_size: u64,
+    /// The number of chunks that each thread computes in advance.
+    pub queue_depth: usize,
+    /// The number of chunk sized buffers that are held outside of the generator threads.
+    pub extra_chunks: usize,
+    /// The maximum memory used by the generator buffers and the extra buffers.
+    pub memory: u64,
 }
 
+/// Minimum number of chunks that each thread computes in advance with a memory limit.
+const MIN_QUEUE_DEPTH: usize = 2;
+
 pub struct DtStreamAgg {
+    stype: DtStreamType,
+    seed: Vec<u8>,
+    round_id: u64,
+    invert_pattern: bool,
+    layout: DtStreamLayout,
     num_threads: usize,
     streams: Vec<DtStream>,
-    cache: Rc<RefCell<BufCache>>,
+    cache: Arc<Mutex<BufCache>>,
     current_index: usize,
+    compare_index: usize,
+    compares: VecDeque<Receiver<DtStreamAggCompare>>,
     is_active: bool,
+    chunk_size: Option<u64>,
+    max_memory: Option<u64>,
+    extra_chunks: usize,
     quiet_level: DisktestQuiet,
 }
 
@@ -74,7 +116,7 @@ impl DtStreamAgg {
         assert!(num_threads > 0);
         assert!(num_threads <= u16::MAX as usize + 1);
 
-        let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
+        let cache = Arc::new(Mutex::new(BufCache::new(DisktestQuiet::Normal)));
         let mut streams = Vec::with_capacity(num_threads);
         for i in 0..num_threads {
             let stream = DtStream::new(
@@ -82,33 +124,121 @@ impl DtStreamAgg {
                 seed.to_vec(),
                 invert_pattern,
                 i.try_into().expect("num_threads overflow"),
+                num_threads.try_into().expect("num_threads overflow"),
                 round_id,
-                Rc::clone(&cache),
+                Arc::clone(&cache),
             );
             streams.push(stream);
         }
 
         DtStreamAgg {
+            stype,
+            seed: seed.to_vec(),
+            round_id,
+            invert_pattern,
+            layout: DtStreamLayout::default(),
             num_threads,
             streams,
             cache,
             current_index: 0,
+            compare_index: 0,
+            compares: VecDeque::new(),
             is_active: false,
+            chunk_size: None,
+            max_memory: None,
+            extra_chunks: 0,
             quiet_level,
         }
     }
 
-    fn calc_chunk_size(&self, sector_size: u32) -> ah::Result<(u64, u64)> {
-        let chunk_factor: u64 = self
-            .get_default_chunk_factor()
-            .try_into()
-            .context("chunk_factor overflow")?;
+    /// Set the size of the chunks, which is the size of the disk I/O transfers.
+    /// The size is rounded to a multiple of the generator base size and the sector size.
+    /// `None` selects the default chunk size of the generator.
+    /// The data depends on the chunk size.
+    /// The new size takes effect on the next activation.
+    pub fn set_chunk_size(&mut self, chunk_size: Option<u64>) {
+        self.chunk_size = chunk_size;
+    }
+
+    /// Limit the memory used by the generator buffers of all threads
+    /// and by the extra buffers set with [`DtStreamAgg::set_extra_chunks`].
+    /// The number of chunks computed in advance is reduced to stay within the limit.
+    /// The chunk size is never changed, because the stream depends on it.
+    /// The new limit takes effect on the next activation.
+    pub fn set_max_memory(&mut self, max_memory: Option<u64>) {
+        self.max_memory = max_memory;
+    }
+
+    /// Set the number of chunk sized buffers that are held outside of the generator threads.
+    /// For example the buffers in the I/O queue and in pending comparisons.
+    /// They are counted in the memory limit.
+    /// The new number takes effect on the next activation.
+    pub fn set_extra_chunks(&mut self, extra_chunks: usize) {
+        self.extra_chunks = extra_chunks;
+    }
+
+    /// Select the stream layout.
+    /// The new layout takes effect on the next activation.
+    pub fn set_layout(&mut self, layout: DtStreamLayout) {
+        self.layout = layout;
+        for stream in &mut self.streams {
+            stream.set_layout(layout);
+        }
+    }
+
+    /// Create a new inactive stream aggregator that generates the same stream.
+    /// This can be used to generate a second part of the stream at the same time.
+    pub fn fork(&self) -> DtStreamAgg {
+        let mut agg = DtStreamAgg::new(
+            self.stype,
+            &self.seed,
+            self.round_id,
+            self.invert_pattern,
+            self.num_threads,
+            self.quiet_level,
+        );
+        agg.set_layout(self.layout);
+        agg.set_chunk_size(self.chunk_size);
+        agg.set_max_memory(self.max_memory);
+        agg.set_extra_chunks(self.extra_chunks);
+        agg
+    }
+
+    /// Calculate the chunk size, the chunk factor and the queue depth of the streams.
+    fn calc_chunk_size(&self, sector_size: u32) -> ah::Result<(u64, u64, usize)> {
         let base_chunk_size: u64 = self
             .get_chunk_size()
             .try_into()
             .context("chunk_size overflow")?;
         let sector_size: u64 = sector_size.into();
 
+        let chunk_factor: u64 = if let Some(chunk_size) = self.chunk_size {
+            // Round to the nearest multiple of the base size and the sector size.
+            let align = base_chunk_size / gcd(base_chunk_size, sector_size) * sector_size;
+            let rounded = chunk_size
+                .checked_add(align / 2)
+                .context("chunk_size overflow")?
+                / align
+                * align;
+            let rounded = rounded.max(align);
+            if rounded != chunk_size && self.quiet_level < DisktestQuiet::NoWarn {
+                eprintln!(
+                    "WARNING: The chunk size {} is not a multiple of the generator base size {} \
+                    and the sector size {}. \n\
+                    The chunk size will be adjusted to {}.",
+                    prettybytes(chunk_size, true, true, true),
+                    prettybytes(base_chunk_size, true, true, true),
+                    prettybytes(sector_size, true, true, true),
+                    prettybytes(rounded, true, true, true)
+                );
+            }
+            rounded / base_chunk_size
+        } else {
+            self.get_default_chunk_factor()
+                .try_into()
+                .context("chunk_factor overflow")?
+        };
+
         let chunk_size = base_chunk_size * chunk_factor;
 
         if chunk_size % sector_size != 0 {
@@ -118,7 +248,32 @@ impl DtStreamAgg {
             ));
         }
 
-        Ok((chunk_size, chunk_factor))
+        let Some(max_memory) = self.max_memory else {
+            return Ok((chunk_size, chunk_factor, DtStream::MAX_THRES));
+        };
+        // Each thread holds the queued chunks and the chunk that is being computed.
+        let num_threads = self.num_threads as u64;
+        let extra_chunks = self.extra_chunks as u64;
+        let depth = ((max_memory / chunk_size).saturating_sub(extra_chunks) / num_threads)
+            .saturating_sub(1);
+        if depth < MIN_QUEUE_DEPTH as u64 {
+            return Err(ah::format_err!(
+                "The memory limit of {} is too small for {} generator threads \
+                 with a chunk size of {}. At least {} are needed. \
+                 Please select a smaller chunk size (--chunk-size) or fewer threads.",
+                prettybytes(max_memory, true, true, true),
+                self.num_threads,
+                prettybytes(chunk_size, true, true, true),
+                prettybytes(
+                    (num_threads * (MIN_QUEUE_DEPTH as u64 + 1) + extra_chunks) * chunk_size,
+                    true,
+                    true,
+                    true
+                )
+            ));
+        }
+        let depth = depth.min(DtStream::MAX_THRES as u64);
+        Ok((chunk_size, chunk_factor, depth.try_into()?))
     }
 
     pub fn activate(
@@ -126,7 +281,16 @@ impl DtStreamAgg {
         mut byte_offset: u64,
         sector_size: u32,
     ) -> ah::Result<DtStreamAggActivateResult> {
-        let (chunk_size, chunk_factor) = self.calc_chunk_size(sector_size)?;
+        self.cancel_compares();
+        let (chunk_size, chunk_factor, queue_depth) = self.calc_chunk_size(sector_size)?;
+        self.cache
+            .lock()
+            .expect("BufCache lock poison")
+            .set_max_bufs(if self.max_memory.is_some() {
+                queue_depth + 1
+            } else {
+                usize::MAX
+            });
         let num_threads: u64 = self
             .num_threads
             .try_into()
@@ -162,6 +326,7 @@ impl DtStreamAgg {
                 iteration * chunk_size
             };
 
+            stream.set_queue_depth(queue_depth);
             stream.activate(
                 thread_offset,
                 chunk_factor.try_into().context("chunk_factor overflow")?,
@@ -172,6 +337,10 @@ impl DtStreamAgg {
         Ok(DtStreamAggActivateResult {
             byte_offset,
             chunk_size,
+            queue_depth,
+            extra_chunks: self.extra_chunks,
+            memory: (num_threads * (queue_depth as u64 + 1) + self.extra_chunks as u64)
+                * chunk_size,
         })
     }
 
@@ -188,42 +357,99 @@ impl DtStreamAgg {
         self.streams[0].get_default_chunk_factor()
     }
 
-    #[inline]
-    fn get_chunk(&mut self) -> ah::Result<Option<DtStreamAggChunk>> {
-        debug_assert!(self.is_active());
+    pub fn wait_chunk(&mut self) -> ah::Result<DtStreamAggChunk> {
+        assert!(
+            self.is_active(),
+            "wait_chunk() called, but stream aggregator is stopped."
+        );
 
-        // Try to get a chunk.
-        let Some(chunk) = self.streams[self.current_index].get_chunk()? else {
-            return Ok(None);
-        };
+        // Wait for the chunk of the current stream.
+        let thread_id = self.current_index;
+        let chunk = self.streams[thread_id].wait_chunk()?;
         // Got one. Switch to next stream.
         self.current_index = (self.current_index + 1) % self.num_threads;
 
-        Ok(Some(DtStreamAggChunk {
+        Ok(DtStreamAggChunk {
             chunk,
-            thread_id: self.current_index,
-            cache: Rc::clone(&self.cache),
-        }))
+            thread_id,
+            cache: Arc::clone(&self.cache),
+        })
     }
 
-    pub fn wait_chunk(&mut self) -> ah::Result<DtStreamAggChunk> {
+    /// Compare the first `len` bytes of the read `buffer` to the generated `chunk`.
+    /// The comparison is done by one of the generator threads.
+    /// The results are returned by [`DtStreamAgg::wait_compare`] in the order of submission.
+    pub fn submit_compare(&mut self, chunk: DtStreamAggChunk, buffer: AlignedBuf, len: usize) {
         assert!(
             self.is_active(),
-            "wait_chunk() called, but stream aggregator is stopped."
+            "submit_compare() called, but stream aggregator is stopped."
         );
+        let (tx, rx) = channel();
+        self.streams[self.compare_index].submit_job(Box::new(move || {
+            let equal = buffer[..len] == chunk.get_data()[..len];
+            let _ = tx.send(DtStreamAggCompare {
+                chunk,
+                buffer,
+                len,
+                equal,
+            });
+        }));
+        self.compares.push_back(rx);
+        self.compare_index = (self.compare_index + 1) % self.num_threads;
+    }
+
+    /// Get the number of submitted comparisons that have not been returned, yet.
+    pub fn compares_pending(&self) -> usize {
+        self.compares.len()
+    }
+
+    /// Wait for the oldest submitted comparison to finish.
+    /// Returns None, if no comparison is pending.
+    pub fn wait_compare(&mut self) -> ah::Result<Option<DtStreamAggCompare>> {
+        let Some(rx) = self.compares.front() else {
+            return Ok(None);
+        };
         loop {
-            if let Some(chunk) = self.get_chunk()? {
-                break Ok(chunk);
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(result) => {
+                    self.compares.pop_front();
+                    break Ok(Some(result));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.streams.iter().any(DtStream::is_thread_error) {
+                        break Err(ah::format_err!(
+                            "Generator stream thread aborted with an error."
+                        ));
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    break Err(ah::format_err!("Comparison job has been dropped."));
+                }
             }
-            std::thread::sleep(Duration::from_millis(1));
         }
     }
+
+    /// Wait for all pending comparisons and discard their results.
+    /// The chunks are recycled while the stream threads are still running.
+    fn cancel_compares(&mut self) {
+        while let Ok(Some(_)) = self.wait_compare() {}
+        self.compares.clear();
+    }
+}
+
+impl Drop for DtStreamAgg {
+    fn drop(&mut self) {
+        self.cancel_compares();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::generator::{GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCrc};
+    use crate::generator::{
+        GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCrc, GeneratorTagged,
+        GeneratorXoshiro,
+    };
 
     #[allow(clippy::cast_possible_truncation)]
     #[allow(clippy::cast_sign_loss)]
@@ -343,6 +569,143 @@ mod tests {
         }
     }
 
+    fn run_chunk_layout_test(algorithm: DtStreamType) {
+        println!("stream aggregator chunk layout test");
+        let new_agg = |num_threads, layout| {
+            let mut agg = DtStreamAgg::new(
+                algorithm,
+                &[1, 2, 3],
+                0,
+                false,
+                num_threads,
+                DisktestQuiet::Normal,
+            );
+            agg.set_layout(layout);
+            agg
+        };
+
+        // Reference data generated by one thread.
+        let mut a = new_agg(1, DtStreamLayout::Chunk);
+        a.activate(0, 512).unwrap();
+        let reference: Vec<Vec<u8>> = (0..8)
+            .map(|_| a.wait_chunk().unwrap().get_data().to_vec())
+            .collect();
+        assert_ne!(reference[0], reference[1]);
+
+        // The data does not depend on the number of threads and the seek offset.
+        let chunk_size = (a.get_chunk_size() * a.get_default_chunk_factor()) as u64;
+        for num_threads in [2, 3] {
+            for offset in [0, 1, 5] {
+                let mut b = new_agg(num_threads, DtStreamLayout::Chunk);
+                b.activate(chunk_size * offset as u64, 512).unwrap();
+                for expected in &reference[offset..] {
+                    assert_eq!(b.wait_chunk().unwrap().get_data(), &expected[..]);
+                }
+            }
+        }
+
+        // The thread layout differs.
+        let mut c = new_agg(1, DtStreamLayout::Thread);
+        c.activate(0, 512).unwrap();
+        let thread_chunk = c.wait_chunk().unwrap().get_data().to_vec();
+        assert_ne!(thread_chunk, reference[0]);
+
+        // A fork generates the same stream in the same layout.
+        for (agg, expected) in [(&a, &reference[0]), (&c, &thread_chunk)] {
+            let mut fork = agg.fork();
+            fork.activate(0, 512).unwrap();
+            assert_eq!(fork.wait_chunk().unwrap().get_data(), &expected[..]);
+        }
+    }
+
+    fn run_compare_test(algorithm: DtStreamType) {
+        println!("stream aggregator compare test");
+        let new_agg = || {
+            let mut agg =
+                DtStreamAgg::new(algorithm, &[1, 2, 3], 0, false, 2, DisktestQuiet::Normal);
+            agg.activate(0, 512).unwrap();
+            agg
+        };
+        let mut a = new_agg();
+        let mut b = new_agg();
+        let chunk_size = a.get_chunk_size() * a.get_default_chunk_factor();
+
+        // The comparisons are returned in the order of submission.
+        for i in 0..6 {
+            let mut buffer = AlignedBuf::new(chunk_size);
+            buffer.copy_from_slice(b.wait_chunk().unwrap().get_data());
+            if i % 3 == 1 {
+                buffer[0] ^= 1;
+            }
+            let chunk = a.wait_chunk().unwrap();
+            a.submit_compare(chunk, buffer, chunk_size - i);
+        }
+        assert_eq!(a.compares_pending(), 6);
+        for i in 0..6 {
+            let cmp = a.wait_compare().unwrap().unwrap();
+            assert_eq!(cmp.len, chunk_size - i);
+            assert_eq!(cmp.equal, i % 3 != 1);
+        }
+        assert_eq!(a.compares_pending(), 0);
+        assert!(a.wait_compare().unwrap().is_none());
+    }
+
+    fn run_memory_test(algorithm: DtStreamType) {
+        println!("stream aggregator memory test");
+        let num_threads = 2;
+        let mut agg = DtStreamAgg::new(
+            algorithm,
+            &[1, 2, 3],
+            0,
+            false,
+            num_threads,
+            DisktestQuiet::NoWarn,
+        );
+        let chunk_size = (agg.get_chunk_size() * agg.get_default_chunk_factor()) as u64;
+        let threads = num_threads as u64;
+
+        // No limit.
+        let res = agg.activate(0, 512).unwrap();
+        assert_eq!(res.chunk_size, chunk_size);
+        assert_eq!(res.queue_depth, DtStream::MAX_THRES);
+        assert_eq!(res.memory, threads * 11 * chunk_size);
+
+        // The queue depth is reduced first.
+        agg.set_max_memory(Some(threads * 4 * chunk_size + 1));
+        let res = agg.activate(0, 512).unwrap();
+        assert_eq!(res.chunk_size, chunk_size);
+        assert_eq!(res.queue_depth, 3);
+        assert_eq!(res.memory, threads * 4 * chunk_size);
+        assert_eq!(
+            agg.wait_chunk().unwrap().get_data().len() as u64,
+            chunk_size
+        );
+
+        // The buffers outside of the generator threads are counted, too.
+        agg.set_extra_chunks(2);
+        let res = agg.activate(0, 512).unwrap();
+        assert_eq!(res.queue_depth, 2);
+        assert_eq!(res.extra_chunks, 2);
+        assert_eq!(res.memory, (threads * 3 + 2) * chunk_size);
+        assert_eq!(
+            agg.fork().activate(0, 512).unwrap().memory,
+            (threads * 3 + 2) * chunk_size
+        );
+        agg.set_extra_chunks(0);
+
+        // The chunk size is never reduced, because the stream depends on it.
+        agg.set_max_memory(Some(threads * 3 * chunk_size - 1));
+        assert!(agg.activate(0, 512).is_err());
+        agg.set_chunk_size(Some(chunk_size / 2));
+        let res = agg.activate(0, 512).unwrap();
+        assert_eq!(res.chunk_size, chunk_size / 2);
+        assert_eq!(res.queue_depth, 4);
+
+        // Too small.
+        agg.set_max_memory(Some(1024));
+        assert!(agg.activate(0, 512).is_err());
+    }
+
     #[test]
     fn test_chacha8() {
         let alg = DtStreamType::ChaCha8;
@@ -352,6 +715,9 @@ mod tests {
             GeneratorChaCha8::DEFAULT_CHUNK_FACTOR,
         );
         run_offset_test(alg);
+        run_chunk_layout_test(alg);
+        run_compare_test(alg);
+        run_memory_test(alg);
     }
 
     #[test]
@@ -363,6 +729,7 @@ mod tests {
             GeneratorChaCha12::DEFAULT_CHUNK_FACTOR,
         );
         run_offset_test(alg);
+        run_chunk_layout_test(alg);
     }
 
     #[test]
@@ -374,6 +741,7 @@ mod tests {
             GeneratorChaCha20::DEFAULT_CHUNK_FACTOR,
         );
         run_offset_test(alg);
+        run_chunk_layout_test(alg);
     }
 
     #[test]
@@ -385,6 +753,32 @@ mod tests {
             GeneratorCrc::DEFAULT_CHUNK_FACTOR,
         );
         run_offset_test(alg);
+        run_chunk_layout_test(alg);
+    }
+
+    #[test]
+    fn test_xoshiro() {
+        let alg = DtStreamType::Xoshiro;
+        run_base_test(
+            alg,
+            GeneratorXoshiro::BASE_SIZE,
+            GeneratorXoshiro::DEFAULT_CHUNK_FACTOR,
+        );
+        run_offset_test(alg);
+        run_chunk_layout_test(alg);
+    }
+
+    #[test]
+    fn test_tagged() {
+        // The sector headers are not evenly distributed.
+        // Therefore, skip the base test.
+        let alg = DtStreamType::Tagged;
+        assert_eq!(
+            DtStreamAgg::new(alg, &[1, 2, 3], 0, false, 1, DisktestQuiet::Normal).get_chunk_size(),
+            GeneratorTagged::BASE_SIZE
+        );
+        run_offset_test(alg);
+        run_chunk_layout_test(alg);
     }
 }
 
@@ -190,6 +190,14 @@ pub fn fold(input: &[u8], output_size: usize) -> Vec<u8> {
     output
 }
 
+/// Greatest common divisor.
+pub fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,191 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use super::{
+    RawIo, RawIoResult,
+    linux::{transfer_at, transfer_result},
+};
+use anyhow as ah;
+use std::{
+    fs::File,
+    io,
+    sync::mpsc::{Receiver, Sender, SyncSender, channel, sync_channel},
+    thread,
+};
+
+/// Pointer to the data of a transfer.
+struct DataPtr(*mut u8);
+
+// SAFETY: The pointer points into the boxed buffer of its transfer.
+// The buffer is moved between the threads together with the pointer.
+unsafe impl Send for DataPtr {}
+
+/// A transfer for the I/O thread.
+struct Transfer<B> {
+    buffer: Box<B>,
+    data: DataPtr,
+    offset: u64,
+    len: usize,
+    write: bool,
+}
+
+/// I/O thread worker function.
+/// Does the transfers one after the other and sends them back with the result.
+fn io_thread<B>(
+    mut file: File,
+    direct_align: Option<usize>,
+    rx: &Receiver<Transfer<B>>,
+    tx: &Sender<(Transfer<B>, io::Result<usize>)>,
+) {
+    while let Ok(transfer) = rx.recv() {
+        // SAFETY: data points to len bytes of the buffer,
+        // which is owned by this thread until the transfer is sent back.
+        // This thread is the only one that transfers data through the queue.
+        let result = unsafe {
+            transfer_at(
+                &mut file,
+                direct_align,
+                transfer.write,
+                transfer.offset,
+                transfer.data.0,
+                transfer.len,
+            )
+        };
+        if tx.send((transfer, result)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Queued raw device I/O for Linux in a dedicated I/O thread.
+/// The transfers are done one after the other,
+/// while the caller prepares the next buffers or processes the previous ones.
+pub struct RawIoThread<B> {
+    tx: Option<SyncSender<Transfer<B>>>,
+    rx: Receiver<(Transfer<B>, io::Result<usize>)>,
+    thread: Option<thread::JoinHandle<()>>,
+    depth: usize,
+    pending: usize,
+}
+
+impl<B: Send + 'static> RawIoThread<B> {
+    /// Start an I/O thread with up to `depth` buffers queued.
+    pub fn new(io: &RawIo, depth: usize) -> ah::Result<Self> {
+        if io.is_chr() {
+            return Err(ah::format_err!(
+                "Queued I/O is not supported for character devices"
+            ));
+        }
+        if depth == 0 {
+            return Err(ah::format_err!("The I/O queue depth must not be zero"));
+        }
+        let file = io.try_clone_file()?;
+        let direct_align = io.direct_align();
+        let (tx, thread_rx) = sync_channel(depth);
+        let (thread_tx, rx) = channel();
+        let thread = thread::Builder::new()
+            .name("disktest-io".to_string())
+            .spawn(move || io_thread(file, direct_align, &thread_rx, &thread_tx))?;
+        Ok(Self {
+            tx: Some(tx),
+            rx,
+            thread: Some(thread),
+            depth,
+            pending: 0,
+        })
+    }
+
+    /// Get the number of pending transfers.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Check whether `depth` transfers are queued.
+    pub fn is_full(&self) -> bool {
+        self.pending >= self.depth
+    }
+
+    /// Queue a transfer of `len` bytes at `data` in `buffer`.
+    fn submit(
+        &mut self,
+        write: bool,
+        offset: u64,
+        buffer: Box<B>,
+        data: *mut u8,
+        len: usize,
+    ) -> ah::Result<()> {
+        if self.is_full() {
+            return Err(ah::format_err!("The I/O queue is full."));
+        }
+        let transfer = Transfer {
+            buffer,
+            data: DataPtr(data),
+            offset,
+            len,
+            write,
+        };
+        let tx = self.tx.as_ref().expect("I/O thread: No sender");
+        if tx.send(transfer).is_err() {
+            return Err(ah::format_err!("The I/O thread has terminated."));
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Queue a write of the first `len` bytes of `buffer` at the file `offset`.
+    pub fn submit_write(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsRef<[u8]>,
+    {
+        if len > buffer.as_ref().len() {
+            return Err(ah::format_err!("Transfer length exceeds the buffer."));
+        }
+        let buffer = Box::new(buffer);
+        let data = (*buffer).as_ref().as_ptr().cast_mut();
+        self.submit(true, offset, buffer, data, len)
+    }
+
+    /// Queue a read of `len` bytes into `buffer` from the file `offset`.
+    pub fn submit_read(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsMut<[u8]>,
+    {
+        let mut buffer = Box::new(buffer);
+        if len > (*buffer).as_mut().len() {
+            return Err(ah::format_err!("Transfer length exceeds the buffer."));
+        }
+        let data = (*buffer).as_mut().as_mut_ptr();
+        self.submit(false, offset, buffer, data, len)
+    }
+
+    /// Wait for the oldest transfer to complete.
+    pub fn complete(&mut self) -> Option<(B, ah::Result<RawIoResult>)> {
+        if self.pending == 0 {
+            return None;
+        }
+        let (transfer, result) = self.rx.recv().expect("The I/O thread has terminated.");
+        self.pending -= 1;
+        Some((*transfer.buffer, transfer_result(transfer.write, result)))
+    }
+}
+
+impl<B> Drop for RawIoThread<B> {
+    fn drop(&mut self) {
+        // The I/O thread finishes the queued transfers and terminates.
+        // The buffers are dropped together with the receiver.
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
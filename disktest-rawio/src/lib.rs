@@ -22,19 +22,63 @@ use std::path::Path;
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod linux;
 
+#[cfg(target_os = "linux")]
+mod iothread;
+
+#[cfg(target_os = "linux")]
+mod queue;
+
+#[cfg(target_os = "linux")]
+mod uring;
+
+#[cfg(not(target_os = "linux"))]
+mod noqueue;
+
 #[cfg(target_os = "windows")]
 mod windows;
 
 pub const DEFAULT_SECTOR_SIZE: u32 = 512;
 
+/// Buffer address alignment for direct I/O.
+/// This is the page size, which satisfies all common sector sizes.
+pub const DIRECT_IO_ALIGN: usize = 4096;
+
+/// Sector and I/O sizes of a raw device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawIoGeometry {
+    /// The smallest unit the device can address.
+    pub logical_sector_size: u32,
+    /// The smallest unit the device can write without a read-modify-write cycle.
+    pub physical_sector_size: u32,
+    /// The preferred minimum I/O size, if reported by the device.
+    pub min_io_size: Option<u32>,
+    /// The optimal I/O size, if reported by the device.
+    pub opt_io_size: Option<u32>,
+}
+
 /// OS interface for raw I/O.
 pub trait RawIoOsIntf: Sized {
     /// Open a file or device.
     fn new(path: &Path, create: bool, read: bool, write: bool) -> ah::Result<Self>;
 
+    /// Get the sector and I/O sizes of the file or device.
+    /// Returns None, if this is not a raw device.
+    fn get_geometry(&self) -> Option<RawIoGeometry>;
+
     /// Get the physical sector size of the file or device.
     /// Returns None, if this is not a raw device.
-    fn get_sector_size(&self) -> Option<u32>;
+    fn get_sector_size(&self) -> Option<u32> {
+        self.get_geometry().map(|g| g.physical_sector_size)
+    }
+
+    /// Get the size of the file or device, in bytes.
+    fn get_size(&mut self) -> ah::Result<u64>;
+
+    /// Enable or disable direct I/O that bypasses the operating system caches.
+    /// Returns an error, if the file or device does not support direct I/O.
+    /// Buffers should be aligned to [`DIRECT_IO_ALIGN`] for direct I/O.
+    /// Unaligned transfers are done with cached I/O.
+    fn set_direct(&mut self, direct: bool) -> ah::Result<()>;
 
     /// Close the file, flush all buffers and drop all caches.
     /// This function ensures that subsequent reads are not read from RAM cache.
@@ -62,6 +106,46 @@ pub trait RawIoOsIntf: Sized {
     fn write(&mut self, buffer: &[u8]) -> ah::Result<RawIoResult>;
 }
 
+/// OS interface for queued raw I/O.
+///
+/// Transfers are done at absolute file offsets and do not move the file position.
+/// They complete in the order of submission.
+/// The queue owns the buffer of a transfer until the transfer has completed.
+/// The file must not be read or written otherwise while transfers are pending.
+pub trait RawIoQueueIntf<B: Send + 'static>: Sized {
+    /// Create a queue for the opened file or device `io`
+    /// with up to `depth` transfers in flight in the kernel.
+    /// Returns an error, if asynchronous I/O is not available.
+    fn new(io: &RawIo, depth: usize) -> ah::Result<Self>;
+
+    /// Create a queue for the opened file or device `io`
+    /// that does one transfer at a time in a dedicated I/O thread.
+    /// Up to `depth` transfers are queued for the thread.
+    /// Returns an error, if the I/O thread is not available.
+    fn new_thread(io: &RawIo, depth: usize) -> ah::Result<Self>;
+
+    /// Get the number of transfers that have been submitted, but not completed, yet.
+    fn pending(&self) -> usize;
+
+    /// Check whether the maximum number of transfers is in flight.
+    fn is_full(&self) -> bool;
+
+    /// Submit a write of the first `len` bytes of `buffer` at the file `offset`.
+    fn submit_write(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsRef<[u8]>;
+
+    /// Submit a read of `len` bytes into the start of `buffer` from the file `offset`.
+    fn submit_read(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsMut<[u8]>;
+
+    /// Wait for the oldest transfer to complete and return its buffer and result.
+    /// A read returns less than the requested number of bytes only at the end of the file.
+    /// Returns None, if no transfer is pending.
+    fn complete(&mut self) -> Option<(B, ah::Result<RawIoResult>)>;
+}
+
 /// Raw I/O operation result code.
 pub enum RawIoResult {
     /// Ok, number of processed bytes.
@@ -76,4 +160,10 @@ pub use crate::linux::RawIoLinux as RawIo;
 #[cfg(target_os = "windows")]
 pub use crate::windows::RawIoWindows as RawIo;
 
+#[cfg(target_os = "linux")]
+pub use crate::queue::RawIoLinuxQueue as RawIoQueue;
+
+#[cfg(not(target_os = "linux"))]
+pub use crate::noqueue::RawIoNoQueue as RawIoQueue;
+
 // vim: ts=4 sw=4 expandtab
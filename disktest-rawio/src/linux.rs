@@ -9,13 +9,16 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 //
 
-use super::{RawIoOsIntf, RawIoResult};
+use super::{DIRECT_IO_ALIGN, RawIoGeometry, RawIoOsIntf, RawIoResult};
 use anyhow::{self as ah, Context as _};
-use libc::{POSIX_FADV_DONTNEED, c_int};
+use libc::{POSIX_FADV_DONTNEED, c_int, c_uint};
 use std::{
     fs::{File, OpenOptions, metadata},
-    io::{Read as _, Seek as _, SeekFrom, Write as _},
-    os::unix::{fs::MetadataExt as _, io::AsRawFd as _},
+    io::{ErrorKind, Read as _, Seek as _, SeekFrom, Write as _},
+    os::unix::{
+        fs::{FileExt as _, MetadataExt as _},
+        io::AsRawFd as _,
+    },
     path::{Path, PathBuf},
 };
 
@@ -26,6 +29,122 @@ const S_IFCHR: u32 = libc::S_IFCHR as u32;
 #[allow(clippy::unnecessary_cast)]
 const S_IFMT: u32 = libc::S_IFMT as u32;
 
+/// Set or clear the `O_DIRECT` flag of an open file.
+fn set_o_direct(file: &File, direct: bool) -> ah::Result<()> {
+    // SAFETY: The fcntl call is safe, because:
+    // - The raw file descriptor is valid. (Closing sets self.file to None).
+    // - F_GETFL only fetches the file status flags and has no other side effects.
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 {
+        return Err(ah::format_err!(
+            "fcntl(F_GETFL) failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let new_flags = if direct {
+        flags | libc::O_DIRECT
+    } else {
+        flags & !libc::O_DIRECT
+    };
+    if new_flags != flags {
+        // SAFETY: The fcntl call is safe, because:
+        // - The raw file descriptor is valid. (Closing sets self.file to None).
+        // - F_SETFL only changes the file status flags.
+        //   O_DIRECT only changes the alignment requirements of subsequent transfers.
+        let res = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, new_flags) };
+        if res < 0 {
+            return Err(ah::format_err!(
+                "fcntl(F_SETFL, O_DIRECT) failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Run an unaligned transfer with `O_DIRECT` temporarily cleared.
+fn without_o_direct<T>(file: &mut File, f: impl FnOnce(&mut File) -> T) -> ah::Result<T> {
+    set_o_direct(file, false)?;
+    let res = f(file);
+    set_o_direct(file, true)?;
+    Ok(res)
+}
+
+/// Transfer `len` bytes at `data` to or from the file at `offset`.
+/// With direct I/O, transfers that are not aligned to `direct_align`
+/// are done with `O_DIRECT` temporarily cleared.
+/// Returns the number of transferred bytes,
+/// which is less than `len` only for reads at the end of the file.
+///
+/// # Safety
+///
+/// `data` must point to `len` bytes that are valid for reads,
+/// and for writes, if `write` is false.
+/// No other transfer on the file may depend on the file status flags meanwhile.
+pub(crate) unsafe fn transfer_at(
+    file: &mut File,
+    direct_align: Option<usize>,
+    write: bool,
+    offset: u64,
+    data: *mut u8,
+    len: usize,
+) -> std::io::Result<usize> {
+    let transfer = |file: &mut File| -> std::io::Result<usize> {
+        if write {
+            // SAFETY: data points to len bytes that are valid for reads.
+            let data = unsafe { std::slice::from_raw_parts(data, len) };
+            file.write_all_at(data, offset)?;
+            return Ok(len);
+        }
+        // SAFETY: data points to len bytes that are valid for reads and writes.
+        let data = unsafe { std::slice::from_raw_parts_mut(data, len) };
+        let mut count = 0;
+        while count < len {
+            match file.read_at(&mut data[count..], offset + count as u64) {
+                Ok(0) => break, // End of file.
+                Ok(n) => count += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(count)
+    };
+    let unaligned = direct_align.is_some_and(|align| {
+        data.addr() % align != 0 || offset % align as u64 != 0 || len % align != 0
+    });
+    if unaligned {
+        without_o_direct(file, transfer).unwrap_or_else(|e| Err(std::io::Error::other(e)))
+    } else {
+        transfer(file)
+    }
+}
+
+/// Convert the result of a queued transfer.
+pub(crate) fn transfer_result(
+    write: bool,
+    result: std::io::Result<usize>,
+) -> ah::Result<RawIoResult> {
+    match result {
+        Ok(count) => Ok(RawIoResult::Ok(count)),
+        Err(e) if write && e.raw_os_error() == Some(libc::ENOSPC) => Ok(RawIoResult::Enospc),
+        Err(e) if write => Err(ah::format_err!("Write error: {e}")),
+        Err(e) => Err(ah::format_err!("Read error: {e}")),
+    }
+}
+
+/// Fetch a block device size value with an ioctl.
+/// Returns None, if the ioctl failed or if the device reported zero.
+fn blk_ioctl_size(file: &File, request: libc::Ioctl) -> Option<u32> {
+    let mut size: c_uint = 0;
+    // SAFETY: The ioctl call is safe, because:
+    // - The raw file descriptor is valid, because we hold a reference to the File.
+    // - size points to a valid and initialized c_uint.
+    //   All of the used block size ioctls write at most an (unsigned) int.
+    // - The ioctl only fetches the size and has no other side effects.
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), request, (&raw mut size).cast::<c_uint>()) };
+    (res == 0 && size > 0 && size <= c_int::MAX as c_uint).then_some(size)
+}
+
 /// Raw device I/O for Linux OS.
 pub struct RawIoLinux {
     path: PathBuf,
@@ -34,7 +153,9 @@ pub struct RawIoLinux {
     write_mode: bool,
     is_blk: bool,
     is_chr: bool,
-    sector_size: Option<u32>,
+    geometry: Option<RawIoGeometry>,
+    direct: bool,
+    direct_align: usize,
 }
 
 impl RawIoLinux {
@@ -54,35 +175,65 @@ impl RawIoLinux {
                 return Err(ah::format_err!("No file object"));
             };
 
-            let mut sector_size: c_int = 0;
-            // SAFETY: The ioctl call is safe, because:
-            // - The raw file descriptor is valid. (Closing sets self.file to None).
-            // - sector_size points to a valid and initialized c_int.
-            // - The ioctl only fetches the sector size and has no other side effects.
-            let res = unsafe {
-                libc::ioctl(
-                    file.as_raw_fd(),
-                    libc::BLKPBSZGET, // get physical sector size.
-                    (&raw mut sector_size).cast::<c_int>(),
-                )
-            };
-            if res < 0 {
+            let Some(physical_sector_size) = blk_ioctl_size(file, libc::BLKPBSZGET) else {
                 return Err(ah::format_err!(
                     "Get device block size: ioctl(BLKPBSZGET) failed."
                 ));
-            }
-            if sector_size <= 0 {
-                return Err(ah::format_err!(
-                    "Get device block size: ioctl(BLKPBSZGET) invalid size."
-                ));
-            }
+            };
+            let logical_sector_size =
+                blk_ioctl_size(file, libc::BLKSSZGET).unwrap_or(physical_sector_size);
 
-            self.sector_size = Some(sector_size.try_into().unwrap_or(0));
+            // Direct I/O transfers must be aligned to the logical sector size.
+            self.direct_align = logical_sector_size.try_into().unwrap_or(DIRECT_IO_ALIGN);
+
+            self.geometry = Some(RawIoGeometry {
+                logical_sector_size,
+                physical_sector_size,
+                min_io_size: blk_ioctl_size(file, libc::BLKIOMIN),
+                opt_io_size: blk_ioctl_size(file, libc::BLKIOOPT),
+            });
         } else {
-            self.sector_size = None;
+            self.geometry = None;
         }
         Ok(())
     }
+
+    /// Get the number of bytes at the start of a transfer buffer
+    /// that can be transferred with direct I/O.
+    /// Returns 0, if the buffer address or the file position is not aligned.
+    /// Without direct I/O the whole buffer can be transferred.
+    fn direct_len(&mut self, addr: usize, len: usize) -> ah::Result<usize> {
+        if !self.direct {
+            return Ok(len);
+        }
+        let Some(file) = self.file.as_mut() else {
+            return Err(ah::format_err!("No file object"));
+        };
+        let align = self.direct_align;
+        if addr % align != 0 || file.stream_position()? % align as u64 != 0 {
+            return Ok(0);
+        }
+        Ok(len - len % align)
+    }
+
+    /// Duplicate the file handle for queued transfers.
+    pub(crate) fn try_clone_file(&self) -> ah::Result<File> {
+        let Some(file) = self.file.as_ref() else {
+            return Err(ah::format_err!("No file object"));
+        };
+        Ok(file.try_clone()?)
+    }
+
+    /// Check whether this is a character device.
+    pub(crate) fn is_chr(&self) -> bool {
+        self.is_chr
+    }
+
+    /// Get the required alignment of direct transfers.
+    /// Returns None, if direct I/O is disabled.
+    pub(crate) fn direct_align(&self) -> Option<usize> {
+        self.direct.then_some(self.direct_align)
+    }
 }
 
 impl RawIoOsIntf for RawIoLinux {
@@ -116,7 +267,9 @@ impl RawIoOsIntf for RawIoLinux {
             write_mode: write,
             is_blk: false,
             is_chr: false,
-            sector_size: None,
+            geometry: None,
+            direct: false,
+            direct_align: DIRECT_IO_ALIGN,
         };
 
         if let Err(e) = self_.read_disk_geometry() {
@@ -127,8 +280,36 @@ impl RawIoOsIntf for RawIoLinux {
         Ok(self_)
     }
 
-    fn get_sector_size(&self) -> Option<u32> {
-        self.sector_size
+    fn get_geometry(&self) -> Option<RawIoGeometry> {
+        self.geometry
+    }
+
+    fn get_size(&mut self) -> ah::Result<u64> {
+        let Some(file) = self.file.as_mut() else {
+            return Err(ah::format_err!("No file object"));
+        };
+        if self.is_blk {
+            // The metadata of a block device does not contain its size.
+            let pos = file.stream_position()?;
+            let size = file.seek(SeekFrom::End(0))?;
+            file.seek(SeekFrom::Start(pos))?;
+            Ok(size)
+        } else {
+            Ok(file.metadata()?.len())
+        }
+    }
+
+    fn set_direct(&mut self, direct: bool) -> ah::Result<()> {
+        let Some(file) = self.file.as_ref() else {
+            return Err(ah::format_err!("No file object"));
+        };
+        if self.is_chr {
+            // Character devices are not cached.
+            return Ok(());
+        }
+        set_o_direct(file, direct).context("Direct I/O is not supported")?;
+        self.direct = direct;
+        Ok(())
     }
 
     fn drop_file_caches(&mut self, offset: u64, size: u64) -> ah::Result<()> {
@@ -163,8 +344,10 @@ impl RawIoOsIntf for RawIoLinux {
             )
         };
 
-        if ret == 0 {
+        if ret == 0 || self.direct {
             // fadvise success.
+            // With direct I/O only the unaligned transfers have been cached.
+            // Dropping them is best effort.
             Ok(())
         } else {
             // Try global drop_caches.
@@ -230,10 +413,17 @@ impl RawIoOsIntf for RawIoLinux {
         if !self.read_mode {
             return Err(ah::format_err!("File is opened without read permission."));
         }
+        let direct_len = self.direct_len(buffer.as_ptr().addr(), buffer.len())?;
         let Some(file) = self.file.as_mut() else {
             return Err(ah::format_err!("No file object"));
         };
-        match file.read(buffer) {
+        let res = if direct_len > 0 || !self.direct {
+            // The unaligned tail of a direct transfer is read by the next call.
+            file.read(&mut buffer[..direct_len])
+        } else {
+            without_o_direct(file, |file| file.read(buffer))?
+        };
+        match res {
             Ok(count) => Ok(RawIoResult::Ok(count)),
             Err(e) => Err(ah::format_err!("Read error: {e}")),
         }
@@ -243,10 +433,16 @@ impl RawIoOsIntf for RawIoLinux {
         if !self.write_mode {
             return Err(ah::format_err!("File is opened without write permission."));
         }
+        let direct_len = self.direct_len(buffer.as_ptr().addr(), buffer.len())?;
         let Some(file) = self.file.as_mut() else {
             return Err(ah::format_err!("No file object"));
         };
-        if let Err(e) = file.write_all(buffer) {
+        let (head, tail) = buffer.split_at(direct_len);
+        let mut res = file.write_all(head);
+        if res.is_ok() && !tail.is_empty() {
+            res = without_o_direct(file, |file| file.write_all(tail))?;
+        }
+        if let Err(e) = res {
             if let Some(err_code) = e.raw_os_error() {
                 if err_code == libc::ENOSPC {
                     return Ok(RawIoResult::Enospc);
@@ -0,0 +1,63 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use super::{RawIo, RawIoQueueIntf, RawIoResult};
+use anyhow as ah;
+use std::{convert::Infallible, marker::PhantomData};
+
+/// Queued raw I/O is not available on this OS.
+/// This type can not be constructed.
+pub struct RawIoNoQueue<B> {
+    never: Infallible,
+    _buffer: PhantomData<B>,
+}
+
+impl<B: Send + 'static> RawIoQueueIntf<B> for RawIoNoQueue<B> {
+    fn new(_io: &RawIo, _depth: usize) -> ah::Result<Self> {
+        Err(ah::format_err!(
+            "Queued I/O is not supported on this operating system, yet."
+        ))
+    }
+
+    fn new_thread(_io: &RawIo, _depth: usize) -> ah::Result<Self> {
+        Err(ah::format_err!(
+            "Queued I/O is not supported on this operating system, yet."
+        ))
+    }
+
+    fn pending(&self) -> usize {
+        match self.never {}
+    }
+
+    fn is_full(&self) -> bool {
+        match self.never {}
+    }
+
+    fn submit_write(&mut self, _offset: u64, _buffer: B, _len: usize) -> ah::Result<()>
+    where
+        B: AsRef<[u8]>,
+    {
+        match self.never {}
+    }
+
+    fn submit_read(&mut self, _offset: u64, _buffer: B, _len: usize) -> ah::Result<()>
+    where
+        B: AsMut<[u8]>,
+    {
+        match self.never {}
+    }
+
+    fn complete(&mut self) -> Option<(B, ah::Result<RawIoResult>)> {
+        match self.never {}
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,74 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use super::{RawIo, RawIoQueueIntf, RawIoResult, iothread::RawIoThread, uring::RawIoUring};
+use anyhow as ah;
+
+/// Queued raw device I/O for Linux.
+pub enum RawIoLinuxQueue<B> {
+    /// Several transfers in flight with `io_uring`.
+    Uring(Box<RawIoUring<B>>),
+    /// One transfer at a time in a dedicated I/O thread.
+    Thread(RawIoThread<B>),
+}
+
+impl<B: Send + 'static> RawIoQueueIntf<B> for RawIoLinuxQueue<B> {
+    fn new(io: &RawIo, depth: usize) -> ah::Result<Self> {
+        Ok(Self::Uring(Box::new(RawIoUring::new(io, depth)?)))
+    }
+
+    fn new_thread(io: &RawIo, depth: usize) -> ah::Result<Self> {
+        Ok(Self::Thread(RawIoThread::new(io, depth)?))
+    }
+
+    fn pending(&self) -> usize {
+        match self {
+            Self::Uring(q) => q.pending(),
+            Self::Thread(q) => q.pending(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Self::Uring(q) => q.is_full(),
+            Self::Thread(q) => q.is_full(),
+        }
+    }
+
+    fn submit_write(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsRef<[u8]>,
+    {
+        match self {
+            Self::Uring(q) => q.submit_write(offset, buffer, len),
+            Self::Thread(q) => q.submit_write(offset, buffer, len),
+        }
+    }
+
+    fn submit_read(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsMut<[u8]>,
+    {
+        match self {
+            Self::Uring(q) => q.submit_read(offset, buffer, len),
+            Self::Thread(q) => q.submit_read(offset, buffer, len),
+        }
+    }
+
+    fn complete(&mut self) -> Option<(B, ah::Result<RawIoResult>)> {
+        match self {
+            Self::Uring(q) => q.complete(),
+            Self::Thread(q) => q.complete(),
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,328 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Storage tester
+//
+// Copyright 2020-2026 Michael Büsch <m@bues.ch>
+//
+// Licensed under the Apache License version 2.0
+// or the MIT license, at your option.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+
+use super::{
+    RawIo, RawIoResult,
+    linux::{transfer_at, transfer_result},
+};
+use anyhow::{self as ah, Context as _};
+use io_uring::{IoUring, opcode, squeue, types};
+use std::{
+    collections::VecDeque, fs::File, io, os::unix::io::AsRawFd as _, ptr::NonNull, thread,
+    time::Duration,
+};
+
+/// A submitted transfer.
+struct Transfer<B> {
+    /// The buffer. It is owned by the queue until the transfer has completed.
+    buffer: NonNull<B>,
+    /// The start of the transferred data in the buffer.
+    data: *mut u8,
+    offset: u64,
+    len: usize,
+    write: bool,
+    /// The number of transferred bytes or the error, once the transfer has completed.
+    result: Option<io::Result<usize>>,
+}
+
+/// Queued raw device I/O for Linux, based on `io_uring`.
+pub struct RawIoUring<B> {
+    ring: IoUring,
+    file: File,
+    depth: usize,
+    direct_align: Option<usize>,
+    /// The pending transfers, in the order of submission.
+    transfers: VecDeque<Transfer<B>>,
+    /// The sequence number of the first pending transfer.
+    /// The sequence numbers identify the completions.
+    first_seq: u64,
+    /// The error that poisoned the queue, if waiting for completions has failed.
+    error: Option<String>,
+}
+
+impl<B> RawIoUring<B> {
+    /// Check whether a transfer is not suitable for direct I/O.
+    fn is_unaligned(&self, data: *const u8, offset: u64, len: usize) -> bool {
+        self.direct_align.is_some_and(|align| {
+            data.addr() % align != 0 || offset % align as u64 != 0 || len % align != 0
+        })
+    }
+
+    /// Wait for at least one transfer to complete and record the results.
+    /// If waiting fails, then the queue is poisoned and all pending transfers fail.
+    fn reap(&mut self) {
+        if self.error.is_some() {
+            return; // All transfers have been failed by poison().
+        }
+        loop {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.poison(&e);
+                    return;
+                }
+            }
+        }
+        self.record_completions();
+    }
+
+    /// Fail all pending transfers and refuse new ones, after waiting has failed.
+    fn poison(&mut self, e: &io::Error) {
+        let error = format!("Failed to wait for queued I/O: {e}");
+        let failed = || Some(Err(io::Error::new(e.kind(), error.clone())));
+
+        // The newest entries that are still in the submission queue
+        // have not been taken by the kernel. Their buffers can be released.
+        let mut not_taken = self.ring.submission().len();
+        for transfer in self.transfers.iter_mut().rev() {
+            if not_taken == 0 {
+                break;
+            }
+            if transfer.result.is_none() {
+                transfer.result = failed();
+                not_taken -= 1;
+            }
+        }
+
+        // The kernel may still access the buffers of the other pending transfers.
+        // Their completions are posted without entering the ring,
+        // so poll for them until the kernel has released all buffers.
+        let taken: Vec<bool> = self.transfers.iter().map(|t| t.result.is_none()).collect();
+        while self.transfers.iter().any(|t| t.result.is_none()) {
+            thread::sleep(Duration::from_millis(1));
+            self.record_completions();
+        }
+        for (transfer, taken) in self.transfers.iter_mut().zip(taken) {
+            if taken {
+                transfer.result = failed();
+            }
+        }
+
+        self.error = Some(error);
+    }
+
+    /// Record the results of the completed transfers.
+    fn record_completions(&mut self) {
+        for cqe in self.ring.completion() {
+            let index = usize::try_from(cqe.user_data() - self.first_seq)
+                .expect("Invalid io_uring completion");
+            let result = cqe.result();
+            self.transfers[index].result = Some(match usize::try_from(result) {
+                Ok(count) => Ok(count),
+                Err(_) => Err(io::Error::from_raw_os_error(-result)),
+            });
+        }
+    }
+
+    /// Wait for all pending transfers to complete.
+    fn wait_all(&mut self) {
+        while self.transfers.iter().any(|t| t.result.is_none()) {
+            self.reap();
+        }
+    }
+
+    /// Transfer synchronously.
+    /// This is used for transfers that are not suitable for direct I/O
+    /// and for the rest of short transfers.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point to `len` bytes of a buffer that is owned by the queue.
+    /// All pending transfers must have completed,
+    /// so that nothing else accesses the buffer or depends on the file status flags.
+    unsafe fn transfer_sync(
+        &mut self,
+        write: bool,
+        offset: u64,
+        data: *mut u8,
+        len: usize,
+    ) -> io::Result<usize> {
+        // SAFETY: See the safety requirements of this function.
+        unsafe { transfer_at(&mut self.file, self.direct_align, write, offset, data, len) }
+    }
+
+    /// Check whether a transfer of `len` bytes from or to a buffer of `buffer_len` bytes
+    /// can be submitted and get the transfer length for the submission.
+    fn check_submit(&self, len: usize, buffer_len: usize) -> ah::Result<u32> {
+        if let Some(error) = self.error.as_ref() {
+            return Err(ah::format_err!("{error}"));
+        }
+        if self.is_full() {
+            return Err(ah::format_err!("The I/O queue is full."));
+        }
+        if len > buffer_len {
+            return Err(ah::format_err!("Transfer length exceeds the buffer."));
+        }
+        u32::try_from(len).context("Transfer length overflows u32")
+    }
+
+    /// Submit a transfer of `len` bytes at `data` in the leaked `buffer`.
+    fn submit(
+        &mut self,
+        write: bool,
+        offset: u64,
+        buffer: NonNull<B>,
+        data: *mut u8,
+        len: u32,
+    ) -> ah::Result<()> {
+        let mut transfer = Transfer {
+            buffer,
+            data,
+            offset,
+            len: len as usize,
+            write,
+            result: None,
+        };
+
+        if self.is_unaligned(data, offset, transfer.len) {
+            self.wait_all();
+            // SAFETY: data points into the buffer that is now owned by the queue
+            //         and all pending transfers have completed.
+            transfer.result =
+                Some(unsafe { self.transfer_sync(write, offset, data, transfer.len) });
+            self.transfers.push_back(transfer);
+            return Ok(());
+        }
+
+        let fd = types::Fd(self.file.as_raw_fd());
+        let entry: squeue::Entry = if write {
+            opcode::Write::new(fd, data, len).offset(offset).build()
+        } else {
+            opcode::Read::new(fd, data, len).offset(offset).build()
+        };
+        let entry = entry.user_data(self.first_seq + self.transfers.len() as u64);
+
+        // SAFETY: The entry only references the file and the buffer.
+        // - The file is owned by the queue and is closed after the ring.
+        // - The buffer is owned by the queue until the transfer has completed.
+        //   Dropping the queue waits for all pending transfers.
+        if unsafe { self.ring.submission().push(&entry) }.is_err() {
+            // SAFETY: The buffer has not been submitted. This is the only reference.
+            drop(unsafe { Box::from_raw(transfer.buffer.as_ptr()) });
+            return Err(ah::format_err!("The io_uring submission queue is full."));
+        }
+        self.transfers.push_back(transfer);
+        loop {
+            match self.ring.submit() {
+                Ok(_) => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                // The entry stays in the submission queue and is submitted by the next wait.
+                Err(e) => break Err(ah::format_err!("Failed to submit queued I/O: {e}")),
+            }
+        }
+    }
+
+    /// Create a queue with up to `depth` transfers in flight.
+    pub fn new(io: &RawIo, depth: usize) -> ah::Result<Self> {
+        if io.is_chr() {
+            return Err(ah::format_err!(
+                "Queued I/O is not supported for character devices"
+            ));
+        }
+        if depth == 0 {
+            return Err(ah::format_err!("The I/O queue depth must not be zero"));
+        }
+        let entries = u32::try_from(depth).context("I/O queue depth overflows u32")?;
+        let ring = IoUring::new(entries).context("io_uring is not available")?;
+        Ok(Self {
+            ring,
+            file: io.try_clone_file()?,
+            depth,
+            direct_align: io.direct_align(),
+            transfers: VecDeque::with_capacity(depth),
+            first_seq: 0,
+            error: None,
+        })
+    }
+
+    /// Get the number of pending transfers.
+    pub fn pending(&self) -> usize {
+        self.transfers.len()
+    }
+
+    /// Check whether `depth` transfers are in flight.
+    pub fn is_full(&self) -> bool {
+        self.transfers.len() >= self.depth
+    }
+
+    /// Submit a write of the first `len` bytes of `buffer` at the file `offset`.
+    pub fn submit_write(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsRef<[u8]>,
+    {
+        let len = self.check_submit(len, buffer.as_ref().len())?;
+        let buffer = Box::leak(Box::new(buffer));
+        let data = buffer.as_ref().as_ptr().cast_mut();
+        self.submit(true, offset, NonNull::from(buffer), data, len)
+    }
+
+    /// Submit a read of `len` bytes into `buffer` from the file `offset`.
+    pub fn submit_read(&mut self, offset: u64, buffer: B, len: usize) -> ah::Result<()>
+    where
+        B: AsMut<[u8]>,
+    {
+        let mut buffer = buffer;
+        let len = self.check_submit(len, buffer.as_mut().len())?;
+        let buffer = Box::leak(Box::new(buffer));
+        let data = buffer.as_mut().as_mut_ptr();
+        self.submit(false, offset, NonNull::from(buffer), data, len)
+    }
+
+    /// Wait for the oldest transfer to complete.
+    pub fn complete(&mut self) -> Option<(B, ah::Result<RawIoResult>)> {
+        while self.transfers.front()?.result.is_none() {
+            self.reap();
+        }
+        let mut transfer = self.transfers.pop_front()?;
+        self.first_seq += 1;
+
+        // Finish short transfers synchronously.
+        // A short read with no data is the end of the file.
+        if let Some(Ok(count)) = transfer.result {
+            if count < transfer.len && (transfer.write || count > 0) {
+                self.wait_all();
+                // SAFETY: The buffer is still owned by the queue
+                //         and all pending transfers have completed.
+                let rest = unsafe {
+                    self.transfer_sync(
+                        transfer.write,
+                        transfer.offset + count as u64,
+                        transfer.data.add(count),
+                        transfer.len - count,
+                    )
+                };
+                transfer.result = Some(rest.map(|rest| count + rest));
+            }
+        }
+
+        // SAFETY: The transfer has completed. The kernel doesn't access the buffer anymore.
+        let buffer = *unsafe { Box::from_raw(transfer.buffer.as_ptr()) };
+        let result = transfer_result(
+            transfer.write,
+            transfer.result.expect("Transfer not completed"),
+        );
+        Some((buffer, result))
+    }
+}
+
+impl<B> Drop for RawIoUring<B> {
+    fn drop(&mut self) {
+        // The kernel may still access the buffers of the pending transfers.
+        self.wait_all();
+        for transfer in self.transfers.drain(..) {
+            // SAFETY: The transfer has completed. This is the only reference to the buffer.
+            drop(unsafe { Box::from_raw(transfer.buffer.as_ptr()) });
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -9,7 +9,7 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 //
 
-use super::{RawIoOsIntf, RawIoResult};
+use super::{RawIoGeometry, RawIoOsIntf, RawIoResult};
 use anyhow::{self as ah, Context as _};
 use std::{
     ffi::{CString, OsString},
@@ -56,7 +56,7 @@ pub struct RawIoWindows {
     write_mode: bool,
     is_raw: bool,
     volume_locked: bool,
-    sector_size: Option<u32>,
+    geometry: Option<RawIoGeometry>,
     disk_size: u64,
     cur_offset: u64,
 }
@@ -168,10 +168,15 @@ impl RawIoWindows {
                 * u64::from(dg.SectorsPerTrack)
                 * u64::from(dg.TracksPerCylinder)
                 * cylinders;
-            self.sector_size = Some(dg.BytesPerSector as u32);
+            self.geometry = Some(RawIoGeometry {
+                logical_sector_size: dg.BytesPerSector as u32,
+                physical_sector_size: dg.BytesPerSector as u32,
+                min_io_size: None,
+                opt_io_size: None,
+            });
         } else {
             self.disk_size = u64::MAX;
-            self.sector_size = None;
+            self.geometry = None;
         }
         Ok(())
     }
@@ -269,7 +274,7 @@ impl RawIoOsIntf for RawIoWindows {
             write_mode: write,
             is_raw,
             volume_locked,
-            sector_size: None,
+            geometry: None,
             disk_size: 0,
             cur_offset: 0,
         };
@@ -282,8 +287,26 @@ impl RawIoOsIntf for RawIoWindows {
         Ok(self_)
     }
 
-    fn get_sector_size(&self) -> Option<u32> {
-        self.sector_size
+    fn get_geometry(&self) -> Option<RawIoGeometry> {
+        self.geometry
+    }
+
+    fn get_size(&mut self) -> ah::Result<u64> {
+        if self.is_raw {
+            Ok(self.disk_size)
+        } else {
+            Ok(std::fs::metadata(&self.path)?.len())
+        }
+    }
+
+    fn set_direct(&mut self, direct: bool) -> ah::Result<()> {
+        if direct {
+            Err(ah::format_err!(
+                "Direct I/O is not supported on Windows, yet."
+            ))
+        } else {
+            Ok(())
+        }
     }
 
     fn drop_file_caches(&mut self, _offset: u64, _size: u64) -> ah::Result<()> {
@@ -12,10 +12,14 @@
 use anyhow as ah;
 use clap::builder::ValueParser;
 use clap::error::ErrorKind::{DisplayHelp, DisplayVersion};
-use clap::{Parser, ValueEnum, value_parser};
-use disktest_lib::{Disktest, DisktestQuiet, DtStreamType, gen_seed_string, parsebytes};
+use clap::{Parser, Subcommand, ValueEnum, value_parser};
+use disktest_lib::{
+    Disktest, DisktestBench, DisktestHexdump, DisktestQuiet, DisktestSampling, DisktestWipeMethod,
+    DisktestWipePass, DtStreamLayout, DtStreamType, gen_seed_string, parsebytes,
+};
 use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Duration;
 
 /// Length of the generated seed.
 const DEFAULT_GEN_SEED_LEN: usize = 40;
@@ -37,6 +41,30 @@ const EXAMPLE: &str = "\
 Example usage:
 disktest --write --verify -j0 \\\\.\\E:";
 
+#[cfg(not(target_os = "windows"))]
+const EXAMPLE_ANALYZE: &str = "\
+Example usage:
+disktest analyze --offset 1G -j0 --seed SEED /dev/sdc";
+
+#[cfg(target_os = "windows")]
+const EXAMPLE_ANALYZE: &str = "\
+Example usage:
+disktest analyze --offset 1G -j0 --seed SEED \\\\.\\E:";
+
+#[cfg(not(target_os = "windows"))]
+const EXAMPLE_ERASE: &str = "\
+Example usage:
+disktest erase --report sdc-erase.txt --sign-key erase.key --gen-sign-key -j0 /dev/sdc";
+
+#[cfg(target_os = "windows")]
+const EXAMPLE_ERASE: &str = "\
+Example usage:
+disktest erase --report erase.txt --sign-key erase.key --gen-sign-key -j0 \\\\.\\PhysicalDrive2";
+
+const EXAMPLE_BENCH: &str = "\
+Example usage:
+disktest bench --threads 1,2,4 --target 3GiB";
+
 #[cfg(not(target_os = "windows"))]
 const HELP_DEVICE_LONG: &str = "\
 Device node of the disk or file path to access.
@@ -49,22 +77,44 @@ Device node of the disk or file path to access.
 This may be a path to the location on the disk to be tested (e.g. E:\\testfile)
 or a raw drive (e.g. \\\\.\\E: or \\\\.\\PhysicalDrive2).";
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
-#[value(rename_all = "UPPER")]
-enum AlgorithmChoice {
-    Chacha8,
-    Chacha12,
-    Chacha20,
-    Crc,
+/// Pattern sequence of the `BADBLOCKS` algorithm shortcut.
+/// This is the sequence of `badblocks -w`.
+const BADBLOCKS_PATTERNS: &str = "PATTERN:AA,PATTERN:55,PATTERN:FF,PATTERN:00";
+
+/// The algorithms of the rounds, in the order they are used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AlgorithmList(Vec<DtStreamType>);
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum LayoutChoice {
+    Thread,
+    Chunk,
 }
 
-impl From<AlgorithmChoice> for DtStreamType {
-    fn from(value: AlgorithmChoice) -> Self {
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum WipeChoice {
+    NistClear,
+    Dod,
+    DodEce,
+    Gutmann,
+}
+
+impl From<WipeChoice> for DisktestWipeMethod {
+    fn from(value: WipeChoice) -> Self {
+        match value {
+            WipeChoice::NistClear => DisktestWipeMethod::NistClear,
+            WipeChoice::Dod => DisktestWipeMethod::Dod,
+            WipeChoice::DodEce => DisktestWipeMethod::DodEce,
+            WipeChoice::Gutmann => DisktestWipeMethod::Gutmann,
+        }
+    }
+}
+
+impl From<LayoutChoice> for DtStreamLayout {
+    fn from(value: LayoutChoice) -> Self {
         match value {
-            AlgorithmChoice::Chacha8 => DtStreamType::ChaCha8,
-            AlgorithmChoice::Chacha12 => DtStreamType::ChaCha12,
-            AlgorithmChoice::Chacha20 => DtStreamType::ChaCha20,
-            AlgorithmChoice::Crc => DtStreamType::Crc,
+            LayoutChoice::Chunk => DtStreamLayout::Chunk,
+            LayoutChoice::Thread => DtStreamLayout::Thread,
         }
     }
 }
@@ -74,18 +124,112 @@ pub struct Args {
     pub device: PathBuf,
     pub write: bool,
     pub verify: bool,
+    pub probe: bool,
     pub seek: u64,
     pub max_bytes: u64,
-    pub algorithm: DtStreamType,
+    pub algorithms: Vec<DtStreamType>,
+    pub layout: DtStreamLayout,
+    pub chunk_size: Option<u64>,
     pub seed: String,
     pub user_seed: bool,
     pub invert_pattern: bool,
     pub threads: usize,
     pub rounds: u64,
     pub start_round: u64,
+    pub max_errors: Option<u64>,
+    pub hexdump: Option<DisktestHexdump>,
+    pub hexdump_context: u32,
+    pub badblocks: Option<PathBuf>,
+    pub badblocks_block_size: u64,
+    pub dmsetup: Option<PathBuf>,
+    pub state_file: Option<PathBuf>,
+    pub resume: bool,
+    pub sampling: Option<DisktestSampling>,
+    pub interleave_lag: Option<u64>,
+    pub direct: bool,
+    pub queue_depth: usize,
+    pub max_memory: Option<u64>,
+    pub wipe: Option<DisktestWipeMethod>,
+    pub analyze: Option<AnalyzeArgs>,
+    pub erase: Option<EraseArgs>,
+    pub check_report: Option<CheckReportArgs>,
+    pub bench: Option<BenchArgs>,
     pub quiet: DisktestQuiet,
 }
 
+impl Args {
+    /// Get the algorithm of the round.
+    /// The list of algorithms is repeated, if there are more rounds than algorithms.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn algorithm(&self, round_id: u64) -> DtStreamType {
+        self.algorithms[(round_id % self.algorithms.len() as u64) as usize]
+    }
+
+    /// Get the wipe pass of the round, if a wipe method is selected.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn wipe_pass(&self, round_id: u64) -> Option<DisktestWipePass> {
+        self.wipe
+            .and_then(|wipe| wipe.passes().get(round_id as usize).copied())
+    }
+
+    /// Get a display name of the data written in the round.
+    pub fn pass_name(&self, round_id: u64) -> String {
+        if let Some(pass) = self.wipe_pass(round_id) {
+            pass.to_string()
+        } else if self.invert_pattern {
+            format!("inverted {}", self.algorithm(round_id))
+        } else {
+            self.algorithm(round_id).to_string()
+        }
+    }
+
+    /// Invert the pattern in this round?
+    pub fn invert(&self, round_id: u64) -> bool {
+        self.wipe_pass(round_id)
+            .map_or(self.invert_pattern, |pass| pass.invert)
+    }
+
+    /// Verify in this round?
+    /// With a wipe method, --verify verifies every pass.
+    pub fn verify_round(&self, round_id: u64) -> bool {
+        self.verify || self.wipe_pass(round_id).is_some_and(|pass| pass.verify)
+    }
+}
+
+/// Command line arguments of the `analyze` subcommand.
+/// The device, seed, algorithm, threads, rounds and search range are in [`Args`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct AnalyzeArgs {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Command line arguments of the `erase` subcommand.
+/// The device, passes ([`Args::algorithms`]), threads and byte count are in [`Args`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct EraseArgs {
+    pub method: String,
+    pub report: PathBuf,
+    pub sign_key: PathBuf,
+    pub gen_sign_key: bool,
+}
+
+/// Command line arguments of the `check-report` subcommand.
+/// The path of the report is [`Args::device`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheckReportArgs {
+    pub public_key: Option<String>,
+}
+
+/// Command line arguments of the `bench` subcommand.
+/// The algorithms are in [`Args::algorithms`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct BenchArgs {
+    pub threads: Vec<usize>,
+    pub duration: Duration,
+    pub target_rate: Option<u64>,
+}
+
 #[derive(Debug, Parser)]
 #[command(
     name = "disktest",
@@ -93,17 +237,23 @@ pub struct Args {
     author = env!("CARGO_PKG_AUTHORS"),
     about = ABOUT,
     after_help = EXAMPLE,
-    verbatim_doc_comment
+    verbatim_doc_comment,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
     /// Device node of the disk or file path to access.
     #[arg(
         verbatim_doc_comment,
         value_name = "DEVICE",
         value_parser = value_parser!(PathBuf),
+        required = true,
         help = HELP_DEVICE_LONG
     )]
-    device: PathBuf,
+    device: Option<PathBuf>,
 
     /// Write pseudo random data to the device.
     /// If this option is not given, then disktest will operate in
@@ -121,6 +271,20 @@ struct CliArgs {
     #[arg(verbatim_doc_comment, short = 'v', long)]
     verify: bool,
 
+    /// Probe the device for fake capacity instead of writing and verifying all of it.
+    /// Small uniquely tagged blocks are written to offsets spread over the
+    /// advertised size. After dropping the caches they are read back
+    /// and checked for lost data and wrapping or aliasing addresses.
+    /// This overwrites the probed blocks on the device and finishes in minutes.
+    /// --bytes may be given to override the advertised size.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'P',
+        long,
+        conflicts_with_all = ["write", "verify", "seek"]
+    )]
+    probe: bool,
+
     /// Seek to the specified byte position on disk
     /// before starting the write/verify operation. This skips the specified
     /// amount of bytes on the disk and also fast forwards the random number generator.
@@ -147,17 +311,61 @@ struct CliArgs {
     max_bytes: u64,
 
     /// Select the random number generator algorithm.
+    /// CHACHA20, CHACHA12, CHACHA8, CRC, XOSHIRO, TAGGED,
+    /// PATTERN:HEX, CHECKERBOARD, WALKING1, WALKING0 or BADBLOCKS.
     /// `ChaCha12` and `ChaCha8` are less cryptographically secure than `ChaCha20`, but
     /// faster. CRC is even faster, but not cryptographically secure at all.
+    /// XOSHIRO is several times faster than CRC and not cryptographically secure either.
+    /// TAGGED writes a header with the offset, round, seed fingerprint and checksum
+    /// into every 512 byte sector, followed by `ChaCha20` payload.
+    /// With TAGGED, verification reports what a bad sector contains instead,
+    /// e.g. data written for another offset (aliasing) or in an earlier round.
+    /// PATTERN:HEX repeats the given 1 to 32 hexadecimal bytes, e.g. PATTERN:00 or PATTERN:AA55.
+    /// CHECKERBOARD alternates 0x55 and 0xAA with the phase inverted in every other sector.
+    /// WALKING1 and WALKING0 walk a single set or cleared bit through the bytes.
+    /// The fixed patterns do not depend on the seed.
+    /// A comma separated list selects the algorithm of each round in turn,
+    /// e.g. "PATTERN:FF,PATTERN:00,CHACHA20" together with --rounds 3.
+    /// BADBLOCKS is the list PATTERN:AA,PATTERN:55,PATTERN:FF,PATTERN:00.
     #[arg(
         verbatim_doc_comment,
         short = 'A',
         long = "algorithm",
+        value_name = "ALG",
+        default_value = "CHACHA20",
+        value_parser = parse_algorithm
+    )]
+    algorithm: AlgorithmList,
+
+    /// Select the layout of the random data stream on the disk.
+    /// THREAD: Every thread generates its own interleaved stream.
+    /// The number of threads must be equal during write and verify.
+    /// This is the layout of disktest versions without the --layout option.
+    /// CHUNK: The data only depends on the seed, round and position.
+    /// The number of threads may differ between write and verify.
+    /// The same layout must be used for write and verify.
+    #[arg(
+        verbatim_doc_comment,
+        long = "layout",
         value_enum,
         ignore_case = true,
-        default_value_t = AlgorithmChoice::Chacha20
+        default_value_t = LayoutChoice::Thread
+    )]
+    layout: LayoutChoice,
+
+    /// The size of the write and read transfers.
+    /// For example 128K to mimic a file system or 64M for sequential throughput.
+    /// The size is rounded to a multiple of the generator base size and the sector size.
+    /// The data depends on the chunk size.
+    /// Therefore, the same chunk size must be used for verification.
+    /// If not given, then the default chunk size of the algorithm is used.
+    #[arg(
+        verbatim_doc_comment,
+        long = "chunk-size",
+        value_name = "BYTES",
+        value_parser = ValueParser::new(parsebytes)
     )]
-    algorithm: AlgorithmChoice,
+    chunk_size: Option<u64>,
 
     /// The seed to use for random number stream generation.
     /// The seed may be any random string (e.g. a long passphrase).
@@ -175,8 +383,8 @@ struct CliArgs {
     /// Number of CPUs to use.
     /// The special value 0 will select the maximum number of online CPUs in the
     /// system. If the number of threads is equal to number of CPUs it is optimal
-    /// for performance. The number of threads must be equal during corresponding
-    /// verify and write mode runs. Otherwise the verification will fail.
+    /// for performance. With --layout THREAD the number of threads must be equal
+    /// during corresponding verify and write mode runs. Otherwise the verification will fail.
     #[arg(
         verbatim_doc_comment,
         short = 'j',
@@ -215,6 +423,582 @@ struct CliArgs {
     )]
     start_round: u64,
 
+    /// Overwrite the device with the passes of a named wipe method.
+    /// Every pass is run as one round. --write is implied.
+    /// NIST-CLEAR: NIST SP 800-88 Clear. One pass of zeros.
+    /// DOD: `DoD` 5220.22-M. Zeros, their complement, random data.
+    /// DOD-ECE: `DoD` 5220.22-M ECE. The DOD passes, random data and the DOD passes again.
+    /// GUTMANN: Gutmann-style. 4 random passes, Gutmann's 27 patterns and 4 random passes.
+    /// The last pass is verified. With --verify every pass is verified.
+    /// See the erase subcommand for a signed erase report.
+    #[arg(
+        verbatim_doc_comment,
+        long = "wipe",
+        value_name = "METHOD",
+        value_enum,
+        ignore_case = true,
+        conflicts_with_all = [
+            "algorithm",
+            "rounds",
+            "invert_pattern",
+            "probe",
+            "sample",
+            "sample_every",
+            "interleave"
+        ]
+    )]
+    wipe: Option<WipeChoice>,
+
+    /// Continue verification after data mismatches and read errors
+    /// and collect all bad ranges.
+    /// On a read error the affected area is read again sector by sector
+    /// and the unreadable sectors are reported separately from the data mismatches.
+    /// Verification stops after NUM bad or unreadable sectors have been found.
+    /// The special value 0 never stops early and verifies the whole range.
+    /// If this option is not given, then verification stops at the first error.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'E',
+        long = "max-errors",
+        value_name = "NUM",
+        value_parser = value_parser!(u64)
+    )]
+    max_errors: Option<u64>,
+
+    /// Print a side by side hex dump of the expected and the read data
+    /// of every mismatching sector to the console.
+    /// Differing bytes are marked with ^^.
+    #[arg(
+        verbatim_doc_comment,
+        long = "hexdump",
+        conflicts_with = "hexdump_file"
+    )]
+    hexdump: bool,
+
+    /// Write the hex dump of every mismatching sector to a file
+    /// instead of printing it to the console.
+    /// The hex dumps of all rounds are written to the file.
+    /// With --resume they are appended to it.
+    #[arg(
+        verbatim_doc_comment,
+        long = "hexdump-file",
+        value_name = "PATH",
+        value_parser = value_parser!(PathBuf)
+    )]
+    hexdump_file: Option<PathBuf>,
+
+    /// Number of additional sectors to include in the hex dump
+    /// before and after each mismatching sector.
+    #[arg(
+        verbatim_doc_comment,
+        long = "hexdump-context",
+        value_name = "SECTORS",
+        default_value_t = 0,
+        value_parser = value_parser!(u32)
+    )]
+    hexdump_context: u32,
+
+    /// Write the bad and unreadable sectors found during verification
+    /// to a file as a list of bad block numbers.
+    /// The list can be passed to 'mke2fs -l' and 'e2fsck -l'.
+    /// This requires --max-errors.
+    #[arg(
+        verbatim_doc_comment,
+        long = "badblocks-file",
+        value_name = "PATH",
+        value_parser = value_parser!(PathBuf),
+        requires = "max_errors"
+    )]
+    badblocks_file: Option<PathBuf>,
+
+    /// Block size of the bad block list.
+    /// This must be the block size of the file system to be created.
+    #[arg(
+        verbatim_doc_comment,
+        long = "badblocks-block-size",
+        value_name = "BYTES",
+        default_value = "4096",
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    badblocks_block_size: u64,
+
+    /// Write a device-mapper table to a file that maps around
+    /// the bad and unreadable sectors found during verification.
+    /// Load it with 'dmsetup create NAME PATH'.
+    /// This requires --max-errors.
+    #[arg(
+        verbatim_doc_comment,
+        long = "dmsetup-file",
+        value_name = "PATH",
+        value_parser = value_parser!(PathBuf),
+        requires = "max_errors"
+    )]
+    dmsetup_file: Option<PathBuf>,
+
+    /// Periodically write the progress of the write and verify runs to a state file.
+    /// The state file contains the phase, round and last synced byte offset,
+    /// but not the seed.
+    /// If the run is aborted, then the command to resume it is printed.
+    #[arg(
+        verbatim_doc_comment,
+        long = "state-file",
+        value_name = "PATH",
+        value_parser = value_parser!(PathBuf)
+    )]
+    state_file: Option<PathBuf>,
+
+    /// Resume an aborted run from the --state-file.
+    /// The seed, algorithm and number of threads must be the same
+    /// as in the aborted run.
+    #[arg(
+        verbatim_doc_comment,
+        long = "resume",
+        requires_all = ["state_file", "seed"],
+        conflicts_with = "probe"
+    )]
+    resume: bool,
+
+    /// Quick check: Only write and verify PERCENT of the chunks.
+    /// The chunks are evenly spread over the whole range,
+    /// so that the full address range is covered in a fraction of the time.
+    /// The sampling coverage is reported at the end.
+    /// Use the same sampling options for write and verify.
+    #[arg(
+        verbatim_doc_comment,
+        long = "sample",
+        value_name = "PERCENT",
+        value_parser = parse_percent,
+        conflicts_with_all = ["probe", "state_file", "sample_every"]
+    )]
+    sample: Option<f64>,
+
+    /// Quick check: Only write and verify every NUM-th chunk.
+    #[arg(
+        verbatim_doc_comment,
+        long = "sample-every",
+        value_name = "NUM",
+        value_parser = value_parser!(u64).range(1_u64..),
+        conflicts_with_all = ["probe", "state_file"]
+    )]
+    sample_every: Option<u64>,
+
+    /// Select a pseudo random subset of --sample PERCENT of the chunks
+    /// instead of evenly spread chunks.
+    /// The subset is derived from the seed and the round.
+    #[arg(verbatim_doc_comment, long = "sample-random", requires = "sample")]
+    sample_random: bool,
+
+    /// Verify the written data in a single pass shortly after writing it,
+    /// instead of writing the whole range first and then verifying it.
+    /// Each time --interleave-lag bytes have been written, they are synced,
+    /// dropped from the caches and read back.
+    /// Errors are found early and stop the run early.
+    /// This requires --write and --verify.
+    #[arg(
+        verbatim_doc_comment,
+        long = "interleave",
+        requires_all = ["write", "verify"],
+        conflicts_with_all = ["state_file", "sample", "sample_every"]
+    )]
+    interleave: bool,
+
+    /// The amount of data that is written before it is verified with --interleave.
+    #[arg(
+        verbatim_doc_comment,
+        long = "interleave-lag",
+        value_name = "BYTES",
+        default_value = "1 GiB",
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    interleave_lag: u64,
+
+    /// Use direct I/O (`O_DIRECT`) on Linux, which bypasses the page cache.
+    /// The data is read back from the disk without having to drop the caches,
+    /// which does not need root permissions.
+    /// Unaligned transfers at the end of the device are done with cached I/O.
+    /// If the file system does not support direct I/O, then cached I/O is used.
+    #[arg(verbatim_doc_comment, long = "direct")]
+    direct: bool,
+
+    /// The number of writes or reads to keep in flight.
+    /// A queue depth above 1 uses `io_uring` on Linux,
+    /// which can increase the throughput of fast devices like NVM Express SSDs.
+    /// The transfers still complete in order.
+    /// If `io_uring` is not available, then one transfer at a time is done.
+    #[arg(
+        verbatim_doc_comment,
+        long = "queue-depth",
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser = value_parser!(u32).range(1_i64..=4096)
+    )]
+    queue_depth: u32,
+
+    /// Limit the memory used by the buffers of the generator threads,
+    /// of the I/O queue and of the comparisons.
+    /// The number of chunks computed in advance is reduced to stay within the limit.
+    /// The chunk size is not changed, because the data depends on it.
+    /// If the limit is too small, then select a smaller --chunk-size.
+    #[arg(
+        verbatim_doc_comment,
+        long = "max-memory",
+        value_name = "BYTES",
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    max_memory: Option<u64>,
+
+    /// Quiet level:
+    /// 0: Normal verboseness.
+    /// 1: Reduced verboseness.
+    /// 2: No informational output.
+    /// 3: No warnings.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'q',
+        long = "quiet",
+        value_name = "LVL",
+        default_value = "0",
+        value_parser = parse_quiet
+    )]
+    quiet: DisktestQuiet,
+}
+
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    /// Identify where a block of foreign data on the device came from.
+    /// The block at the given offset is searched for in the pseudo random stream
+    /// of the given seed, algorithm and number of threads.
+    /// The block is classified as data written for another offset and round,
+    /// as all 0x00 or all 0xFF bytes or as unrelated data.
+    #[command(verbatim_doc_comment, after_help = EXAMPLE_ANALYZE)]
+    Analyze(CliAnalyzeArgs),
+
+    /// Sanitize the device and write a signed erase report.
+    /// The device is overwritten with each of the passes in turn.
+    /// Then the last pass is verified over the whole device.
+    /// The report contains the device identity, the passes, the byte counts,
+    /// the timestamps and the verification result.
+    /// It is signed with an Ed25519 key and written even if the erase fails.
+    #[command(verbatim_doc_comment, after_help = EXAMPLE_ERASE)]
+    Erase(CliEraseArgs),
+
+    /// Check the signature of an erase report.
+    #[command(verbatim_doc_comment)]
+    CheckReport(CliCheckReportArgs),
+
+    /// Measure the data rate of the random number generators.
+    /// Every algorithm is run with every number of threads.
+    /// Nothing is written to or read from any device.
+    /// With --target the cheapest setting that is at least as fast as the target is recommended.
+    /// This is the setting with the fewest threads and then with the most secure algorithm.
+    #[command(verbatim_doc_comment, after_help = EXAMPLE_BENCH)]
+    Bench(CliBenchArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct CliBenchArgs {
+    /// Comma separated list of the algorithms to benchmark.
+    /// See --algorithm in the main help for the available algorithms.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'A',
+        long = "algorithm",
+        value_name = "LIST",
+        default_value = "CHACHA20,CHACHA12,CHACHA8,CRC,XOSHIRO",
+        value_parser = parse_algorithm
+    )]
+    algorithm: AlgorithmList,
+
+    /// Comma separated list of the numbers of threads to benchmark.
+    /// The special value 0 selects the number of online CPUs in the system.
+    /// If not given, then the powers of two up to the number of online CPUs are used.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'j',
+        long = "threads",
+        value_name = "LIST",
+        value_delimiter = ',',
+        value_parser = value_parser!(u32).range(0_i64..=u16::MAX as i64 + 1)
+    )]
+    threads: Vec<u32>,
+
+    /// Number of seconds to run each benchmark.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'd',
+        long = "duration",
+        value_name = "SECONDS",
+        default_value_t = 1,
+        value_parser = value_parser!(u64).range(1_u64..)
+    )]
+    duration: u64,
+
+    /// The data rate that is required, in bytes per second.
+    /// For example the write speed of the device to be tested.
+    #[arg(
+        verbatim_doc_comment,
+        short = 't',
+        long = "target",
+        value_name = "BYTES",
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    target_rate: Option<u64>,
+
+    /// Quiet level:
+    /// 0: Normal verboseness.
+    /// 1: Reduced verboseness.
+    /// 2: No informational output.
+    /// 3: No warnings.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'q',
+        long = "quiet",
+        value_name = "LVL",
+        default_value = "0",
+        value_parser = parse_quiet
+    )]
+    quiet: DisktestQuiet,
+}
+
+#[derive(Debug, clap::Args)]
+struct CliEraseArgs {
+    /// Device node of the disk or file path to erase.
+    #[arg(
+        verbatim_doc_comment,
+        value_name = "DEVICE",
+        value_parser = value_parser!(PathBuf)
+    )]
+    device: PathBuf,
+
+    /// Comma separated list of the overwrite passes.
+    /// Every entry is an algorithm as accepted by --algorithm.
+    /// The random algorithms use a new random seed.
+    /// The default is a random pass followed by a zero pass.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'A',
+        long = "passes",
+        value_name = "LIST",
+        default_value = "CHACHA20,PATTERN:00",
+        value_parser = parse_algorithm
+    )]
+    passes: AlgorithmList,
+
+    /// Use the passes of a named wipe method instead of --passes.
+    /// NIST-CLEAR, DOD, DOD-ECE or GUTMANN.
+    /// See --wipe in the main help for details.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'm',
+        long = "method",
+        value_name = "METHOD",
+        value_enum,
+        ignore_case = true,
+        conflicts_with = "passes"
+    )]
+    method: Option<WipeChoice>,
+
+    /// Verify every pass after writing it.
+    /// The last pass is always verified.
+    #[arg(verbatim_doc_comment, short = 'v', long)]
+    verify: bool,
+
+    /// Number of bytes to erase.
+    /// If not given, then the whole disk will be erased.
+    /// This is required for regular files, because they would grow until the file system is full.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'b',
+        long = "bytes",
+        value_name = "BYTES",
+        default_value_t = Disktest::UNLIMITED,
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    max_bytes: u64,
+
+    /// Write the signed erase report to this file.
+    #[arg(
+        verbatim_doc_comment,
+        long = "report",
+        value_name = "FILE",
+        value_parser = value_parser!(PathBuf)
+    )]
+    report: PathBuf,
+
+    /// The Ed25519 key to sign the report with, as PKCS#8 (DER) file.
+    /// Such a key can be generated with --gen-sign-key
+    /// or with: openssl genpkey -algorithm ed25519 -outform DER -out FILE
+    #[arg(
+        verbatim_doc_comment,
+        long = "sign-key",
+        value_name = "FILE",
+        value_parser = value_parser!(PathBuf)
+    )]
+    sign_key: PathBuf,
+
+    /// Generate a new signing key and write it to the --sign-key file.
+    /// An existing file is never overwritten.
+    #[arg(verbatim_doc_comment, long = "gen-sign-key")]
+    gen_sign_key: bool,
+
+    /// Number of threads to use.
+    /// The special value 0 selects the number of online CPUs in the system.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'j',
+        long = "threads",
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser = value_parser!(u32).range(0_i64..=u16::MAX as i64 + 1)
+    )]
+    threads: u32,
+
+    /// Quiet level:
+    /// 0: Normal verboseness.
+    /// 1: Reduced verboseness.
+    /// 2: No informational output.
+    /// 3: No warnings.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'q',
+        long = "quiet",
+        value_name = "LVL",
+        default_value = "0",
+        value_parser = parse_quiet
+    )]
+    quiet: DisktestQuiet,
+}
+
+#[derive(Debug, clap::Args)]
+struct CliCheckReportArgs {
+    /// The signed erase report to check.
+    #[arg(
+        verbatim_doc_comment,
+        value_name = "REPORT",
+        value_parser = value_parser!(PathBuf)
+    )]
+    report: PathBuf,
+
+    /// The expected public key of the signer, as hex string.
+    /// If not given, then only the integrity of the report is checked
+    /// against the public key that is included in the report.
+    #[arg(verbatim_doc_comment, long = "public-key", value_name = "HEX")]
+    public_key: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+struct CliAnalyzeArgs {
+    /// Device node of the disk, file path or dump file to analyze.
+    #[arg(
+        verbatim_doc_comment,
+        value_name = "DEVICE",
+        value_parser = value_parser!(PathBuf)
+    )]
+    device: PathBuf,
+
+    /// Byte offset of the block to analyze.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'o',
+        long,
+        value_name = "BYTES",
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    offset: u64,
+
+    /// Length of the block to analyze.
+    /// The special value 0 selects one sector.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'l',
+        long,
+        value_name = "BYTES",
+        default_value_t = 0,
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    length: u64,
+
+    /// Number of bytes of the stream to search.
+    /// If not given, then the stream is searched up to the size of the device.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'b',
+        long = "bytes",
+        value_name = "BYTES",
+        default_value_t = Disktest::UNLIMITED,
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    max_bytes: u64,
+
+    /// The random number generator algorithm the device has been written with.
+    /// A comma separated list selects the algorithm of each round in turn.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'A',
+        long = "algorithm",
+        value_name = "ALG",
+        default_value = "CHACHA20",
+        value_parser = parse_algorithm
+    )]
+    algorithm: AlgorithmList,
+
+    /// The stream layout the device has been written with.
+    #[arg(
+        verbatim_doc_comment,
+        long = "layout",
+        value_enum,
+        ignore_case = true,
+        default_value_t = LayoutChoice::Thread
+    )]
+    layout: LayoutChoice,
+
+    /// The chunk size the device has been written with.
+    #[arg(
+        verbatim_doc_comment,
+        long = "chunk-size",
+        value_name = "BYTES",
+        value_parser = ValueParser::new(parsebytes)
+    )]
+    chunk_size: Option<u64>,
+
+    /// The seed the device has been written with.
+    #[arg(verbatim_doc_comment, short = 'S', long = "seed", value_name = "SEED")]
+    seed: String,
+
+    /// Number of threads to use.
+    /// With --layout THREAD this must be the number of threads the device has been written with.
+    /// The special value 0 selects the number of online CPUs in the system.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'j',
+        long = "threads",
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser = value_parser!(u32).range(0_i64..=u16::MAX as i64 + 1)
+    )]
+    threads: u32,
+
+    /// The number of rounds to search.
+    #[arg(
+        verbatim_doc_comment,
+        short = 'R',
+        long = "rounds",
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser = value_parser!(u64).range(1_u64..)
+    )]
+    rounds: u64,
+
+    /// The first round to search.
+    #[arg(
+        verbatim_doc_comment,
+        long = "start-round",
+        value_name = "IDX",
+        default_value_t = 0,
+        value_parser = value_parser!(u64).range(0_u64..=u64::MAX - 1)
+    )]
+    start_round: u64,
+
     /// Quiet level:
     /// 0: Normal verboseness.
     /// 1: Reduced verboseness.
@@ -231,11 +1015,222 @@ struct CliArgs {
     quiet: DisktestQuiet,
 }
 
+impl CliAnalyzeArgs {
+    fn into_args(self) -> Args {
+        Args {
+            device: self.device,
+            write: false,
+            verify: false,
+            probe: false,
+            seek: 0,
+            max_bytes: self.max_bytes,
+            algorithms: self.algorithm.0,
+            layout: self.layout.into(),
+            chunk_size: self.chunk_size,
+            seed: self.seed,
+            user_seed: true,
+            invert_pattern: false,
+            threads: self.threads as usize,
+            rounds: self.start_round.saturating_add(self.rounds),
+            start_round: self.start_round,
+            max_errors: None,
+            hexdump: None,
+            hexdump_context: 0,
+            badblocks: None,
+            badblocks_block_size: 0,
+            dmsetup: None,
+            state_file: None,
+            resume: false,
+            sampling: None,
+            interleave_lag: None,
+            direct: false,
+            queue_depth: 1,
+            max_memory: None,
+            wipe: None,
+            analyze: Some(AnalyzeArgs {
+                offset: self.offset,
+                length: self.length,
+            }),
+            erase: None,
+            check_report: None,
+            bench: None,
+            quiet: self.quiet,
+        }
+    }
+}
+
+impl CliEraseArgs {
+    fn into_args(self) -> Args {
+        let wipe: Option<DisktestWipeMethod> = self.method.map(Into::into);
+        let (method, algorithms) = if let Some(wipe) = wipe {
+            let algorithms = wipe.passes().iter().map(|pass| pass.algorithm).collect();
+            (wipe.to_string(), algorithms)
+        } else {
+            let method = self
+                .passes
+                .0
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",");
+            (method, self.passes.0)
+        };
+        let rounds = algorithms.len() as u64;
+        Args {
+            device: self.device,
+            write: true,
+            verify: self.verify,
+            probe: false,
+            seek: 0,
+            max_bytes: self.max_bytes,
+            algorithms,
+            layout: DtStreamLayout::Thread,
+            chunk_size: None,
+            seed: gen_seed_string(DEFAULT_GEN_SEED_LEN),
+            user_seed: false,
+            invert_pattern: false,
+            threads: self.threads as usize,
+            rounds,
+            start_round: 0,
+            max_errors: None,
+            hexdump: None,
+            hexdump_context: 0,
+            badblocks: None,
+            badblocks_block_size: 0,
+            dmsetup: None,
+            state_file: None,
+            resume: false,
+            sampling: None,
+            interleave_lag: None,
+            direct: false,
+            queue_depth: 1,
+            max_memory: None,
+            wipe,
+            analyze: None,
+            erase: Some(EraseArgs {
+                method,
+                report: self.report,
+                sign_key: self.sign_key,
+                gen_sign_key: self.gen_sign_key,
+            }),
+            check_report: None,
+            bench: None,
+            quiet: self.quiet,
+        }
+    }
+}
+
+impl CliCheckReportArgs {
+    fn into_args(self) -> Args {
+        Args {
+            device: self.report,
+            write: false,
+            verify: false,
+            probe: false,
+            seek: 0,
+            max_bytes: 0,
+            algorithms: vec![DtStreamType::ChaCha20],
+            layout: DtStreamLayout::Thread,
+            chunk_size: None,
+            seed: String::new(),
+            user_seed: true,
+            invert_pattern: false,
+            threads: 1,
+            rounds: 1,
+            start_round: 0,
+            max_errors: None,
+            hexdump: None,
+            hexdump_context: 0,
+            badblocks: None,
+            badblocks_block_size: 0,
+            dmsetup: None,
+            state_file: None,
+            resume: false,
+            sampling: None,
+            interleave_lag: None,
+            direct: false,
+            queue_depth: 1,
+            max_memory: None,
+            wipe: None,
+            analyze: None,
+            erase: None,
+            check_report: Some(CheckReportArgs {
+                public_key: self.public_key,
+            }),
+            bench: None,
+            quiet: DisktestQuiet::Normal,
+        }
+    }
+}
+
+impl CliBenchArgs {
+    fn into_args(self) -> Args {
+        let threads = if self.threads.is_empty() {
+            DisktestBench::default_threads()
+        } else {
+            self.threads.into_iter().map(|t| t as usize).collect()
+        };
+        Args {
+            device: PathBuf::new(),
+            write: false,
+            verify: false,
+            probe: false,
+            seek: 0,
+            max_bytes: 0,
+            algorithms: self.algorithm.0,
+            layout: DtStreamLayout::Thread,
+            chunk_size: None,
+            seed: String::new(),
+            user_seed: true,
+            invert_pattern: false,
+            threads: 1,
+            rounds: 1,
+            start_round: 0,
+            max_errors: None,
+            hexdump: None,
+            hexdump_context: 0,
+            badblocks: None,
+            badblocks_block_size: 0,
+            dmsetup: None,
+            state_file: None,
+            resume: false,
+            sampling: None,
+            interleave_lag: None,
+            direct: false,
+            queue_depth: 1,
+            max_memory: None,
+            wipe: None,
+            analyze: None,
+            erase: None,
+            check_report: None,
+            bench: Some(BenchArgs {
+                threads,
+                duration: Duration::from_secs(self.duration),
+                target_rate: self.target_rate,
+            }),
+            quiet: self.quiet,
+        }
+    }
+}
+
 impl CliArgs {
     fn into_args(self) -> ah::Result<Args> {
-        let write = self.write;
+        match self.command {
+            Some(CliCommand::Analyze(analyze)) => return Ok(analyze.into_args()),
+            Some(CliCommand::Erase(erase)) => return Ok(erase.into_args()),
+            Some(CliCommand::CheckReport(check)) => return Ok(check.into_args()),
+            Some(CliCommand::Bench(bench)) => return Ok(bench.into_args()),
+            None => (),
+        }
+        let Some(device) = self.device else {
+            return Err(ah::format_err!("No DEVICE given."));
+        };
+
+        let wipe: Option<DisktestWipeMethod> = self.wipe.map(Into::into);
+        let write = self.write || wipe.is_some();
         let mut verify = self.verify;
-        if !write && !verify {
+        let probe = self.probe;
+        if !write && !verify && !probe {
             verify = true;
         }
 
@@ -251,7 +1246,21 @@ impl CliArgs {
             ));
         }
 
-        let mut rounds = self.rounds;
+        let hexdump = if let Some(path) = self.hexdump_file {
+            Some(DisktestHexdump::File(path))
+        } else if self.hexdump {
+            Some(DisktestHexdump::Console)
+        } else {
+            None
+        };
+
+        let (algorithms, mut rounds) = if let Some(wipe) = wipe {
+            let passes = wipe.passes();
+            let algorithms: Vec<_> = passes.iter().map(|pass| pass.algorithm).collect();
+            (algorithms, passes.len() as u64)
+        } else {
+            (self.algorithm.0, self.rounds)
+        };
         if rounds == 0 {
             rounds = u64::MAX;
         }
@@ -260,19 +1269,53 @@ impl CliArgs {
             rounds = start_round + 1;
         }
 
+        let sampling = match (self.sample, self.sample_every) {
+            (Some(percent), _) if self.sample_random => Some(DisktestSampling::Random(percent)),
+            (Some(percent), _) => Some(DisktestSampling::Percent(percent)),
+            (None, Some(n)) => Some(DisktestSampling::EveryNth(n)),
+            (None, None) => None,
+        };
+
         Ok(Args {
-            device: self.device,
+            device,
             write,
             verify,
+            probe,
             seek: self.seek,
             max_bytes: self.max_bytes,
-            algorithm: self.algorithm.into(),
+            algorithms,
+            layout: self.layout.into(),
+            chunk_size: self.chunk_size,
             seed,
             user_seed,
             invert_pattern: self.invert_pattern,
             threads: self.threads as usize,
             rounds,
             start_round,
+            max_errors: self.max_errors.map(|max_errors| {
+                if max_errors == 0 {
+                    Disktest::UNLIMITED
+                } else {
+                    max_errors
+                }
+            }),
+            hexdump,
+            hexdump_context: self.hexdump_context,
+            badblocks: self.badblocks_file,
+            badblocks_block_size: self.badblocks_block_size,
+            dmsetup: self.dmsetup_file,
+            state_file: self.state_file,
+            resume: self.resume,
+            sampling,
+            interleave_lag: self.interleave.then_some(self.interleave_lag),
+            direct: self.direct,
+            queue_depth: self.queue_depth as usize,
+            max_memory: self.max_memory,
+            wipe,
+            analyze: None,
+            erase: None,
+            check_report: None,
+            bench: None,
             quiet: self.quiet,
         })
     }
@@ -294,6 +1337,33 @@ fn parse_quiet(value: &str) -> Result<DisktestQuiet, String> {
     Ok(quiet)
 }
 
+fn parse_algorithm(value: &str) -> Result<AlgorithmList, String> {
+    let value = if value.trim().eq_ignore_ascii_case("badblocks") {
+        BADBLOCKS_PATTERNS
+    } else {
+        value
+    };
+    value
+        .split(',')
+        .map(|name| name.parse::<DtStreamType>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map(AlgorithmList)
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent = value
+        .trim()
+        .trim_end_matches('%')
+        .trim_end()
+        .parse::<f64>()
+        .map_err(|e| e.to_string())?;
+    if percent > 0.0 && percent <= 100.0 {
+        Ok(percent)
+    } else {
+        Err(format!("Invalid percentage '{value}'. Allowed: (0, 100]."))
+    }
+}
+
 /// Parse all command line arguments and put them into a structure.
 pub fn parse_args<I, T>(args: I) -> ah::Result<Args>
 where
@@ -318,7 +1388,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use disktest_lib::Disktest;
+    use disktest_lib::{Disktest, DisktestWipeMethod, DtPattern};
 
     #[test]
     fn test_parse_args() {
@@ -328,15 +1398,42 @@ mod tests {
         assert_eq!(a.device, PathBuf::from("/dev/foobar"));
         assert!(!a.write);
         assert!(a.verify);
+        assert!(!a.probe);
         assert_eq!(a.seek, 0);
         assert_eq!(a.max_bytes, Disktest::UNLIMITED);
-        assert_eq!(a.algorithm, DtStreamType::ChaCha20);
+        assert_eq!(a.algorithm(0), DtStreamType::ChaCha20);
+        assert_eq!(a.layout, DtStreamLayout::Thread);
         assert_eq!(a.seed, "x");
         assert!(a.user_seed);
         assert!(!a.invert_pattern);
         assert_eq!(a.threads, 1);
+        assert_eq!(a.max_errors, None);
+        assert_eq!(a.hexdump, None);
+        assert_eq!(a.hexdump_context, 0);
+        assert_eq!(a.badblocks, None);
+        assert_eq!(a.badblocks_block_size, 4096);
+        assert_eq!(a.dmsetup, None);
+        assert_eq!(a.state_file, None);
+        assert!(!a.resume);
+        assert_eq!(a.sampling, None);
+        assert_eq!(a.interleave_lag, None);
+        assert!(!a.direct);
+        assert_eq!(a.queue_depth, 1);
+        assert_eq!(a.max_memory, None);
+        assert_eq!(a.chunk_size, None);
+        assert_eq!(a.analyze, None);
         assert_eq!(a.quiet, DisktestQuiet::Normal);
 
+        let a = parse_args(vec!["disktest", "--probe", "/dev/foobar"]).unwrap();
+        assert!(a.probe);
+        assert!(!a.write);
+        assert!(!a.verify);
+        let a = parse_args(vec!["disktest", "-P", "-b", "1G", "/dev/foobar"]).unwrap();
+        assert!(a.probe);
+        assert_eq!(a.max_bytes, 1024 * 1024 * 1024);
+        assert!(parse_args(vec!["disktest", "-P", "-w", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "-P", "-Sx", "-v", "/dev/foobar"]).is_err());
+
         let a = parse_args(vec!["disktest", "--write", "/dev/foobar"]).unwrap();
         assert_eq!(a.device, PathBuf::from("/dev/foobar"));
         assert!(a.write);
@@ -386,14 +1483,57 @@ mod tests {
             "/dev/foobar",
         ])
         .unwrap();
-        assert_eq!(a.algorithm, DtStreamType::ChaCha8);
+        assert_eq!(a.algorithm(0), DtStreamType::ChaCha8);
         let a = parse_args(vec!["disktest", "-w", "-A", "chacha8", "/dev/foobar"]).unwrap();
-        assert_eq!(a.algorithm, DtStreamType::ChaCha8);
+        assert_eq!(a.algorithm(0), DtStreamType::ChaCha8);
         let a = parse_args(vec!["disktest", "-w", "-A", "chacha12", "/dev/foobar"]).unwrap();
-        assert_eq!(a.algorithm, DtStreamType::ChaCha12);
+        assert_eq!(a.algorithm(0), DtStreamType::ChaCha12);
         let a = parse_args(vec!["disktest", "-w", "-A", "crc", "/dev/foobar"]).unwrap();
-        assert_eq!(a.algorithm, DtStreamType::Crc);
+        assert_eq!(a.algorithm(0), DtStreamType::Crc);
+        let a = parse_args(vec!["disktest", "-w", "-A", "xoshiro", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm(0), DtStreamType::Xoshiro);
+        let a = parse_args(vec!["disktest", "-w", "-A", "tagged", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm(0), DtStreamType::Tagged);
         assert!(parse_args(vec!["disktest", "-w", "-A", "invalid", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "-w", "-A", "pattern:aa55", "/dev/foobar"]).unwrap();
+        assert_eq!(
+            a.algorithm(0),
+            DtStreamType::Pattern(DtPattern::from_hex("AA55").unwrap())
+        );
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "-A",
+            "CHECKERBOARD,walking1,Walking0,crc",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.algorithms.len(), 4);
+        assert_eq!(
+            a.algorithm(0),
+            DtStreamType::Pattern(DtPattern::Checkerboard)
+        );
+        assert_eq!(
+            a.algorithm(1),
+            DtStreamType::Pattern(DtPattern::WalkingOnes)
+        );
+        assert_eq!(
+            a.algorithm(2),
+            DtStreamType::Pattern(DtPattern::WalkingZeros)
+        );
+        assert_eq!(a.algorithm(3), DtStreamType::Crc);
+        assert_eq!(
+            a.algorithm(4),
+            DtStreamType::Pattern(DtPattern::Checkerboard)
+        );
+        let a = parse_args(vec!["disktest", "-w", "-A", "badblocks", "/dev/foobar"]).unwrap();
+        assert_eq!(
+            a.algorithms,
+            [0xAA, 0x55, 0xFF, 0x00].map(|b| DtStreamType::Pattern(DtPattern::Byte(b)))
+        );
+        assert!(parse_args(vec!["disktest", "-w", "-A", "pattern:", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "-w", "-A", "pattern:a", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "-w", "-A", "crc,", "/dev/foobar"]).is_err());
 
         let a = parse_args(vec!["disktest", "-w", "--seed", "mysecret", "/dev/foobar"]).unwrap();
         assert_eq!(a.seed, "mysecret");
@@ -415,10 +1555,429 @@ mod tests {
         let a = parse_args(vec!["disktest", "-w", "-q2", "/dev/foobar"]).unwrap();
         assert_eq!(a.quiet, DisktestQuiet::NoInfo);
 
+        let a = parse_args(vec!["disktest", "--max-errors", "10", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.max_errors, Some(10));
+        let a = parse_args(vec!["disktest", "-E0", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.max_errors, Some(Disktest::UNLIMITED));
+
+        let a = parse_args(vec!["disktest", "--hexdump", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.hexdump, Some(DisktestHexdump::Console));
+        let a = parse_args(vec![
+            "disktest",
+            "--hexdump-file",
+            "/tmp/dump.txt",
+            "--hexdump-context",
+            "2",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(
+            a.hexdump,
+            Some(DisktestHexdump::File(PathBuf::from("/tmp/dump.txt")))
+        );
+        assert_eq!(a.hexdump_context, 2);
+
+        let a = parse_args(vec![
+            "disktest",
+            "-E0",
+            "--badblocks-file",
+            "/tmp/bb.txt",
+            "--badblocks-block-size",
+            "1k",
+            "--dmsetup-file",
+            "/tmp/dm.txt",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.badblocks, Some(PathBuf::from("/tmp/bb.txt")));
+        assert_eq!(a.badblocks_block_size, 1024);
+        assert_eq!(a.dmsetup, Some(PathBuf::from("/tmp/dm.txt")));
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "--badblocks-file",
+                "/tmp/bb.txt",
+                "-Sx",
+                "/dev/foobar"
+            ])
+            .is_err()
+        );
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "--dmsetup-file",
+                "/tmp/dm.txt",
+                "-Sx",
+                "/dev/foobar"
+            ])
+            .is_err()
+        );
+
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "--state-file",
+            "/tmp/state",
+            "--resume",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.state_file, Some(PathBuf::from("/tmp/state")));
+        assert!(a.resume);
+        assert!(parse_args(vec!["disktest", "-w", "--resume", "-Sx", "/dev/foobar"]).is_err());
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "-w",
+                "--state-file",
+                "/tmp/state",
+                "--resume",
+                "/dev/foobar"
+            ])
+            .is_err()
+        );
+
+        let a = parse_args(vec!["disktest", "-w", "--sample", "1.5", "/dev/foobar"]).unwrap();
+        assert_eq!(a.sampling, Some(DisktestSampling::Percent(1.5)));
+        let a = parse_args(vec![
+            "disktest",
+            "--sample",
+            "10%",
+            "--sample-random",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.sampling, Some(DisktestSampling::Random(10.0)));
+        let a = parse_args(vec![
+            "disktest",
+            "--sample-every",
+            "100",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.sampling, Some(DisktestSampling::EveryNth(100)));
+        assert!(parse_args(vec!["disktest", "--sample", "0", "-Sx", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "--sample", "101", "-Sx", "/dev/foobar"]).is_err());
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "--sample-every",
+                "0",
+                "-Sx",
+                "/dev/foobar"
+            ])
+            .is_err()
+        );
+        assert!(parse_args(vec!["disktest", "--sample-random", "-Sx", "/dev/foobar"]).is_err());
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "--sample",
+                "1",
+                "--sample-every",
+                "10",
+                "-Sx",
+                "/dev/foobar"
+            ])
+            .is_err()
+        );
+        assert!(parse_args(vec!["disktest", "-P", "--sample", "1", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "-w", "-v", "--interleave", "/dev/foobar"]).unwrap();
+        assert_eq!(a.interleave_lag, Some(1024 * 1024 * 1024));
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "-v",
+            "--interleave",
+            "--interleave-lag",
+            "64M",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.interleave_lag, Some(64 * 1024 * 1024));
+        assert!(parse_args(vec!["disktest", "-w", "--interleave", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "-v", "--interleave", "-Sx", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "-w", "--direct", "/dev/foobar"]).unwrap();
+        assert!(a.direct);
+
+        let a = parse_args(vec!["disktest", "-w", "--queue-depth", "32", "/dev/foobar"]).unwrap();
+        assert_eq!(a.queue_depth, 32);
+        assert!(parse_args(vec!["disktest", "--queue-depth", "0", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "--queue-depth", "4097", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "-w", "--max-memory", "64M", "/dev/foobar"]).unwrap();
+        assert_eq!(a.max_memory, Some(64 * 1024 * 1024));
+
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "--chunk-size",
+            "128K",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.chunk_size, Some(128 * 1024));
+
+        let a = parse_args(vec!["disktest", "--wipe", "dod", "/dev/foobar"]).unwrap();
+        assert!(a.write);
+        assert!(!a.verify);
+        assert_eq!(a.wipe, Some(DisktestWipeMethod::Dod));
+        assert_eq!(a.rounds, 3);
+        assert_eq!(a.algorithm(0), DtStreamType::Pattern(DtPattern::Byte(0)));
+        assert_eq!(a.algorithm(1), DtStreamType::Pattern(DtPattern::Byte(0)));
+        assert_eq!(a.algorithm(2), DtStreamType::ChaCha20);
+        assert!(!a.invert(0));
+        assert!(a.invert(1));
+        assert!(!a.invert(2));
+        assert!(!a.verify_round(0));
+        assert!(a.verify_round(2));
+        assert_eq!(a.pass_name(1), "complement of pattern:00");
+        let a = parse_args(vec!["disktest", "--wipe", "GUTMANN", "-v", "/dev/foobar"]).unwrap();
+        assert_eq!(a.rounds, 35);
+        assert!(a.verify_round(0));
+        let a = parse_args(vec!["disktest", "--wipe", "nist-clear", "/dev/foobar"]).unwrap();
+        assert_eq!(a.rounds, 1);
+        assert!(a.verify_round(0));
+        let a = parse_args(vec!["disktest", "-w", "-i", "-A", "crc", "/dev/foobar"]).unwrap();
+        assert_eq!(a.wipe, None);
+        assert!(a.invert(0));
+        assert_eq!(a.pass_name(0), "inverted crc");
+        assert!(parse_args(vec!["disktest", "--wipe", "foo", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "--wipe", "dod", "-R2", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "--wipe", "dod", "-Acrc", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "--wipe", "dod", "-i", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "--layout", "thread", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.layout, DtStreamLayout::Thread);
+        let a = parse_args(vec!["disktest", "--layout", "CHUNK", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.layout, DtStreamLayout::Chunk);
+        assert!(parse_args(vec!["disktest", "--layout", "foo", "-Sx", "/dev/foobar"]).is_err());
+
         let a = parse_args(vec!["disktest", "-w", "--invert-pattern", "/dev/foobar"]).unwrap();
         assert!(a.invert_pattern);
         let a = parse_args(vec!["disktest", "-w", "-i", "/dev/foobar"]).unwrap();
         assert!(a.invert_pattern);
+
+        assert!(parse_args(vec!["disktest"]).is_err());
+        assert!(parse_args(vec!["disktest", "analyze", "-Sx", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "analyze", "--offset", "1M", "/dev/foobar"]).is_err());
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "-w",
+                "analyze",
+                "--offset",
+                "1M",
+                "-Sx",
+                "/dev/foobar"
+            ])
+            .is_err()
+        );
+        let a = parse_args(vec![
+            "disktest",
+            "analyze",
+            "--offset",
+            "1M",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.device, PathBuf::from("/dev/foobar"));
+        assert!(!a.write);
+        assert!(!a.verify);
+        assert_eq!(a.seed, "x");
+        assert!(a.user_seed);
+        assert_eq!(a.start_round, 0);
+        assert_eq!(a.rounds, 1);
+        assert_eq!(a.max_bytes, Disktest::UNLIMITED);
+        assert_eq!(
+            a.analyze,
+            Some(AnalyzeArgs {
+                offset: 1024 * 1024,
+                length: 0
+            })
+        );
+        let a = parse_args(vec![
+            "disktest",
+            "analyze",
+            "-o4096",
+            "-l",
+            "8k",
+            "-b",
+            "1G",
+            "-R3",
+            "--start-round",
+            "2",
+            "-j4",
+            "-A",
+            "chacha8",
+            "--layout",
+            "chunk",
+            "-Sx",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.algorithm(0), DtStreamType::ChaCha8);
+        assert_eq!(a.layout, DtStreamLayout::Chunk);
+        assert_eq!(a.threads, 4);
+        assert_eq!(a.start_round, 2);
+        assert_eq!(a.rounds, 5);
+        assert_eq!(a.max_bytes, 1024 * 1024 * 1024);
+        assert_eq!(
+            a.analyze,
+            Some(AnalyzeArgs {
+                offset: 4096,
+                length: 8 * 1024
+            })
+        );
+
+        assert!(parse_args(vec!["disktest", "erase", "/dev/foobar"]).is_err());
+        let a = parse_args(vec![
+            "disktest",
+            "erase",
+            "--report",
+            "r.txt",
+            "--sign-key",
+            "k.der",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.device, PathBuf::from("/dev/foobar"));
+        assert!(a.write);
+        assert!(!a.verify);
+        assert!(!a.user_seed);
+        assert_eq!(a.rounds, 2);
+        assert_eq!(a.algorithm(0), DtStreamType::ChaCha20);
+        assert_eq!(a.algorithm(1), DtStreamType::Pattern(DtPattern::Byte(0)));
+        assert_eq!(a.max_bytes, Disktest::UNLIMITED);
+        assert_eq!(
+            a.erase,
+            Some(EraseArgs {
+                method: "chacha20,pattern:00".to_string(),
+                report: PathBuf::from("r.txt"),
+                sign_key: PathBuf::from("k.der"),
+                gen_sign_key: false,
+            })
+        );
+        let a = parse_args(vec![
+            "disktest",
+            "erase",
+            "--report",
+            "r.txt",
+            "--sign-key",
+            "k.der",
+            "--gen-sign-key",
+            "--passes",
+            "pattern:ff",
+            "-b1M",
+            "-j2",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.rounds, 1);
+        assert_eq!(a.algorithm(0), DtStreamType::Pattern(DtPattern::Byte(0xFF)));
+        assert_eq!(a.max_bytes, 1024 * 1024);
+        assert_eq!(a.threads, 2);
+        assert!(a.erase.unwrap().gen_sign_key);
+        let a = parse_args(vec![
+            "disktest",
+            "erase",
+            "--report",
+            "r.txt",
+            "--sign-key",
+            "k.der",
+            "--method",
+            "dod-ece",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.rounds, 7);
+        assert!(a.invert(5));
+        assert_eq!(a.erase.unwrap().method, "DoD 5220.22-M ECE");
+        assert!(
+            parse_args(vec![
+                "disktest",
+                "erase",
+                "--report",
+                "r.txt",
+                "--sign-key",
+                "k.der",
+                "--method",
+                "dod",
+                "--passes",
+                "crc",
+                "/dev/foobar",
+            ])
+            .is_err()
+        );
+
+        let a = parse_args(vec!["disktest", "check-report", "r.txt"]).unwrap();
+        assert_eq!(a.device, PathBuf::from("r.txt"));
+        assert_eq!(a.check_report, Some(CheckReportArgs { public_key: None }));
+        let a = parse_args(vec![
+            "disktest",
+            "check-report",
+            "--public-key",
+            "ab",
+            "r.txt",
+        ])
+        .unwrap();
+        assert_eq!(
+            a.check_report,
+            Some(CheckReportArgs {
+                public_key: Some("ab".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_bench() {
+        let a = parse_args(vec!["disktest", "bench"]).unwrap();
+        assert_eq!(a.algorithms, DisktestBench::ALGORITHMS);
+        assert_eq!(
+            a.bench,
+            Some(BenchArgs {
+                threads: DisktestBench::default_threads(),
+                duration: Duration::from_secs(1),
+                target_rate: None,
+            })
+        );
+        assert!(!a.write);
+        assert!(!a.verify);
+
+        let a = parse_args(vec![
+            "disktest",
+            "bench",
+            "-A",
+            "crc,xoshiro",
+            "-j",
+            "1,2,0",
+            "-d",
+            "3",
+            "-t",
+            "2G",
+        ])
+        .unwrap();
+        assert_eq!(a.algorithms, [DtStreamType::Crc, DtStreamType::Xoshiro]);
+        assert_eq!(
+            a.bench,
+            Some(BenchArgs {
+                threads: vec![1, 2, 0],
+                duration: Duration::from_secs(3),
+                target_rate: Some(2 * 1024 * 1024 * 1024),
+            })
+        );
+
+        assert!(parse_args(vec!["disktest", "bench", "-d", "0"]).is_err());
+        assert!(parse_args(vec!["disktest", "bench", "-j", "1,x"]).is_err());
+        assert!(parse_args(vec!["disktest", "bench", "/dev/foobar"]).is_err());
     }
 }
 
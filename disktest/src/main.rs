@@ -14,12 +14,21 @@
 mod args;
 
 use anyhow as ah;
-use args::{Args, parse_args};
+use args::{AnalyzeArgs, Args, BenchArgs, CheckReportArgs, EraseArgs, parse_args};
 use chrono::prelude::*;
-use disktest_lib::{Disktest, DisktestFile, DisktestQuiet};
+use disktest_lib::{
+    Disktest, DisktestBench, DisktestBlockOrigin, DisktestErasePass, DisktestEraseReport,
+    DisktestFile, DisktestHexdump, DisktestPhase, DisktestQuiet, DisktestSigningKey, DisktestState,
+    prettybytes,
+};
 use std::{
     env::args_os,
-    sync::{Arc, atomic::AtomicBool},
+    ffi::OsString,
+    fs::File,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 /// Install abort signal handlers and return
@@ -53,35 +62,409 @@ fn print_generated_seed(seed: &str, verbose: bool) {
 fn new_disktest(
     args: &Args,
     round_id: u64,
+    read: bool,
     write: bool,
     abort: &Arc<AtomicBool>,
 ) -> ah::Result<(Disktest, DisktestFile)> {
-    Ok((
-        Disktest::new(
-            args.algorithm,
+    let mut disktest = Disktest::new(
+        args.algorithm(round_id),
+        args.seed.as_bytes(),
+        round_id,
+        args.invert(round_id),
+        args.threads,
+        args.quiet,
+        Some(Arc::clone(abort)),
+    );
+    disktest.set_stream_layout(args.layout);
+    disktest.set_chunk_size(args.chunk_size);
+    disktest.set_max_errors(args.max_errors);
+    disktest.set_max_memory(args.max_memory);
+    disktest.set_hexdump(args.hexdump.clone(), args.hexdump_context);
+    disktest.set_state_file(args.state_file.clone());
+    let mut file = DisktestFile::open(&args.device, read, write)?;
+    file.set_direct_io(args.direct);
+    file.set_queue_depth(args.queue_depth);
+    Ok((disktest, file))
+}
+
+/// Quote a command line argument for the shell, if needed.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Build the command line that resumes this run from the state file.
+fn resume_command(args: &Args, cmdline: &[OsString]) -> String {
+    let mut cmd: Vec<String> = cmdline
+        .iter()
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .filter(|arg| arg != "--resume")
+        .collect();
+    cmd.push("--resume".to_string());
+    if !args.user_seed {
+        cmd.push("--seed".to_string());
+        cmd.push(shell_quote(&args.seed));
+    }
+    cmd.join(" ")
+}
+
+/// Load the state file to resume from and check it against the arguments.
+fn load_resume_state(args: &Args) -> ah::Result<Option<DisktestState>> {
+    let (true, Some(path)) = (args.resume, args.state_file.as_ref()) else {
+        return Ok(None);
+    };
+    let state = DisktestState::load(path)?;
+    if state.phase == DisktestPhase::Write && !args.write {
+        return Err(ah::format_err!(
+            "Cannot resume: The state file has been written in write mode, but --write is not given."
+        ));
+    }
+    if state.round_id < args.start_round || state.round_id >= args.rounds {
+        return Err(ah::format_err!(
+            "Cannot resume: Round {} of the state file is not in the requested range of rounds.",
+            state.round_id
+        ));
+    }
+    Ok(Some(state))
+}
+
+/// Prepare a disktest core instance to resume from the state.
+fn resume_disktest(disktest: &mut Disktest, state: &DisktestState) -> ah::Result<()> {
+    // Continue with the chunk size the run has been started with.
+    // The state records the rounded chunk size, which can differ from --chunk-size.
+    disktest.set_chunk_size(Some(state.chunk_size));
+    disktest.check_state(state)
+}
+
+/// Run the fake capacity probe.
+fn run_probe(args: &Args, abort: &Arc<AtomicBool>) -> ah::Result<()> {
+    let mut disktest = Disktest::new(
+        args.algorithm(0),
+        args.seed.as_bytes(),
+        0,
+        false,
+        args.threads,
+        args.quiet,
+        Some(Arc::clone(abort)),
+    );
+    disktest.set_chunk_size(args.chunk_size);
+    disktest.set_max_memory(args.max_memory);
+    let mut file = DisktestFile::open(&args.device, true, true)?;
+    file.set_direct_io(args.direct);
+    let res = disktest.probe(file, args.max_bytes)?;
+    if !res.is_good() {
+        return Err(ah::format_err!(
+            "Fake capacity detected! Only {} of the advertised {} are known to be usable.",
+            prettybytes(res.usable_size(), true, true, true),
+            prettybytes(res.advertised_size, true, true, true)
+        ));
+    }
+    if args.quiet == DisktestQuiet::Normal {
+        println!("Success!");
+    }
+    Ok(())
+}
+
+/// Run the analysis of a block of foreign data.
+/// All requested rounds are searched until the origin of the block is found.
+fn run_analyze(args: &Args, analyze: &AnalyzeArgs, abort: &Arc<AtomicBool>) -> ah::Result<()> {
+    let mut origin = DisktestBlockOrigin::Unrelated;
+    for round in args.start_round..args.rounds {
+        let mut disktest = Disktest::new(
+            args.algorithm(round),
             args.seed.as_bytes(),
-            round_id,
-            args.invert_pattern,
+            round,
+            false,
             args.threads,
             args.quiet,
             Some(Arc::clone(abort)),
-        ),
-        DisktestFile::open(&args.device, !write, write)?,
-    ))
+        );
+        disktest.set_stream_layout(args.layout);
+        disktest.set_chunk_size(args.chunk_size);
+        let file = DisktestFile::open(&args.device, true, false)?;
+        origin = disktest.analyze(file, analyze.offset, analyze.length, args.max_bytes)?;
+        if origin != DisktestBlockOrigin::Unrelated {
+            break;
+        }
+    }
+    if args.quiet < DisktestQuiet::NoWarn {
+        println!(
+            "Block at offset {}: {origin}.",
+            prettybytes(analyze.offset, true, true, true)
+        );
+    }
+    Ok(())
+}
+
+/// Run the erase passes and the final verification and write the signed report.
+fn run_erase(args: &Args, erase: &EraseArgs, abort: &Arc<AtomicBool>) -> ah::Result<()> {
+    // Get the key first, so that a bad key does not waste a whole erase run.
+    let key = if erase.gen_sign_key {
+        let key = DisktestSigningKey::generate(&erase.sign_key)?;
+        if args.quiet < DisktestQuiet::NoInfo {
+            println!(
+                "Generated signing key {} with the public key:\n    {}",
+                erase.sign_key.display(),
+                key.public_key_hex()
+            );
+        }
+        key
+    } else {
+        DisktestSigningKey::load(&erase.sign_key)?
+    };
+
+    let identity = DisktestFile::open(&args.device, false, true)?.identity()?;
+    let mut report = DisktestEraseReport::new(identity, &erase.method, args.seed.as_bytes());
+
+    let mut result = Ok(());
+    for round in args.start_round..args.rounds {
+        if args.quiet < DisktestQuiet::NoInfo {
+            println!(
+                "{}[{}] Erase pass {} of {}: {}",
+                if round > args.start_round { "\n" } else { "" },
+                Local::now().format("%F %R"),
+                round + 1,
+                args.rounds,
+                args.pass_name(round),
+            );
+        }
+        let mut pass = DisktestErasePass {
+            algorithm: args.algorithm(round),
+            invert: args.invert(round),
+            bytes: 0,
+            verified_bytes: None,
+            start: Utc::now(),
+            end: Utc::now(),
+            error: None,
+        };
+        let (mut disktest, file) = new_disktest(args, round, false, true, abort)?;
+        let mut res = disktest
+            .write(file, args.seek, args.max_bytes)
+            .map(|bytes| {
+                pass.bytes = bytes;
+            });
+        // The last pass is verified below.
+        if res.is_ok() && args.verify_round(round) && round + 1 < args.rounds {
+            let (mut disktest, file) = new_disktest(args, round, true, false, abort)?;
+            res = disktest.verify(file, args.seek, pass.bytes).map(|bytes| {
+                pass.verified_bytes = Some(bytes);
+            });
+        }
+        pass.end = Utc::now();
+        pass.error = res.as_ref().err().map(ToString::to_string);
+        report.passes.push(pass);
+        if let Err(e) = res {
+            result = Err(e);
+            break;
+        }
+    }
+
+    // A regular file grows while it is written.
+    if let Ok(identity) =
+        DisktestFile::open(&args.device, true, false).and_then(|mut f| f.identity())
+    {
+        report.device = identity;
+    }
+
+    // The mandatory verification of the last pass over the whole written range.
+    if let (Ok(()), Some(last)) = (&result, report.passes.last()) {
+        let round = args.rounds - 1;
+        if args.quiet < DisktestQuiet::NoInfo {
+            println!(
+                "\n[{}] Verifying the last pass: {}",
+                Local::now().format("%F %R"),
+                args.pass_name(round)
+            );
+        }
+        let start = Utc::now();
+        let (mut disktest, file) = new_disktest(args, round, true, false, abort)?;
+        let res = disktest.verify(file, args.seek, last.bytes);
+        let verification = DisktestErasePass {
+            algorithm: last.algorithm,
+            invert: last.invert,
+            bytes: *res.as_ref().unwrap_or(&0),
+            verified_bytes: None,
+            start,
+            end: Utc::now(),
+            error: res.as_ref().err().map(ToString::to_string),
+        };
+        report.verification = Some(verification);
+        result = res.map(|_| ());
+    }
+
+    report.save_signed(&erase.report, &key)?;
+    if args.quiet < DisktestQuiet::NoInfo {
+        println!("\nWrote the signed erase report {}", erase.report.display());
+    }
+    result?;
+    if !report.is_success() {
+        return Err(ah::format_err!(
+            "The erase is incomplete. See the erase report for details."
+        ));
+    }
+    if args.quiet == DisktestQuiet::Normal {
+        println!("Success!");
+    }
+    Ok(())
+}
+
+/// Check the signature of an erase report.
+fn run_check_report(args: &Args, check: &CheckReportArgs) -> ah::Result<()> {
+    let text = std::fs::read_to_string(&args.device).map_err(|e| {
+        ah::format_err!("Failed to read erase report {}: {e}", args.device.display())
+    })?;
+    DisktestEraseReport::check_signed(&text, check.public_key.as_deref())?;
+    if args.quiet == DisktestQuiet::Normal {
+        if check.public_key.is_some() {
+            println!("The erase report signature is valid.");
+        } else {
+            println!(
+                "The erase report is intact. \
+                 Pass --public-key to also check who has signed it."
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Benchmark the random number generators and recommend a setting.
+fn run_bench(args: &Args, bench: &BenchArgs, abort: &Arc<AtomicBool>) -> ah::Result<()> {
+    let mut results = vec![];
+    for &algorithm in &args.algorithms {
+        for &threads in &bench.threads {
+            let result = DisktestBench::run(algorithm, threads, bench.duration, Some(abort))?;
+            if args.quiet < DisktestQuiet::NoInfo {
+                println!(
+                    "{:<10} -j{:<4} {}/s",
+                    algorithm.to_string().to_uppercase(),
+                    result.threads,
+                    prettybytes(result.rate(), true, false, false)
+                );
+            }
+            results.push(result);
+        }
+    }
+
+    let (recommended, label) = if let Some(target_rate) = bench.target_rate {
+        (
+            DisktestBench::recommend(&results, target_rate),
+            format!(
+                "Recommended setting for {}/s",
+                prettybytes(target_rate, true, false, false)
+            ),
+        )
+    } else {
+        (
+            results.iter().max_by_key(|result| result.rate()),
+            "Fastest setting".to_string(),
+        )
+    };
+    if args.quiet < DisktestQuiet::NoWarn {
+        if let Some(result) = recommended {
+            println!(
+                "\n{label}: -A{} -j{} ({}/s)",
+                result.algorithm.to_string().to_uppercase(),
+                result.threads,
+                prettybytes(result.rate(), true, false, false)
+            );
+        } else {
+            println!("\nNone of the settings reaches the target data rate.");
+        }
+    }
+    if recommended.is_none() {
+        return Err(ah::format_err!("Target data rate not reached."));
+    }
+    Ok(())
+}
+
+/// Export the bad and unreadable sectors of the last verification run, if requested.
+fn export_bad_ranges(args: &Args, disktest: &Disktest) -> ah::Result<()> {
+    if let Some(path) = &args.badblocks {
+        disktest.export_badblocks(path, args.badblocks_block_size)?;
+    }
+    if let Some(path) = &args.dmsetup {
+        disktest.export_dmsetup_table(path)?;
+    }
+    Ok(())
+}
+
+/// Export the bad ranges after verification and merge the export errors into the `result`.
+fn finish_verify(args: &Args, disktest: &Disktest, result: ah::Result<()>) -> ah::Result<()> {
+    if let Err(e) = export_bad_ranges(args, disktest) {
+        if result.is_ok() {
+            return Err(e);
+        } else if args.quiet < DisktestQuiet::NoWarn {
+            eprintln!("{e}");
+        }
+    }
+    result
 }
 
 /// Main program entry point.
 fn main() -> ah::Result<()> {
-    let args = parse_args(args_os())?;
+    let cmdline: Vec<OsString> = args_os().collect();
+    let args = parse_args(cmdline.clone())?;
     let abort = install_abort_handlers()?;
 
+    if args.probe {
+        return run_probe(&args, &abort);
+    }
+    if let Some(analyze) = &args.analyze {
+        return run_analyze(&args, analyze, &abort);
+    }
+    if let Some(erase) = &args.erase {
+        return run_erase(&args, erase, &abort);
+    }
+    if let Some(check) = &args.check_report {
+        return run_check_report(&args, check);
+    }
+    if let Some(bench) = &args.bench {
+        return run_bench(&args, bench, &abort);
+    }
+
     if !args.user_seed && args.quiet < DisktestQuiet::NoInfo {
         print_generated_seed(&args.seed, true);
     }
 
+    let mut resume = load_resume_state(&args)?;
+    if let (None, Some(DisktestHexdump::File(path))) = (resume.as_ref(), args.hexdump.as_ref()) {
+        // Start a new hex dump file. The verifications of all rounds append to it.
+        if let Err(e) = File::create(path) {
+            return Err(ah::format_err!(
+                "Failed to create hex dump file {}: {e}",
+                path.display()
+            ));
+        }
+    }
+    let start_round = resume.as_ref().map_or(args.start_round, |state| {
+        if args.write {
+            state.round_id
+        } else {
+            // In verify-only mode the round of the state file is always the start round.
+            args.start_round
+        }
+    });
+
     let mut result = Ok(());
-    for round in args.start_round..args.rounds {
-        if args.rounds > 1 {
+    for round in start_round..args.rounds {
+        if let (Some(wipe), Some(pass)) = (args.wipe, args.wipe_pass(round)) {
+            if args.quiet < DisktestQuiet::NoInfo {
+                println!(
+                    "{}[{}] {wipe} pass {} of {}: {pass}",
+                    if round > args.start_round { "\n" } else { "" },
+                    Local::now().format("%F %R"),
+                    round + 1,
+                    args.rounds,
+                );
+            }
+        } else if args.rounds > 1 {
             let tod = Local::now().format("%F %R");
             let end = if args.rounds == u64::MAX {
                 "inf]".to_string()
@@ -105,17 +488,67 @@ fn main() -> ah::Result<()> {
             args.start_round
         };
 
-        // Run write-mode, if requested.
+        // Continue the aborted phase, if resuming.
+        let mut write = args.write;
+        let mut write_range = (args.seek, args.max_bytes);
+        let mut verify_range = (args.seek, args.max_bytes);
+        let resume_state = resume.take();
+        if let Some(state) = resume_state.as_ref() {
+            match state.phase {
+                DisktestPhase::Write => write_range = (state.offset, state.bytes_left()),
+                DisktestPhase::Verify => {
+                    write = false;
+                    verify_range = (state.offset, state.bytes_left());
+                }
+            }
+        }
+
+        // Run the interleaved write and verify mode, if requested.
+        let mut verify = args.verify_round(round_id);
         result = Ok(());
-        if args.write {
-            let (mut disktest, file) = new_disktest(&args, round_id, true, &abort)?;
-            result = disktest.write(file, args.seek, args.max_bytes).map(|_| ());
+        if let (true, true, Some(lag)) = (write, verify, args.interleave_lag) {
+            let (mut disktest, file) = new_disktest(&args, round_id, true, true, &abort)?;
+            let res = disktest
+                .write_verify(file, write_range.0, write_range.1, lag)
+                .map(|_| ());
+            result = finish_verify(&args, &disktest, res);
+            write = false;
+            verify = false;
+        }
+
+        // Run write-mode, if requested.
+        if write {
+            let (mut disktest, file) = new_disktest(&args, round_id, false, true, &abort)?;
+            if let Some(state) = resume_state.as_ref() {
+                resume_disktest(&mut disktest, state)?;
+            }
+            result = if let Some(sampling) = args.sampling {
+                disktest
+                    .write_sampled(file, write_range.0, write_range.1, sampling)
+                    .map(|_| ())
+            } else {
+                disktest
+                    .write(file, write_range.0, write_range.1)
+                    .map(|_| ())
+            };
         }
 
         // Run verify-mode, if requested.
-        if args.verify && result.is_ok() {
-            let (mut disktest, file) = new_disktest(&args, round_id, false, &abort)?;
-            result = disktest.verify(file, args.seek, args.max_bytes).map(|_| ());
+        if verify && result.is_ok() {
+            let (mut disktest, file) = new_disktest(&args, round_id, true, false, &abort)?;
+            if let Some(state) = resume_state.as_ref() {
+                resume_disktest(&mut disktest, state)?;
+            }
+            let res = if let Some(sampling) = args.sampling {
+                disktest
+                    .verify_sampled(file, verify_range.0, verify_range.1, sampling)
+                    .map(|_| ())
+            } else {
+                disktest
+                    .verify(file, verify_range.0, verify_range.1)
+                    .map(|_| ())
+            };
+            result = finish_verify(&args, &disktest, res);
         }
 
         if result.is_err() {
@@ -126,6 +559,21 @@ fn main() -> ah::Result<()> {
     if !args.user_seed && args.quiet < DisktestQuiet::NoInfo {
         print_generated_seed(&args.seed, false);
     }
+    if let Some(path) = args.state_file.as_ref() {
+        if result.is_ok() {
+            // The run is complete. There is nothing to resume.
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound && args.quiet < DisktestQuiet::NoWarn {
+                    eprintln!("WARNING: Failed to remove the state file: {e}");
+                }
+            }
+        } else if abort.load(Ordering::Relaxed) && args.quiet < DisktestQuiet::NoWarn {
+            eprintln!(
+                "\nThe run can be resumed with:\n    {}\n",
+                resume_command(&args, &cmdline)
+            );
+        }
+    }
     if result.is_ok() && args.quiet == DisktestQuiet::Normal {
         println!("Success!");
     }